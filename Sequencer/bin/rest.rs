@@ -96,6 +96,9 @@ pub struct BlockHeaderDTO {
     orders_commitment: String,
     fills_commitment: String,
     timestamp_ms: u64,
+    hash_scheme: String,
+    commit_scheme: String,
+    proof_status: String,
 }
 pub async fn get_block(
     State(state): State<AppState>,
@@ -103,7 +106,8 @@ pub async fn get_block(
 ) -> Option<Json<BlockHeaderDTO>> {
     let r = sqlx::query!(r#"
         SELECT block_number, batch_id, parent_state_root, new_state_root,
-               markets_root, orders_commitment, fills_commitment, timestamp_ms
+               markets_root, orders_commitment, fills_commitment, timestamp_ms, hash_scheme, commit_scheme,
+               proof_status
         FROM batches WHERE block_number=$1
     "#, block_number as i64).fetch_optional(&state.pool).await.ok()??;
 
@@ -116,6 +120,45 @@ pub async fn get_block(
         orders_commitment: hex::encode(r.orders_commitment),
         fills_commitment: hex::encode(r.fills_commitment),
         timestamp_ms: r.timestamp_ms as u64,
+        hash_scheme: engine::HashScheme::from_id(r.hash_scheme as u16)
+            .map_or_else(|| format!("unknown({})", r.hash_scheme), |s| s.to_string()),
+        commit_scheme: sequencer::commit::CommitScheme::from_id(r.commit_scheme as u16)
+            .map_or_else(|| format!("unknown({})", r.commit_scheme), |s| s.name().into()),
+        proof_status: proof_status_name(r.proof_status),
+    }))
+}
+
+fn proof_status_name(id: i16) -> String {
+    sequencer::prover::ProofStatus::from_id(id).map_or_else(|| format!("unknown({id})"), |s| s.name().into())
+}
+
+#[derive(Serialize)]
+pub struct ProofDTO {
+    block_number: u64,
+    status: String,
+    attempts: u32,
+    error: Option<String>,
+    vkey: Option<String>,
+    proof: Option<String>,        // hex of the serialized SP1 proof, once proved
+    public_values: Option<String>,
+}
+pub async fn get_block_proof(
+    State(state): State<AppState>,
+    Path(block_number): Path<u64>,
+) -> Option<Json<ProofDTO>> {
+    let r = sqlx::query!(r#"
+        SELECT block_number, proof_status, proof_attempts, proof_error, proof_vkey, proof, proof_public_values
+        FROM batches WHERE block_number=$1
+    "#, block_number as i64).fetch_optional(&state.pool).await.ok()??;
+
+    Some(Json(ProofDTO{
+        block_number: r.block_number as u64,
+        status: proof_status_name(r.proof_status),
+        attempts: r.proof_attempts as u32,
+        error: r.proof_error,
+        vkey: r.proof_vkey,
+        proof: r.proof.map(hex::encode),
+        public_values: r.proof_public_values.map(hex::encode),
     }))
 }
 
@@ -125,9 +168,14 @@ pub async fn get_orderbook(
 ) -> Json<TopOfBook> {
     // Quick & dirty: compute from open orders table (for small scale)
     let rows = sqlx::query!(r#"
-        SELECT side, price_tick, SUM(remaining) as qty
+        SELECT side, price_tick,
+               -- icebergs count only their current slice, see Order::displayed
+               SUM(CASE WHEN display_qty IS NULL THEN remaining
+                        ELSE LEAST(remaining, display_qty - (amount - remaining) % display_qty)
+                   END) as qty
         FROM orders
         WHERE pair_id=$1 AND remaining>0
+          AND (stop_kind = 0 OR stop_triggered) -- pending stops are not on the book
         GROUP BY side, price_tick
         ORDER BY price_tick ASC
     "#, pair_id as i64).fetch_all(&state.pool).await.unwrap();
@@ -160,6 +208,14 @@ struct SubmitOrderReq {
     nonce: u64,
     order_hash: String,       // hex of the signed payload hash
     pk_hash: String,          // Poseidon(Ax,Ay,0) hex (private identity hash)
+    #[serde(default)]
+    time_in_force: u8,        // 0=GTC,1=IOC,2=FOK,3=PostOnly
+    #[serde(default)]
+    stop_kind: u8,            // 0=none,1=stop-market,2=stop-limit
+    #[serde(default)]
+    trigger_tick: u64,        // last trade price that activates a stop
+    #[serde(default)]
+    display_qty: Option<u64>, // iceberg slice size; None shows everything
 }
 #[derive(Serialize)]
 struct SubmitOrderRes {
@@ -192,16 +248,31 @@ pub async fn post_order(
     if order_hash.len()!=32 || pk_hash.len()!=32 {
         return Err(bad((400, "hash length must be 32 bytes".into())));
     }
+    if req.time_in_force > 3 {
+        return Err(bad((400, "time_in_force must be 0..=3".into())));
+    }
+    if req.stop_kind > 2 {
+        return Err(bad((400, "stop_kind must be 0..=2".into())));
+    }
+    if req.stop_kind != 0 && req.trigger_tick == 0 {
+        return Err(bad((400, "stop orders need a trigger_tick".into())));
+    }
+    if req.display_qty == Some(0) {
+        return Err(bad((400, "display_qty must be positive".into())));
+    }
 
     // insert order
     sqlx::query!(
         r#"INSERT INTO orders
            (order_id, order_hash, pair_id, side, price_tick, amount, remaining,
-            time_bucket, nonce, ingest_seq, status)
-           VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,0)"#,
+            time_bucket, nonce, ingest_seq, time_in_force, stop_kind, trigger_tick,
+            stop_triggered, display_qty, status)
+           VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,false,$14,0)"#,
         order_id as i64, &order_hash, req.pair_id as i64, req.side as i16,
         req.price_tick as i64, req.amount as i64, req.amount as i64,
-        req.time_bucket as i32, req.nonce as i64, ingest_seq as i64
+        req.time_bucket as i32, req.nonce as i64, ingest_seq as i64,
+        req.time_in_force as i16, req.stop_kind as i16, req.trigger_tick as i64,
+        req.display_qty.map(|d| d as i64)
     ).execute(&mut *conn).await.map_err(bad)?;
 
     // private owner mapping
@@ -9,6 +9,10 @@ CREATE TABLE IF NOT EXISTS markets (
   maker_bps     INT NOT NULL,
   taker_bps     INT NOT NULL,
   status        SMALLINT NOT NULL,
+  clearing_mode SMALLINT NOT NULL DEFAULT 0, -- 0 continuous, 1 uniform price
+  allocation    SMALLINT NOT NULL DEFAULT 0, -- 0 FIFO, 1 pro-rata, 2 FIFO/pro-rata
+  fifo_pct      SMALLINT NOT NULL DEFAULT 0, -- FIFO share under allocation 2
+  stp_mode      SMALLINT NOT NULL DEFAULT 0, -- 0 none, 1 cancel newest, 2 oldest, 3 both, 4 decrement
   params_hash   BYTEA NOT NULL
 );
 
@@ -24,7 +28,15 @@ CREATE TABLE IF NOT EXISTS orders (
   time_bucket   INT    NOT NULL,
   nonce         BIGINT NOT NULL,
   ingest_seq    BIGINT NOT NULL,
-  status        SMALLINT NOT NULL DEFAULT 0, -- 0 open, 1 filled, 2 canceled
+  time_in_force SMALLINT NOT NULL DEFAULT 0, -- 0 GTC, 1 IOC, 2 FOK, 3 post-only
+  stop_kind     SMALLINT NOT NULL DEFAULT 0, -- 0 none, 1 stop-market, 2 stop-limit
+  trigger_tick  BIGINT   NOT NULL DEFAULT 0,
+  stop_triggered BOOLEAN NOT NULL DEFAULT false,
+  queue_seq     BIGINT,                      -- re-stamped ingest_seq of a triggered stop or refilled iceberg
+  display_qty   BIGINT,                      -- iceberg slice size; NULL shows everything
+  status        SMALLINT NOT NULL DEFAULT 0, -- 0 open, 1 filled, 2 canceled, 3 rejected, 4 quarantined
+  reject_reason TEXT,                        -- RejectReason, when status = 3
+  quarantine_reason TEXT,                    -- MatchError that parked it, when status = 4
   created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
   updated_at    TIMESTAMPTZ NOT NULL DEFAULT now()
 );
@@ -51,13 +63,35 @@ CREATE TABLE IF NOT EXISTS fills (
   seller_order_hash BYTEA  NOT NULL,
   buyer_pid         BYTEA  NOT NULL,
   seller_pid        BYTEA  NOT NULL,
-  fee_bps           INT    NOT NULL,
+  maker_side        SMALLINT NOT NULL,   -- 0=Bid, 1=Ask
+  maker_fee         NUMERIC(38,0) NOT NULL,
+  taker_fee         NUMERIC(38,0) NOT NULL,
   fill_salt         BYTEA,
   PRIMARY KEY (batch_id, match_id)
 );
 
 CREATE INDEX IF NOT EXISTS idx_fills_pair ON fills(pair_id);
 
+-- implied-trade legs; all legs of a group_id settle together
+CREATE TABLE IF NOT EXISTS fill_legs (
+  batch_id     BIGINT NOT NULL,
+  match_id     BIGINT NOT NULL,
+  group_id     BIGINT NOT NULL,
+  pair_id      BIGINT NOT NULL REFERENCES markets(pair_id),
+  price_tick   BIGINT NOT NULL,
+  fill_qty     BIGINT NOT NULL,
+  time_bucket  INT    NOT NULL,
+  order_id     BIGINT NOT NULL REFERENCES orders(order_id),
+  order_hash   BYTEA  NOT NULL,
+  side         SMALLINT NOT NULL,
+  pid          BYTEA  NOT NULL,
+  is_maker     BOOLEAN NOT NULL,
+  fee          NUMERIC(38,0) NOT NULL,
+  fill_salt    BYTEA,
+  PRIMARY KEY (batch_id, match_id)
+);
+CREATE INDEX IF NOT EXISTS idx_fill_legs_group ON fill_legs(batch_id, group_id);
+
 -- batches/blocks
 CREATE TABLE IF NOT EXISTS batches (
   block_number        BIGINT PRIMARY KEY,
@@ -68,10 +102,25 @@ CREATE TABLE IF NOT EXISTS batches (
   orders_commitment   BYTEA  NOT NULL,
   fills_commitment    BYTEA  NOT NULL,
   timestamp_ms        BIGINT NOT NULL,
+  hash_scheme         SMALLINT NOT NULL, -- 1 blake3, 2 poseidon-bn254, 3 sha256
+  commit_scheme       SMALLINT NOT NULL DEFAULT 1, -- 1 hash chain, 2 merkle
+  proof_status        SMALLINT NOT NULL DEFAULT 0, -- 0 pending, 1 proving, 2 proved, 3 failed
+  proof_attempts      INT      NOT NULL DEFAULT 0,
+  proof_error         TEXT,
+  proof               BYTEA,  -- serialized SP1ProofWithPublicValues
+  proof_public_values BYTEA,
+  proof_vkey          TEXT,
+  proved_at           TIMESTAMPTZ,
   proof_tx_hash       BYTEA,
   proof_artifact_uri  TEXT
 );
 
+-- what each block was matched from (block::BlockExport), for prove-block
+CREATE TABLE IF NOT EXISTS block_inputs (
+  block_number  BIGINT PRIMARY KEY REFERENCES batches(block_number),
+  export        JSONB  NOT NULL
+);
+
 -- optional convenience linking
 CREATE TABLE IF NOT EXISTS batch_fills (
   block_number  BIGINT NOT NULL REFERENCES batches(block_number),
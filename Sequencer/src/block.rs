@@ -1,7 +1,10 @@
 use engine::types::*;
-use async_trait::async_trait;
-use crate::commit::{PoseidonHasher, commit_orders, commit_fills, commit_markets};
-use engine::r#match;
+use crate::commit::{CommitScheme, HashBackend, HashScheme, commit_orders, commit_fills, commit_markets};
+use crate::state::{self, BookUpdate, OrderState};
+use crate::prover::{ProofArtifact, ProofStatus};
+use engine::{BatchOptions, ImpliedRoute, LevelBook};
+use std::collections::{BTreeMap, VecDeque};
+use tokio::sync::Mutex;
 use tracing::{info, debug, warn, instrument};
 
 #[derive(Clone, Copy, Debug)]
@@ -13,52 +16,361 @@ pub struct BatchId(pub u64);
 pub struct BlockHeader {
     pub block_number: BlockNumber,
     pub batch_id: BatchId,
+    pub hash_scheme: HashScheme,     // backend behind the roots and commitments below
+    pub commit_scheme: CommitScheme, // how leaves fold into them
     pub parent_state_root: [u8;32],
-    pub new_state_root: [u8;32],     // set after zk proof
+    pub new_state_root: [u8;32],     // state::OrderState root after this block
     pub markets_root: [u8;32],
     pub orders_commitment: [u8;32],
     pub fills_commitment: [u8;32],
     pub timestamp_ms: u64,
 }
 
+pub use fibonacci_lib::{BlockCommitment, BlockExport, BlockInput, CostModel};
+
+impl From<&BlockHeader> for BlockCommitment {
+    fn from(h: &BlockHeader) -> Self {
+        BlockCommitment {
+            blockNumber: h.block_number.0,
+            batchId: h.batch_id.0,
+            hashScheme: h.hash_scheme.id(),
+            commitScheme: h.commit_scheme.id(),
+            parentStateRoot: h.parent_state_root.into(),
+            newStateRoot: h.new_state_root.into(),
+            marketsRoot: h.markets_root.into(),
+            ordersCommitment: h.orders_commitment.into(),
+            fillsCommitment: h.fills_commitment.into(),
+            timestampMs: h.timestamp_ms,
+        }
+    }
+}
+
+impl TryFrom<BlockCommitment> for BlockHeader {
+    type Error = anyhow::Error;
+
+    /// Fails only on scheme ids this build does not know.
+    fn try_from(c: BlockCommitment) -> anyhow::Result<Self> {
+        Ok(BlockHeader {
+            block_number: BlockNumber(c.blockNumber),
+            batch_id: BatchId(c.batchId),
+            hash_scheme: HashScheme::from_id(c.hashScheme)
+                .ok_or_else(|| anyhow::anyhow!("unknown hash scheme {}", c.hashScheme))?,
+            commit_scheme: CommitScheme::from_id(c.commitScheme)
+                .ok_or_else(|| anyhow::anyhow!("unknown commit scheme {}", c.commitScheme))?,
+            parent_state_root: c.parentStateRoot.0,
+            new_state_root: c.newStateRoot.0,
+            markets_root: c.marketsRoot.0,
+            orders_commitment: c.ordersCommitment.0,
+            fills_commitment: c.fillsCommitment.0,
+            timestamp_ms: c.timestampMs,
+        })
+    }
+}
+
+impl BlockHeader {
+    /// ABI encoding of the header as `BlockCommitment`, the bytes the guest
+    /// commits as public values.
+    pub fn abi_encode(&self) -> Vec<u8> {
+        use alloy_sol_types::SolType;
+        BlockCommitment::abi_encode(&BlockCommitment::from(self))
+    }
+
+    /// Inverse of `abi_encode`, e.g. for a proof's public values.
+    pub fn abi_decode(public_values: &[u8]) -> anyhow::Result<Self> {
+        use alloy_sol_types::SolType;
+        BlockCommitment::abi_decode(public_values)?.try_into()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Block {
     pub header: BlockHeader,
     pub markets_used: Vec<MarketParams>,
-    pub orders_snapshot: Vec<Order>,
+    pub orders_snapshot: Vec<Order>, // open orders at parent_state_root
+    pub updates: Vec<BookUpdate>,    // cancels and amends since the previous block
+    pub new_orders: Vec<Order>,      // arrived since the previous block
     pub fills: Vec<FillDraft>,
+    pub legs: Vec<FillLeg>,           // implied-trade legs, grouped by group_id
+    pub residuals: Vec<OrderResidual>, // remaining quantity after fills and cancels
+    pub rejected: Vec<OrderReject>, // failed validate_order; removed from the book
+    pub triggered: Vec<OrderTrigger>, // stop orders that entered the book
+    pub refills: Vec<OrderRefill>,    // iceberg slices re-queued
+    pub faults: Vec<MarketFault>,     // matching errors; offending orders quarantined
+    pub owners: OwnerMap,             // owner pk hash of every pre-batch order
+    pub implied: Vec<ImpliedRoute>,   // routes the batch was matched with
+}
+
+pub use engine::MarketFault;
+
+impl Block {
+    /// Orders dropped because matching objected to them.
+    pub fn quarantined(&self) -> Vec<OrderId> {
+        self.faults.iter().flat_map(|f| f.error.offending_orders().iter().copied()).collect()
+    }
+
+    /// What the zk guest re-executes this block from. Fill salts are taken
+    /// back out of the fills and legs.
+    pub fn input(&self) -> BlockInput {
+        let salts: BTreeMap<u64, [u8;32]> = self.fills.iter().filter_map(|f| f.fill_salt.map(|s| (f.match_id, s)))
+            .chain(self.legs.iter().filter_map(|l| l.fill_salt.map(|s| (l.match_id, s))))
+            .collect();
+        let salted = self.fills.iter().any(|f| f.fill_salt.is_some()) || self.legs.iter().any(|l| l.fill_salt.is_some());
+        BlockInput {
+            block_number: self.header.block_number.0,
+            batch_id: self.header.batch_id.0,
+            hash_scheme: self.header.hash_scheme,
+            commit_scheme: self.header.commit_scheme,
+            parent_state_root: self.header.parent_state_root,
+            timestamp_ms: self.header.timestamp_ms,
+            markets: self.markets_used.clone(),
+            orders: self.orders_snapshot.clone(),
+            updates: self.updates.clone(),
+            new_orders: self.new_orders.clone(),
+            owners: self.owners.clone(),
+            implied: self.implied.clone(),
+            fill_salts: salted.then_some(salts),
+        }
+    }
+
+    /// `input()` with the header it must prove, for `prove-block`.
+    pub fn export(&self) -> BlockExport {
+        BlockExport { input: self.input(), public_values: self.header.abi_encode() }
+    }
 }
 
 #[async_trait::async_trait]
+#[allow(clippy::double_must_use)]
 pub trait Db: Send + Sync + 'static {
     type Tx<'a>: DbTx + Send where Self: 'a;
     async fn begin_repeatable_read(&self) -> anyhow::Result<Self::Tx<'_>>;
 }
 
 #[async_trait::async_trait]
+#[allow(clippy::double_must_use)]
 pub trait DbTx: Send {
     async fn load_active_markets(&mut self) -> anyhow::Result<Vec<MarketParams>>;
     async fn load_open_orders_snapshot(&mut self) -> anyhow::Result<Vec<Order>>;
+    /// Open orders with `ingest_seq` above their market's cursor; markets
+    /// without a cursor return everything.
+    async fn load_open_orders_after(
+        &mut self, cursors: &BTreeMap<PairId, u64>
+    ) -> anyhow::Result<Vec<Order>>;
     async fn load_owner_pkhash_map_for_orders(
         &mut self, orders: &[Order]
-    ) -> anyhow::Result<std::collections::HashMap<u64, PkHash>>;
+    ) -> anyhow::Result<OwnerMap>;
 
     async fn insert_fills(&mut self, fills: &[FillDraft]) -> anyhow::Result<()>;
+    async fn insert_fill_legs(&mut self, legs: &[FillLeg]) -> anyhow::Result<()>;
     async fn apply_residuals(&mut self, residuals: &[OrderResidual]) -> anyhow::Result<()>;
+    async fn reject_orders(&mut self, rejects: &[OrderReject]) -> anyhow::Result<()>;
+    /// Flag stops as triggered and store their re-stamped sequence. The
+    /// original `ingest_seq` is kept for `load_open_orders_after` cursors.
+    async fn mark_triggered(&mut self, triggers: &[OrderTrigger]) -> anyhow::Result<()>;
+    /// Store the new queue position of refilled icebergs (same column as
+    /// triggered stops; later entries for an order supersede earlier ones).
+    async fn requeue_orders(&mut self, refills: &[OrderRefill]) -> anyhow::Result<()>;
+    /// Park the orders each fault names (e.g. no owner row), with the
+    /// fault as the reason, so they are not loaded again until someone
+    /// repairs them.
+    async fn quarantine_orders(&mut self, faults: &[MarketFault]) -> anyhow::Result<()>;
 
     async fn insert_batch_row(&mut self, header: &BlockHeader) -> anyhow::Result<()>;
+    /// Keep what the block was matched from, so it can be proven later.
+    async fn insert_block_export(&mut self, block_number: BlockNumber, export: &BlockExport) -> anyhow::Result<()>;
     async fn link_fills_to_batch(&mut self, block_num: BlockNumber, fills: &[FillDraft]) -> anyhow::Result<()>;
 
+    async fn load_block_export(&mut self, block_number: BlockNumber) -> anyhow::Result<Option<BlockExport>>;
+    /// Blocks whose proof is pending or was in progress, oldest first,
+    /// with the attempts made on each so far.
+    async fn load_unproven_blocks(&mut self) -> anyhow::Result<Vec<(BlockNumber, u32)>>;
+    async fn set_proof_status(
+        &mut self, block_number: BlockNumber, status: ProofStatus, attempts: u32, error: Option<&str>,
+    ) -> anyhow::Result<()>;
+    /// Attach a finished proof to the block's header row and mark it proved.
+    async fn store_proof(&mut self, block_number: BlockNumber, artifact: &ProofArtifact) -> anyhow::Result<()>;
+
     async fn commit(self) -> anyhow::Result<()>;
 }
 
-pub struct BlockBuilder<D: Db, H: PoseidonHasher> {
+/// Books an incremental `BlockBuilder` keeps in memory between batches.
+#[derive(Default)]
+pub struct LiveBooks {
+    pub books: BTreeMap<PairId, LevelBook>,
+    owners: OwnerMap,               // owner of every order currently resting
+    cursors: BTreeMap<PairId, u64>, // highest ingest_seq loaded per market
+    updates: Vec<BookUpdate>,       // cancels and amends since the last block
+}
+
+impl LiveBooks {
+    /// Resting orders across all markets, in `PairId` then book order.
+    pub fn snapshot(&self) -> Vec<Order> {
+        self.books.values().flat_map(|b| b.snapshot()).collect()
+    }
+}
+
+/// What the matching phase of `build_block` hands to commit/persist.
+struct Matched {
+    updates: Vec<BookUpdate>, // cancels and amends since the last batch
+    orders: Vec<Order>,       // open orders before this batch, by OrderId
+    new_orders: Vec<Order>,   // arrived since the last batch
+    fills: Vec<FillDraft>,
+    legs: Vec<FillLeg>,
+    residuals: Vec<OrderResidual>,
+    rejected: Vec<OrderReject>,
+    triggered: Vec<OrderTrigger>,
+    refills: Vec<OrderRefill>,
+    faults: Vec<MarketFault>,
+    owners: OwnerMap,       // owners of `orders` and `new_orders`, as matched
+}
+
+impl Matched {
+    fn extend_plans(&mut self, plans: Vec<engine::ExecutionPlan>) {
+        for p in plans {
+            debug!(pair_id = p.pair_id.0, fills = p.fills.len(), legs = p.legs.len(), residuals = p.residuals.len(), "matched_market");
+            self.fills.extend(p.fills);
+            self.legs.extend(p.legs);
+            self.residuals.extend(p.residuals);
+            self.triggered.extend(p.triggered);
+            self.refills.extend(p.refills);
+        }
+    }
+
+    fn quarantined(&self) -> Vec<OrderId> {
+        self.faults.iter().flat_map(|f| f.error.offending_orders().iter().copied()).collect()
+    }
+}
+
+/// Keep at most `n` of `orders`, taking markets in turn and each market's
+/// orders by `ingest_seq`, so what is kept per market is a prefix and the
+/// market cursors skip nothing. The rest stay in the database for later.
+fn take_in_turn(orders: Vec<Order>, n: usize) -> (Vec<Order>, usize) {
+    let total = orders.len();
+    if total <= n {
+        return (orders, 0);
+    }
+    let mut per_market: BTreeMap<PairId, VecDeque<Order>> = BTreeMap::new();
+    for o in orders {
+        per_market.entry(o.pair_id).or_default().push_back(o);
+    }
+    for q in per_market.values_mut() {
+        q.make_contiguous().sort_by_key(|o| o.ingest_seq);
+    }
+    let mut kept = Vec::with_capacity(n);
+    while kept.len() < n {
+        for q in per_market.values_mut() {
+            if kept.len() < n {
+                kept.extend(q.pop_front());
+            }
+        }
+    }
+    (kept, total - n)
+}
+
+/// Split `ords` into those passing `validate_order` and rejects.
+fn validated(mkt: &MarketParams, ords: Vec<Order>, rejected: &mut Vec<OrderReject>) -> Vec<Order> {
+    let mut valid = Vec::with_capacity(ords.len());
+    for o in ords {
+        match engine::validate_order(mkt, &o) {
+            Ok(()) => valid.push(o),
+            Err(reason) => rejected.push(OrderReject { order_id: o.order_id, reason }),
+        }
+    }
+    valid
+}
+
+pub struct BlockBuilder<D: Db> {
     db: D,
-    hasher: H,
+    hasher: HashBackend,
+    commit_scheme: CommitScheme,
+    state: Mutex<OrderState<HashBackend>>,
+    live: Option<Mutex<LiveBooks>>,
+    opts: BatchOptions,
+    budget: Option<(CostModel, u64)>, // proving cost model, max cycles per block
 }
 
-impl<D: Db, H: PoseidonHasher + engine::pid::Poseidon32> BlockBuilder<D, H> {
-    pub fn new(db: D, hasher: H) -> Self { Self { db, hasher } }
+impl<D: Db> BlockBuilder<D> {
+    /// `scheme` picks the hash backend for PIDs, commitments and plan
+    /// digests; it is recorded in every header this builder produces.
+    pub fn new(db: D, scheme: HashScheme) -> Self {
+        Self {
+            db,
+            hasher: HashBackend::new(scheme),
+            commit_scheme: CommitScheme::default(),
+            state: Mutex::new(OrderState::new(HashBackend::new(scheme))),
+            live: None,
+            opts: BatchOptions::default(),
+            budget: None,
+        }
+    }
+
+    /// Keep per-market `LevelBook`s across batches and only load orders that
+    /// arrived since the previous block. The first block warms the books
+    /// from the full open-order set.
+    ///
+    /// Cancels and amends must then also go through `cancel_order` /
+    /// `amend_order` so the in-memory book stays in step with the database;
+    /// they are listed in the next block. A snapshot builder finds them by
+    /// comparing the open orders with the state instead.
+    pub fn incremental(db: D, scheme: HashScheme) -> Self {
+        Self { live: Some(Mutex::new(LiveBooks::default())), ..Self::new(db, scheme) }
+    }
+
+    /// Match up to `n` markets at once when rebuilding from a snapshot.
+    /// The block is the same for any `n`; incremental books match in turn.
+    pub fn match_threads(mut self, n: usize) -> Self {
+        self.opts.threads = n.max(1);
+        self
+    }
+
+    /// Fold commitments with `scheme` instead of the default Merkle trees.
+    pub fn commit_scheme(mut self, scheme: CommitScheme) -> Self {
+        self.commit_scheme = scheme;
+        self
+    }
+
+    /// Let orders on these triangles fill through the other two markets
+    /// once outright matching is done (see `engine::implied`).
+    pub fn implied_routes(mut self, routes: Vec<ImpliedRoute>) -> Self {
+        self.opts.implied = routes;
+        self
+    }
+
+    /// Keep blocks provable within `max_cycles` by `model` (see the
+    /// `profile-block` script). Incremental builders defer new orders past
+    /// the order count that fits, oldest first per market; a snapshot block
+    /// has to carry the whole book, so it is only reported when over.
+    pub fn proving_budget(mut self, model: CostModel, max_cycles: u64) -> Self {
+        self.budget = Some((model, max_cycles));
+        self
+    }
+
+    pub async fn cancel_order(&self, pair_id: PairId, id: OrderId) -> anyhow::Result<()> {
+        if let Some(live) = &self.live {
+            let mut live = live.lock().await;
+            if let Some(book) = live.books.get_mut(&pair_id) {
+                book.cancel(id).map_err(|e| anyhow::anyhow!("cancel {e:?}"))?;
+                live.owners.remove(&id.0);
+                live.updates.push(BookUpdate::Cancel(id));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn amend_order(
+        &self, pair_id: PairId, id: OrderId, price_tick: u64, remaining: u64, requeue_seq: u64,
+    ) -> anyhow::Result<()> {
+        if let Some(live) = &self.live {
+            let mut live = live.lock().await;
+            if let Some(book) = live.books.get_mut(&pair_id) {
+                book.amend(id, price_tick, remaining, requeue_seq).map_err(|e| anyhow::anyhow!("amend {e:?}"))?;
+                if remaining == 0 {
+                    live.owners.remove(&id.0);
+                }
+                live.updates.push(BookUpdate::Amend { order_id: id, price_tick, remaining, requeue_seq });
+            }
+        }
+        Ok(())
+    }
 
     #[instrument(level = "info", skip(self, salt_fn), fields(block_number = block_number.0, batch_id = batch_id.0, use_fill_salt))]
     pub async fn build_block(
@@ -68,70 +380,586 @@ impl<D: Db, H: PoseidonHasher + engine::pid::Poseidon32> BlockBuilder<D, H> {
         parent_state_root: [u8;32],
         timestamp_ms: u64,
         use_fill_salt: bool,
-        mut salt_fn: impl FnMut(u64,u64)->[u8;32] + Send,
+        salt_fn: impl Fn(u64,u64)->[u8;32] + Send + Sync,
     ) -> anyhow::Result<Block> {
         debug!("begin_block_build");
+        let mut state = self.state.lock().await;
+        anyhow::ensure!(
+            state.root() == parent_state_root,
+            "parent_state_root is not the root of the last block built here",
+        );
         let mut tx = self.db.begin_repeatable_read().await?;
 
         let markets = tx.load_active_markets().await?;
         debug!(markets_len = markets.len(), "loaded_markets");
-        let markets_root = commit_markets(&self.hasher, &markets);
+        let markets_root = commit_markets(&self.hasher, self.commit_scheme, &markets);
 
-        let orders = tx.load_open_orders_snapshot().await?;
-        debug!(orders_len = orders.len(), "loaded_orders_snapshot");
-        let owner_map = tx.load_owner_pkhash_map_for_orders(&orders).await?;
+        // An error after the live books were touched leaves them ahead of the
+        // database; drop them so the next block reloads from scratch.
+        let mut live = match &self.live {
+            Some(m) => Some(m.lock().await),
+            None => None,
+        };
+        let res = async {
+            let matched = match live.as_deref_mut() {
+                None => self.match_snapshot(&state, &mut tx, &markets, batch_id, use_fill_salt, &salt_fn).await?,
+                Some(l) => self.match_live(&state, l, &mut tx, &markets, batch_id, use_fill_salt, &salt_fn).await?,
+            };
+            self.finish_block(&mut state, tx, matched, markets, block_number, batch_id, parent_state_root, markets_root, timestamp_ms).await
+        }.await;
+        if res.is_err() {
+            if let Some(l) = live.as_deref_mut() {
+                warn!("resetting_live_books");
+                *l = LiveBooks::default();
+            }
+        }
+        res
+    }
+
+    /// Full rebuild: load every open order and match each market from scratch.
+    async fn match_snapshot(
+        &self,
+        state: &OrderState<HashBackend>,
+        tx: &mut D::Tx<'_>,
+        markets: &[MarketParams],
+        batch_id: BatchId,
+        use_fill_salt: bool,
+        salt_fn: impl Fn(u64,u64)->[u8;32] + Send + Sync,
+    ) -> anyhow::Result<Matched> {
+        let open = tx.load_open_orders_snapshot().await?;
+        debug!(orders_len = open.len(), "loaded_orders_snapshot");
+        let owner_map = tx.load_owner_pkhash_map_for_orders(&open).await?;
         debug!(owners = owner_map.len(), "loaded_owner_map");
 
-        // group by market
-        use std::collections::BTreeMap;
-        let mut map: BTreeMap<PairId, (MarketParams, Vec<Order>)> = BTreeMap::new();
-        for m in &markets { map.insert(m.pair_id, (m.clone(), Vec::new())); }
-        for o in orders.iter().cloned() {
-            if let Some((_, v)) = map.get_mut(&o.pair_id) { v.push(o); }
+        // whatever differs from the state was cancelled, amended or is new
+        let (updates, new_orders) = state.diff(open);
+        let orders: Vec<Order> = state::pre_batch(state, &updates)?.orders().cloned().collect();
+        debug!(updates = updates.len(), new_orders = new_orders.len(), "diffed_against_state");
+        let plan = engine::match_batch(
+            batch_id.0, markets, orders.clone(), new_orders.clone(), &owner_map, &self.hasher, use_fill_salt,
+            salt_fn, &self.opts,
+        );
+        for f in &plan.faults {
+            warn!(pair_id = f.pair_id.0, error = ?f.error, "match_failed");
         }
-        debug!(markets_with_orders = map.len(), "grouped_orders_by_market");
 
-        let mut all_fills = Vec::<FillDraft>::new();
-        let mut all_residuals = Vec::<OrderResidual>::new();
+        let mut m = Matched {
+            updates, orders, new_orders, fills: Vec::new(), legs: Vec::new(), residuals: Vec::new(),
+            rejected: plan.rejected, triggered: Vec::new(), refills: Vec::new(), faults: plan.faults,
+            owners: owner_map,
+        };
+        m.extend_plans(plan.plans);
+        Ok(m)
+    }
+
+    /// Incremental: load only new orders and match them into the live books.
+    /// Cold books are first warmed with the open orders the state holds, so
+    /// only orders it has not seen come in as new.
+    #[allow(clippy::too_many_arguments)]
+    async fn match_live(
+        &self,
+        state: &OrderState<HashBackend>,
+        live: &mut LiveBooks,
+        tx: &mut D::Tx<'_>,
+        markets: &[MarketParams],
+        batch_id: BatchId,
+        use_fill_salt: bool,
+        mut salt_fn: impl FnMut(u64,u64)->[u8;32] + Send,
+    ) -> anyhow::Result<Matched> {
+        let book_err = |e: engine::BookError| anyhow::anyhow!("live book: {e:?}");
+
+        let (updates, mut new_orders) = if live.books.is_empty() {
+            let (updates, added) = state.diff(tx.load_open_orders_after(&live.cursors).await?);
+            let resting: Vec<Order> = state::pre_batch(state, &updates)?.orders().cloned().collect();
+            live.owners.extend(tx.load_owner_pkhash_map_for_orders(&resting).await?);
+            debug!(resting = resting.len(), updates = updates.len(), "warming_live_books");
+            for o in resting {
+                let c = live.cursors.entry(o.pair_id).or_insert(0);
+                *c = (*c).max(o.ingest_seq);
+                live.books.entry(o.pair_id).or_insert_with(|| LevelBook::new(o.pair_id)).insert(o).map_err(book_err)?;
+            }
+            live.updates.clear();
+            (updates, added)
+        } else {
+            (std::mem::take(&mut live.updates), tx.load_open_orders_after(&live.cursors).await?)
+        };
+        debug!(new_orders = new_orders.len(), "loaded_new_orders");
+        if let Some((model, max_cycles)) = self.budget {
+            let resting: usize = live.books.values().map(|b| b.len()).sum();
+            let room = (model.max_orders(max_cycles) as usize).saturating_sub(resting);
+            let (kept, deferred) = take_in_turn(new_orders, room);
+            new_orders = kept;
+            if deferred > 0 {
+                warn!(deferred, resting, room, "new_orders_deferred_for_proving_budget");
+            }
+        }
+        let new_owners = tx.load_owner_pkhash_map_for_orders(&new_orders).await?;
+        live.owners.extend(new_owners);
+        // the guest matches what the state holds, which the books must equal
+        let orders: Vec<Order> = state::pre_batch(state, &updates)?.orders().cloned().collect();
+        let resting: usize = live.books.values().map(|b| b.len()).sum();
+        anyhow::ensure!(resting == orders.len(), "live books hold {resting} orders, the state {}", orders.len());
+        let owners: OwnerMap = orders.iter().chain(&new_orders)
+            .filter_map(|o| live.owners.get(&o.order_id.0).map(|pk| (o.order_id.0, *pk)))
+            .collect();
 
-        for (pair_id, (mkt, ords)) in map {
-            debug!(pair_id = pair_id.0, orders_for_market = ords.len(), "matching_market");
-            let plan = r#match::match_market(
-                pair_id, batch_id.0, &mkt, ords, &owner_map, &self.hasher, use_fill_salt,
-                |b, i| salt_fn(b, i),
+        let mut incoming: BTreeMap<PairId, Vec<Order>> = BTreeMap::new();
+        for o in &new_orders {
+            let c = live.cursors.entry(o.pair_id).or_insert(0);
+            *c = (*c).max(o.ingest_seq);
+            incoming.entry(o.pair_id).or_default().push(o.clone());
+        }
+
+        let mut plans = Vec::<engine::ExecutionPlan>::new();
+        let mut rejected = Vec::<OrderReject>::new();
+        let mut faults = Vec::<MarketFault>::new();
+
+        for mkt in markets {
+            let ords = incoming.remove(&mkt.pair_id).unwrap_or_default();
+            let book = live.books.entry(mkt.pair_id).or_insert_with(|| LevelBook::new(mkt.pair_id));
+            // as in engine::match_batch, an Active market checks what rests
+            // too: its parameters may have changed since the orders arrived
+            if mkt.status == MarketStatus::Active {
+                for o in book.snapshot() {
+                    if let Err(reason) = engine::validate_order(mkt, &o) {
+                        book.cancel(o.order_id).map_err(book_err)?;
+                        live.owners.remove(&o.order_id.0);
+                        rejected.push(OrderReject { order_id: o.order_id, reason });
+                    }
+                }
+            }
+            let valid = validated(mkt, ords, &mut rejected);
+            if mkt.status != MarketStatus::Active {
+                debug!(pair_id = mkt.pair_id.0, status = ?mkt.status, "skipping_inactive_market");
+                for o in valid { book.insert(o).map_err(book_err)?; }
+                continue;
+            }
+
+            debug!(pair_id = mkt.pair_id.0, new_orders = valid.len(), resting = book.len(), "matching_market");
+            // incoming stays in the book on a match error, so retries pass nothing new
+            let mut valid = Some(valid);
+            let plan = loop {
+                match book.match_batch(
+                    valid.take().unwrap_or_default(), mkt, batch_id.0, engine::batch::match_id_base(mkt.pair_id), &live.owners, &self.hasher,
+                    use_fill_salt, &mut salt_fn,
+                ) {
+                    Ok(plan) => break Some(plan),
+                    Err(engine::BookError::Match(error)) => {
+                        warn!(pair_id = mkt.pair_id.0, ?error, "match_failed");
+                        let bad = error.offending_orders().to_vec();
+                        faults.push(MarketFault { pair_id: mkt.pair_id, error });
+                        if bad.is_empty() {
+                            break None;
+                        }
+                        for id in bad {
+                            book.cancel(id).map_err(book_err)?;
+                            live.owners.remove(&id.0);
+                        }
+                    }
+                    Err(e) => return Err(book_err(e)),
+                }
+            };
+            plans.extend(plan);
+        }
+
+        // orders for markets that were not loaded just rest until they are
+        for (pair_id, ords) in incoming {
+            let book = live.books.entry(pair_id).or_insert_with(|| LevelBook::new(pair_id));
+            for o in ords { book.insert(o).map_err(book_err)?; }
+        }
+
+        // same plan order as engine::match_batch, which the zk guest replays
+        plans.sort_by_key(|p| p.pair_id);
+
+        // implied legs trade what now rests, then come off the live books;
+        // icebergs they refill move back in their levels
+        if !self.opts.implied.is_empty() {
+            let mut resting: BTreeMap<PairId, Vec<Order>> = plans.iter()
+                .filter_map(|p| live.books.get(&p.pair_id).map(|b| (p.pair_id, b.snapshot())))
+                .collect();
+            engine::match_implied(
+                batch_id.0, &self.opts.implied, markets, &mut resting, &mut plans, &live.owners, &self.hasher,
+                use_fill_salt, &mut salt_fn,
             );
-            debug!(pair_id = pair_id.0, fills = plan.fills.len(), residuals = plan.residuals.len(), "matched_market");
-            all_fills.extend(plan.fills);
-            all_residuals.extend(plan.residuals);
+            for l in plans.iter().flat_map(|p| &p.legs) {
+                if let Some(book) = live.books.get_mut(&l.pair_id) {
+                    book.apply_fill(l.order_id, l.fill_qty).map_err(book_err)?;
+                }
+            }
+            for p in &plans {
+                let Some(book) = live.books.get_mut(&p.pair_id) else { continue };
+                for r in &p.refills {
+                    if book.get(r.order_id).is_some() {
+                        book.requeue(r.order_id, r.ingest_seq).map_err(book_err)?;
+                    }
+                }
+            }
+        }
+
+        let mut m = Matched {
+            updates, orders, new_orders, fills: Vec::new(), legs: Vec::new(), residuals: Vec::new(),
+            rejected, triggered: Vec::new(), refills: Vec::new(), faults, owners,
+        };
+        m.extend_plans(plans);
+        for r in m.residuals.iter().filter(|r| r.remaining_after == 0) {
+            live.owners.remove(&r.order_id.0);
+        }
+        for r in &m.rejected {
+            live.owners.remove(&r.order_id.0);
+        }
+        Ok(m)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn finish_block(
+        &self,
+        state: &mut OrderState<HashBackend>,
+        mut tx: D::Tx<'_>,
+        m: Matched,
+        markets: Vec<MarketParams>,
+        block_number: BlockNumber,
+        batch_id: BatchId,
+        parent_state_root: [u8;32],
+        markets_root: [u8;32],
+        timestamp_ms: u64,
+    ) -> anyhow::Result<Block> {
+        info!(total_fills = m.fills.len(), total_residuals = m.residuals.len(), "matching_complete");
+        if !m.rejected.is_empty() {
+            warn!(rejected = m.rejected.len(), "orders_failed_validation");
+        }
+        if !m.faults.is_empty() {
+            warn!(faults = m.faults.len(), quarantined = m.quarantined().len(), "markets_with_match_errors");
+        }
+
+        if let Some((model, max_cycles)) = self.budget {
+            let orders = (m.orders.len() + m.new_orders.len()) as u64;
+            let cycles = model.cycles(orders, (m.fills.len() + m.legs.len()) as u64);
+            debug!(estimated_cycles = cycles, max_cycles, "proving_cost");
+            if cycles > max_cycles {
+                warn!(estimated_cycles = cycles, max_cycles, orders, "block_over_proving_budget");
+            }
         }
-        info!(total_fills = all_fills.len(), total_residuals = all_residuals.len(), "matching_complete");
 
-        // commitments
-        let orders_commitment = commit_orders(&self.hasher, &orders);
-        let fills_commitment  = commit_fills(&self.hasher, &all_fills);
+        // commitments (the full pre-batch book, so this part stays O(open orders))
+        let committed: Vec<Order> = m.orders.iter().chain(&m.new_orders).cloned().collect();
+        let orders_commitment = commit_orders(&self.hasher, self.commit_scheme, &committed);
+        let fills_commitment  = commit_fills(&self.hasher, self.commit_scheme, &m.fills, &m.legs);
         debug!("computed_commitments");
 
         // persist
-        tx.insert_fills(&all_fills).await?;
-        tx.apply_residuals(&all_residuals).await?;
+        tx.insert_fills(&m.fills).await?;
+        tx.insert_fill_legs(&m.legs).await?;
+        tx.apply_residuals(&m.residuals).await?;
+        tx.reject_orders(&m.rejected).await?;
+        tx.mark_triggered(&m.triggered).await?;
+        tx.requeue_orders(&m.refills).await?;
+        tx.quarantine_orders(&m.faults).await?;
         debug!("persisted_fills_and_residuals");
 
         let header = BlockHeader {
-            block_number, batch_id, parent_state_root,
-            new_state_root: [0u8;32], // fill after zk proof
+            block_number, batch_id,
+            hash_scheme: self.hasher.scheme(),
+            commit_scheme: self.commit_scheme,
+            parent_state_root,
+            new_state_root: [0u8;32], // below, from the block contents
             markets_root, orders_commitment, fills_commitment,
             timestamp_ms,
         };
-        tx.insert_batch_row(&header).await?;
-        tx.link_fills_to_batch(block_number, &all_fills).await?;
+        let mut block = Block {
+            header,
+            markets_used: markets,
+            orders_snapshot: state.orders().cloned().collect(),
+            updates: m.updates,
+            new_orders: m.new_orders,
+            fills: m.fills,
+            legs: m.legs,
+            residuals: m.residuals,
+            rejected: m.rejected,
+            triggered: m.triggered,
+            refills: m.refills,
+            faults: m.faults,
+            owners: m.owners,
+            implied: self.opts.implied.clone(),
+        };
+        let next = state::apply_block(state, &block)?;
+        block.header.new_state_root = next.root();
+        debug!("computed_state_root");
+
+        tx.insert_batch_row(&block.header).await?;
+        tx.insert_block_export(block_number, &block.export()).await?;
+        tx.link_fills_to_batch(block_number, &block.fills).await?;
         tx.commit().await?;
+        // only a committed block moves the state
+        *state = next;
         info!("block_persisted");
+        Ok(block)
+    }
 
-        Ok(Block {
-            header,
-            markets_used: markets,
-            orders_snapshot: orders,
-            fills: all_fills,
-        })
+    /// Root of the open-order state after the last block built here; the
+    /// next block's `parent_state_root`. Before the first block this is the
+    /// empty-state root.
+    pub async fn state_root(&self) -> [u8;32] {
+        self.state.lock().await.root()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_db::{market, order, MemDb};
+
+    fn builder(db: MemDb, live: bool) -> BlockBuilder<MemDb> {
+        match live {
+            true => BlockBuilder::incremental(db, HashScheme::Blake3),
+            false => BlockBuilder::new(db, HashScheme::Blake3),
+        }
+    }
+
+    async fn build(b: &BlockBuilder<MemDb>, n: u64) -> anyhow::Result<Block> {
+        let parent = b.state_root().await;
+        b.build_block(BlockNumber(n), BatchId(n), parent, n, false, |_, _| [0u8; 32]).await
+    }
+
+    /// The zk guest, given the block's input, commits the block's header.
+    fn assert_replays(block: &Block) {
+        let replayed = BlockHeader::try_from(fibonacci_lib::execute_block(&block.input())).unwrap();
+        assert_eq!(replayed.abi_encode(), block.header.abi_encode());
+    }
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            block_number: BlockNumber(42), batch_id: BatchId(7),
+            hash_scheme: HashScheme::Sha256, commit_scheme: CommitScheme::HashChain,
+            parent_state_root: [1u8; 32], new_state_root: [2u8; 32], markets_root: [3u8; 32],
+            orders_commitment: [4u8; 32], fills_commitment: [5u8; 32],
+            timestamp_ms: 1_700_000_000_000,
+        }
+    }
+
+    #[test]
+    fn header_round_trips_through_the_abi() {
+        let h = header();
+        let bytes = h.abi_encode();
+        assert_eq!(bytes.len(), 10 * 32); // static struct: one word per field
+        let back = BlockHeader::abi_decode(&bytes).unwrap();
+        assert_eq!(back.abi_encode(), bytes);
+        assert_eq!((back.block_number.0, back.batch_id.0, back.timestamp_ms), (42, 7, 1_700_000_000_000));
+        assert_eq!((back.hash_scheme, back.commit_scheme), (HashScheme::Sha256, CommitScheme::HashChain));
+        assert_eq!((back.parent_state_root, back.fills_commitment), ([1u8; 32], [5u8; 32]));
+    }
+
+    #[test]
+    fn unknown_scheme_ids_do_not_convert() {
+        let c = BlockCommitment::from(&header());
+        let err = BlockHeader::try_from(BlockCommitment { hashScheme: 99, ..c.clone() }).unwrap_err();
+        assert!(err.to_string().contains("unknown hash scheme 99"));
+        let err = BlockHeader::try_from(BlockCommitment { commitScheme: 99, ..c }).unwrap_err();
+        assert!(err.to_string().contains("unknown commit scheme 99"));
+        assert!(BlockHeader::abi_decode(&[0u8; 31]).is_err());
+    }
+
+    #[tokio::test]
+    async fn cancels_and_amends_between_blocks_are_listed_and_replay() {
+        let mut roots = Vec::new();
+        for live in [false, true] {
+            let db = MemDb::new(vec![market(1)]);
+            for o in [order(1, Side::Ask, 100, 5, 1), order(2, Side::Bid, 90, 5, 2), order(3, Side::Ask, 110, 5, 3)] {
+                db.add(o);
+            }
+            let b = builder(db.clone(), live);
+            let b1 = build(&b, 1).await.unwrap();
+            assert_eq!(b1.header.parent_state_root, OrderState::genesis_root(&HashBackend::new(HashScheme::Blake3)));
+            assert_replays(&b1);
+
+            db.cancel(OrderId(1));
+            db.amend(OrderId(2), 95, 3, 10);
+            b.cancel_order(PairId(1), OrderId(1)).await.unwrap();
+            b.amend_order(PairId(1), OrderId(2), 95, 3, 10).await.unwrap();
+            db.add(order(4, Side::Bid, 110, 2, 11));
+
+            let b2 = build(&b, 2).await.unwrap();
+            assert_eq!(b2.header.parent_state_root, b1.header.new_state_root);
+            assert_eq!(b2.orders_snapshot.len(), 3);
+            assert_eq!(b2.updates, [
+                BookUpdate::Cancel(OrderId(1)),
+                BookUpdate::Amend { order_id: OrderId(2), price_tick: 95, remaining: 3, requeue_seq: 10 },
+            ]);
+            assert_eq!(b2.new_orders.iter().map(|o| o.order_id.0).collect::<Vec<_>>(), [4]);
+            assert_eq!(b2.fills.len(), 1);
+            assert_replays(&b2);
+            roots.push((b1.header.new_state_root, b2.header.new_state_root));
+        }
+        assert_eq!(roots[0], roots[1]);
+    }
+
+    #[tokio::test]
+    async fn consecutive_blocks_aggregate() {
+        let db = MemDb::new(vec![market(1)]);
+        db.add(order(1, Side::Ask, 100, 5, 1));
+        let b = builder(db.clone(), false);
+        let b1 = build(&b, 1).await.unwrap();
+        db.add(order(2, Side::Bid, 100, 2, 2));
+        let b2 = build(&b, 2).await.unwrap();
+
+        let pvs = [b1.header.abi_encode(), b2.header.abi_encode()];
+        let agg = fibonacci_lib::aggregate([0u8; 32], &pvs).unwrap();
+        assert_eq!(agg.parentStateRoot, b1.header.parent_state_root);
+        assert_eq!(agg.newStateRoot, b2.header.new_state_root);
+        // a block 2 built on another state does not link
+        let other = builder(MemDb::new(vec![market(1)]), false);
+        build(&other, 1).await.unwrap();
+        let pvs = [b1.header.abi_encode(), build(&other, 2).await.unwrap().header.abi_encode()];
+        assert_eq!(fibonacci_lib::aggregate([0u8; 32], &pvs).unwrap_err(), fibonacci_lib::AggregateError::Unlinked { block: 2 });
+    }
+
+    #[tokio::test]
+    async fn guest_checks_the_orders_against_the_parent_root() {
+        let db = MemDb::new(vec![market(1)]);
+        db.add(order(1, Side::Ask, 100, 5, 1));
+        db.add(order(2, Side::Bid, 90, 5, 2));
+        let b = builder(db.clone(), false);
+        build(&b, 1).await.unwrap();
+        db.add(order(3, Side::Bid, 100, 5, 3));
+        let block = build(&b, 2).await.unwrap();
+        assert_replays(&block);
+
+        // a different parent book, e.g. without the resting ask
+        let mut input = block.input();
+        input.orders.retain(|o| o.order_id != OrderId(1));
+        assert!(std::panic::catch_unwind(|| fibonacci_lib::execute_block(&input)).is_err());
+    }
+
+    #[tokio::test]
+    async fn resting_orders_are_revalidated_like_the_guest() {
+        for live in [false, true] {
+            let db = MemDb::new(vec![market(1)]);
+            db.add(order(1, Side::Ask, 101, 5, 1));
+            db.add(order(2, Side::Ask, 110, 5, 2));
+            let b = builder(db.clone(), live);
+            assert_replays(&build(&b, 1).await.unwrap());
+
+            // the tick grows; the resting ask at 101 is now off-tick
+            db.with(|t| t.markets[0].price_tick = 5);
+            db.add(order(3, Side::Bid, 105, 5, 3));
+            let block = build(&b, 2).await.unwrap();
+            assert_eq!(block.rejected.iter().map(|r| (r.order_id, r.reason)).collect::<Vec<_>>(), [(OrderId(1), RejectReason::OffTick)]);
+            assert!(block.fills.is_empty());
+            assert_replays(&block);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_market_fault_quarantines_its_orders_and_spares_the_rest() {
+        for live in [false, true] {
+            let db = MemDb::new(vec![market(1), market(2)]);
+            db.add(order(1, Side::Ask, 100, 5, 1));
+            db.add(order(2, Side::Bid, 100, 5, 2));
+            db.add(Order { pair_id: PairId(2), ..order(3, Side::Ask, 100, 5, 3) });
+            db.add(Order { pair_id: PairId(2), ..order(4, Side::Bid, 100, 5, 4) });
+            db.with(|t| t.owners.remove(&1));
+            let b = builder(db.clone(), live);
+            let block = build(&b, 1).await.unwrap();
+
+            assert_eq!(block.faults.len(), 1);
+            assert_eq!(block.faults[0].pair_id, PairId(1));
+            assert_eq!(block.faults[0].error, engine::MatchError::MissingOwners(vec![OrderId(1)]));
+            assert_eq!(block.quarantined(), [OrderId(1)]);
+            assert_eq!(db.with(|t| t.quarantined.get(&OrderId(1)).cloned()), Some(format!("{:?}", block.faults[0].error)));
+            assert!(b.state.lock().await.get(OrderId(1)).is_none());
+            assert!(block.fills.iter().any(|f| f.pair_id == PairId(2)));
+            assert_replays(&block);
+        }
+    }
+
+    #[tokio::test]
+    async fn orders_over_the_proving_budget_wait_their_turn() {
+        // one cycle per order, so the budget is the order count
+        let model = CostModel { base: 0.0, per_order: 1.0, per_fill: 0.0 };
+        let db = MemDb::new(vec![market(1), market(2)]);
+        db.add(order(1, Side::Ask, 100, 5, 1));
+        db.add(order(2, Side::Bid, 100, 5, 2));
+        db.add(order(3, Side::Ask, 100, 5, 3));
+        db.add(Order { pair_id: PairId(2), ..order(4, Side::Ask, 100, 5, 4) });
+        db.add(Order { pair_id: PairId(2), ..order(5, Side::Bid, 100, 5, 5) });
+        let b = builder(db.clone(), true).proving_budget(model, 3);
+        let ids = |block: &Block| {
+            let mut ids: Vec<u64> = block.new_orders.iter().map(|o| o.order_id.0).collect();
+            ids.sort();
+            ids
+        };
+
+        // markets take turns, oldest first: 1 and 2 from pair 1, 4 from pair 2
+        let b1 = build(&b, 1).await.unwrap();
+        assert_eq!(ids(&b1), [1, 2, 4]);
+        assert_eq!(b1.fills.len(), 1);
+        assert_replays(&b1);
+
+        // 4 rests, leaving room for the two deferred orders
+        let b2 = build(&b, 2).await.unwrap();
+        assert_eq!(ids(&b2), [3, 5]);
+        assert_eq!(b2.fills.len(), 1);
+        assert_replays(&b2);
+        assert!(build(&b, 3).await.unwrap().new_orders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn implied_refills_requeue_icebergs_in_the_live_books() {
+        let route = ImpliedRoute { outright: PairId(1), first: PairId(2), second: PairId(3) };
+        let on = |pair: u32, o: Order| Order { pair_id: PairId(pair), ..o };
+        let mut roots = Vec::new();
+        for live in [false, true] {
+            let db = MemDb::new(vec![market(1), market(2), market(3)]);
+            db.add(on(2, Order { display_qty: Some(3), ..order(1, Side::Ask, 10, 10, 1) }));
+            db.add(on(2, order(2, Side::Ask, 10, 5, 2)));
+            db.add(on(3, order(3, Side::Ask, 20, 100, 3)));
+            db.add(order(4, Side::Bid, 210, 5, 4));
+            let b = builder(db.clone(), live).implied_routes(vec![route]);
+            let b1 = build(&b, 1).await.unwrap();
+            assert_eq!(b1.refills.iter().map(|r| r.order_id).collect::<Vec<_>>(), [OrderId(1)]);
+            assert_replays(&b1);
+
+            // the plain ask is now ahead of the iceberg
+            db.add(order(5, Side::Bid, 210, 2, 5));
+            let b2 = build(&b, 2).await.unwrap();
+            assert_eq!(b2.legs.iter().filter(|l| l.pair_id == PairId(2)).map(|l| l.order_id).collect::<Vec<_>>(), [OrderId(2)]);
+            assert_replays(&b2);
+            roots.push(b2.header.new_state_root);
+        }
+        assert_eq!(roots[0], roots[1]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_commit_leaves_the_state_for_a_retry() {
+        for live in [false, true] {
+            let db = MemDb::new(vec![market(1)]);
+            db.add(order(1, Side::Ask, 100, 5, 1));
+            let b = builder(db.clone(), live);
+            build(&b, 1).await.unwrap();
+            let root = b.state_root().await;
+
+            db.add(order(2, Side::Bid, 100, 2, 2));
+            db.fail_on(Some("commit"));
+            assert!(build(&b, 2).await.is_err());
+            assert_eq!(b.state_root().await, root);
+            assert!(db.with(|t| t.fills.is_empty()));
+
+            db.fail_on(None);
+            let block = build(&b, 2).await.unwrap();
+            assert_eq!(block.header.parent_state_root, root);
+            assert_eq!(block.fills.len(), 1);
+            assert_replays(&block);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_block_must_extend_the_state_root() {
+        let db = MemDb::new(vec![market(1)]);
+        db.add(order(1, Side::Ask, 100, 5, 1));
+        let b = builder(db.clone(), false);
+        let root = b.state_root().await;
+        let err = b.build_block(BlockNumber(1), BatchId(1), [9u8; 32], 0, false, |_, _| [0u8; 32]).await;
+        assert!(err.is_err());
+        assert_eq!(b.state_root().await, root);
+        assert!(db.with(|t| t.headers.is_empty()));
     }
 }
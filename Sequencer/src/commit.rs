@@ -1,74 +1,70 @@
-use engine::types::{FillDraft, MarketParams, Order};
-use tracing::debug;
+use engine::{merkle_proof, verify_merkle};
 
-pub trait PoseidonHasher {
-    fn h_bytes(&self, domain_tag: u64, bytes: &[u8]) -> [u8; 32];
-    fn h2(&self, domain_tag: u64, a: [u8; 32], b: [u8; 32]) -> [u8; 32];
-}
+use crate::block::Block;
+pub use engine::hash::{Hasher, HashBackend, HashScheme};
+pub use engine::MerkleProof;
+// Commitments live in the engine so the zk guest recomputes them exactly.
+pub use engine::commit::*;
 
-pub struct BlakePoseidonStub;
-impl PoseidonHasher for BlakePoseidonStub {
-    fn h_bytes(&self, tag: u64, bytes: &[u8]) -> [u8; 32] {
-        let mut v = tag.to_le_bytes().to_vec();
-        v.extend_from_slice(bytes);
-        *blake3::hash(&v).as_bytes()
-    }
-    fn h2(&self, tag: u64, a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
-        let mut v = tag.to_le_bytes().to_vec();
-        v.extend_from_slice(&a);
-        v.extend_from_slice(&b);
-        *blake3::hash(&v).as_bytes()
+/// Inclusion proof for the fill or implied leg with `match_id` against
+/// `block.header.fills_commitment`. `None` if no such match, or the block
+/// was committed with `CommitScheme::HashChain`.
+pub fn prove_fill(block: &Block, match_id: u64) -> Option<MerkleProof> {
+    if block.header.commit_scheme != CommitScheme::Merkle {
+        return None;
     }
+    let index = block.fills.iter().map(|f| f.match_id)
+        .chain(block.legs.iter().map(|l| l.match_id))
+        .position(|id| id == match_id)?;
+    let h = HashBackend::new(block.header.hash_scheme);
+    merkle_proof(&h, domains::FILLS_NODE, &fill_leaves(&h, &block.fills, &block.legs), index)
 }
 
-pub mod domains {
-    // Original verbose domain tags (placeholders); replace with real ones later.
-    pub const ORDER_LEAF: u64 = 0x76C6; // "order_leaf"
-    pub const ORDERS_ACC: u64 = 0x72646; // "orders_acc"
-    pub const FILL_LEAF: u64 = 0x66C66; // "fill_leaf"
-    pub const FILLS_ACC: u64 = 0x66663; // "fills_acc"
-    pub const MARKET_LEAF: u64 = 0x6D61726; // "market_leaf"
-    pub const MARKETS_ACC: u64 = 0x6D61723; // "markets_acc"
+/// Checks `leaf` (`fill_leaf` or `leg_leaf`) against a block's
+/// `fills_commitment`, with the hasher named by its `hash_scheme`.
+pub fn verify_fill_inclusion<H: Hasher>(h: &H, root: [u8; 32], leaf: [u8; 32], proof: &MerkleProof) -> bool {
+    verify_merkle(h, domains::FILLS_NODE, root, leaf, proof)
 }
 
-pub fn commit_orders<H: PoseidonHasher>(h: &H, orders: &[Order]) -> [u8; 32] {
-    use crate::encode::encode_order;
-    let mut acc = [0u8; 32];
-    for (i, o) in orders.iter().enumerate() {
-        let leaf = h.h_bytes(domains::ORDER_LEAF, &encode_order(o));
-        acc = h.h2(domains::ORDERS_ACC, acc, leaf);
-        if i % 1024 == 0 {
-            debug!(i, total = orders.len(), "commit_orders_progress");
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BatchId, BlockBuilder, BlockNumber};
+    use crate::mem_db::{market, order, MemDb};
+    use engine::types::Side;
+
+    /// One ask swept by a bid, then a second bid that fills against the rest.
+    async fn block_with_fills(scheme: CommitScheme) -> Block {
+        let db = MemDb::new(vec![market(1)]);
+        db.add(order(1, Side::Ask, 100, 2, 1));
+        db.add(order(2, Side::Ask, 101, 2, 2));
+        db.add(order(3, Side::Bid, 101, 3, 3));
+        db.add(order(4, Side::Bid, 101, 1, 4));
+        let b = BlockBuilder::new(db, HashScheme::Blake3).commit_scheme(scheme);
+        let parent = b.state_root().await;
+        b.build_block(BlockNumber(1), BatchId(1), parent, 0, false, |_, _| [0u8; 32]).await.unwrap()
     }
-    debug!(count = orders.len(), "commit_orders_done");
-    acc
-}
 
-pub fn commit_fills<H: PoseidonHasher>(h: &H, fills: &[FillDraft]) -> [u8; 32] {
-    use crate::encode::encode_fill;
-    let mut acc = [0u8; 32];
-    for (i, f) in fills.iter().enumerate() {
-        let leaf = h.h_bytes(domains::FILL_LEAF, &encode_fill(f));
-        acc = h.h2(domains::FILLS_ACC, acc, leaf);
-        if i % 1024 == 0 {
-            debug!(i, total = fills.len(), "commit_fills_progress");
+    #[tokio::test]
+    async fn every_fill_proves_against_the_header() {
+        let block = block_with_fills(CommitScheme::Merkle).await;
+        assert_eq!(block.fills.len(), 3);
+        let h = HashBackend::new(block.header.hash_scheme);
+        let root = block.header.fills_commitment;
+        for f in &block.fills {
+            let proof = prove_fill(&block, f.match_id).unwrap();
+            assert!(verify_fill_inclusion(&h, root, fill_leaf(&h, f), &proof));
+            // the proof is for this fill only
+            let other = block.fills.iter().find(|o| o.match_id != f.match_id).unwrap();
+            assert!(!verify_fill_inclusion(&h, root, fill_leaf(&h, other), &proof));
         }
+        assert!(prove_fill(&block, u64::MAX).is_none());
     }
-    debug!(count = fills.len(), "commit_fills_done");
-    acc
-}
 
-pub fn commit_markets<H: PoseidonHasher>(h: &H, mkts: &[MarketParams]) -> [u8; 32] {
-    use crate::encode::encode_market;
-    let mut acc = [0u8; 32];
-    for (i, m) in mkts.iter().enumerate() {
-        let leaf = h.h_bytes(domains::MARKET_LEAF, &encode_market(m));
-        acc = h.h2(domains::MARKETS_ACC, acc, leaf);
-        if i % 256 == 0 {
-            debug!(i, total = mkts.len(), "commit_markets_progress");
-        }
+    #[tokio::test]
+    async fn hash_chain_blocks_have_no_fill_proofs() {
+        let block = block_with_fills(CommitScheme::HashChain).await;
+        assert!(!block.fills.is_empty());
+        assert!(prove_fill(&block, block.fills[0].match_id).is_none());
     }
-    debug!(count = mkts.len(), "commit_markets_done");
-    acc
 }
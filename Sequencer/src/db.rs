@@ -1,4 +1,5 @@
-// use crate::block::{Db, DbTx, BlockHeader, BlockNumber};
+// use crate::block::{Db, DbTx, BlockExport, BlockHeader, BlockNumber, MarketFault};
+// use crate::prover::{ProofArtifact, ProofStatus};
 // use crate::types::*;
 // use anyhow::Result;
 // use sqlx::{Pool, Postgres, Acquire};
@@ -29,7 +30,7 @@
 //     async fn load_active_markets(&mut self) -> Result<Vec<MarketParams>> {
 //         let rows = sqlx::query!(
 //             r#"SELECT pair_id, symbol, price_tick, size_step, notional_min, notional_max,
-//                       maker_bps, taker_bps, status
+//                       maker_bps, taker_bps, status, clearing_mode, allocation, fifo_pct, stp_mode
 //                FROM markets WHERE status IN (0,1,2)"#
 //         ).fetch_all(&mut self.conn).await?;
 
@@ -48,7 +49,20 @@
 //                     1 => MarketStatus::Paused,
 //                     2 => MarketStatus::CancelOnly,
 //                     _ => MarketStatus::Delisted
-//                 }
+//                 },
+//                 clearing: if r.clearing_mode == 1 { ClearingMode::UniformPrice } else { ClearingMode::Continuous },
+//                 allocation: match r.allocation {
+//                     1 => AllocationPolicy::ProRata,
+//                     2 => AllocationPolicy::FifoProRata { fifo_pct: r.fifo_pct as u8 },
+//                     _ => AllocationPolicy::Fifo,
+//                 },
+//                 stp: match r.stp_mode {
+//                     1 => StpMode::CancelNewest,
+//                     2 => StpMode::CancelOldest,
+//                     3 => StpMode::CancelBoth,
+//                     4 => StpMode::DecrementAndCancel,
+//                     _ => StpMode::None,
+//                 },
 //             });
 //         }
 //         Ok(out)
@@ -57,8 +71,9 @@
 //     async fn load_open_orders_snapshot(&mut self) -> Result<Vec<Order>> {
 //         let rows = sqlx::query!(
 //             r#"SELECT order_id, order_hash, pair_id, side, price_tick, amount, remaining,
-//                       time_bucket, nonce, ingest_seq
-//                FROM orders WHERE remaining > 0
+//                       time_bucket, nonce, COALESCE(queue_seq, ingest_seq) AS "ingest_seq!",
+//                       time_in_force, stop_kind, trigger_tick, stop_triggered, display_qty
+//                FROM orders WHERE remaining > 0 AND status <> 4
 //                ORDER BY pair_id, side, price_tick, ingest_seq"#
 //         ).fetch_all(&mut self.conn).await?;
 
@@ -77,21 +92,62 @@
 //                 time_bucket: r.time_bucket as u32,
 //                 nonce: r.nonce as u64,
 //                 ingest_seq: r.ingest_seq as u64,
+//                 tif: match r.time_in_force {
+//                     1 => TimeInForce::Ioc,
+//                     2 => TimeInForce::Fok,
+//                     3 => TimeInForce::PostOnly,
+//                     _ => TimeInForce::Gtc,
+//                 },
+//                 stop: match r.stop_kind {
+//                     1 | 2 => Some(StopTrigger {
+//                         kind: if r.stop_kind == 1 { StopKind::Market } else { StopKind::Limit },
+//                         trigger_tick: r.trigger_tick as u64,
+//                         triggered: r.stop_triggered,
+//                     }),
+//                     _ => None,
+//                 },
+//                 display_qty: r.display_qty.map(|d| d as u64),
 //             });
 //         }
 //         Ok(out)
 //     }
 
+//     async fn load_open_orders_after(
+//         &mut self, cursors: &std::collections::BTreeMap<PairId, u64>
+//     ) -> Result<Vec<Order>> {
+//         // Cheap enough to filter client side until the cursor set gets large;
+//         // the (pair_id, ingest_seq) index keeps the scan bounded. Cursors
+//         // track arrival order, so compare the raw ingest_seq, not queue_seq.
+//         let floor = cursors.values().copied().min().unwrap_or(0) as i64;
+//         let rows = sqlx::query!(
+//             r#"SELECT order_id, order_hash, pair_id, side, price_tick, amount, remaining,
+//                       time_bucket, nonce, COALESCE(queue_seq, ingest_seq) AS "ingest_seq!",
+//                       ingest_seq AS arrival_seq,
+//                       time_in_force, stop_kind, trigger_tick, stop_triggered, display_qty
+//                FROM orders WHERE remaining > 0 AND status <> 4 AND ingest_seq > $1
+//                ORDER BY pair_id, ingest_seq"#, floor
+//         ).fetch_all(&mut self.conn).await?;
+//
+//         let mut out = Vec::new();
+//         for r in rows {
+//             let pair_id = PairId(r.pair_id as u32);
+//             if cursors.get(&pair_id).is_some_and(|c| r.arrival_seq as u64 <= *c) {
+//                 continue;
+//             }
+//             out.push(/* same row -> Order mapping as load_open_orders_snapshot */);
+//         }
+//         Ok(out)
+//     }
+
 //     async fn load_owner_pkhash_map_for_orders(
 //         &mut self, orders: &[Order]
-//     ) -> Result<std::collections::HashMap<u64, PkHash>> {
-//         use std::collections::HashMap;
-//         if orders.is_empty() { return Ok(HashMap::new()); }
+//     ) -> Result<OwnerMap> {
+//         if orders.is_empty() { return Ok(OwnerMap::new()); }
 //         let ids: Vec<i64> = orders.iter().map(|o| o.order_id.0 as i64).collect();
 //         let rows = sqlx::query!(
 //             r#"SELECT order_id, pk_hash FROM order_owners_private WHERE order_id = ANY($1)"#, &ids
 //         ).fetch_all(&mut self.conn).await?;
-//         let mut map = HashMap::new();
+//         let mut map = OwnerMap::new();
 //         for r in rows {
 //             let mut pk = [0u8;32];
 //             pk.copy_from_slice(&r.pk_hash);
@@ -106,8 +162,8 @@
 //                 r#"INSERT INTO fills
 //                    (batch_id, match_id, pair_id, price_tick, fill_qty, time_bucket,
 //                     buyer_order_id, seller_order_id, buyer_order_hash, seller_order_hash,
-//                     buyer_pid, seller_pid, fee_bps, fill_salt)
-//                   VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14)"#,
+//                     buyer_pid, seller_pid, maker_side, maker_fee, taker_fee, fill_salt)
+//                   VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16)"#,
 //                 f.batch_id as i64,
 //                 f.match_id as i64,
 //                 f.pair_id.0 as i64,
@@ -120,13 +176,41 @@
 //                 &f.seller_order_hash[..],
 //                 &f.buyer_pid[..],
 //                 &f.seller_pid[..],
-//                 f.fee_bps as i32,
+//                 if f.maker_side == Side::Bid { 0i16 } else { 1i16 },
+//                 f.maker_fee.to_string(),
+//                 f.taker_fee.to_string(),
 //                 f.fill_salt.as_ref().map(|s| &s[..])
 //             ).execute(&mut self.conn).await?;
 //         }
 //         Ok(())
 //     }
 
+//     async fn insert_fill_legs(&mut self, legs: &[FillLeg]) -> Result<()> {
+//         for l in legs {
+//             sqlx::query!(
+//                 r#"INSERT INTO fill_legs
+//                    (batch_id, match_id, group_id, pair_id, price_tick, fill_qty, time_bucket,
+//                     order_id, order_hash, side, pid, is_maker, fee, fill_salt)
+//                   VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14)"#,
+//                 l.batch_id as i64,
+//                 l.match_id as i64,
+//                 l.group_id as i64,
+//                 l.pair_id.0 as i64,
+//                 l.price_tick as i64,
+//                 l.fill_qty as i64,
+//                 l.time_bucket as i32,
+//                 l.order_id.0 as i64,
+//                 &l.order_hash[..],
+//                 if l.side == Side::Bid { 0i16 } else { 1i16 },
+//                 &l.pid[..],
+//                 l.is_maker,
+//                 l.fee.to_string(),
+//                 l.fill_salt.as_ref().map(|s| &s[..])
+//             ).execute(&mut self.conn).await?;
+//         }
+//         Ok(())
+//     }
+
 //     async fn apply_residuals(&mut self, residuals: &[OrderResidual]) -> Result<()> {
 //         for r in residuals {
 //             let status = if r.now_filled { 1 } else { 0 };
@@ -140,12 +224,60 @@
 //         Ok(())
 //     }
 
+//     async fn reject_orders(&mut self, rejects: &[OrderReject]) -> Result<()> {
+//         for r in rejects {
+//             sqlx::query!(
+//                 r#"UPDATE orders
+//                    SET remaining = 0, status = 3, reject_reason = $1, updated_at = now()
+//                    WHERE order_id = $2"#,
+//                 format!("{:?}", r.reason), r.order_id.0 as i64
+//             ).execute(&mut self.conn).await?;
+//         }
+//         Ok(())
+//     }
+
+//     async fn mark_triggered(&mut self, triggers: &[OrderTrigger]) -> Result<()> {
+//         for t in triggers {
+//             sqlx::query!(
+//                 r#"UPDATE orders
+//                    SET stop_triggered = true, queue_seq = $1, updated_at = now()
+//                    WHERE order_id = $2"#,
+//                 t.ingest_seq as i64, t.order_id.0 as i64
+//             ).execute(&mut self.conn).await?;
+//         }
+//         Ok(())
+//     }
+
+//     async fn requeue_orders(&mut self, refills: &[OrderRefill]) -> Result<()> {
+//         for r in refills {
+//             sqlx::query!(
+//                 r#"UPDATE orders SET queue_seq = $1, updated_at = now() WHERE order_id = $2"#,
+//                 r.ingest_seq as i64, r.order_id.0 as i64
+//             ).execute(&mut self.conn).await?;
+//         }
+//         Ok(())
+//     }
+
+//     async fn quarantine_orders(&mut self, faults: &[MarketFault]) -> Result<()> {
+//         for f in faults {
+//             let ids: Vec<i64> = f.error.offending_orders().iter().map(|id| id.0 as i64).collect();
+//             if ids.is_empty() { continue; }
+//             // status 4 = quarantined; the open-order loaders skip it (remaining is kept)
+//             sqlx::query!(
+//                 r#"UPDATE orders SET status = 4, quarantine_reason = $1, updated_at = now()
+//                    WHERE order_id = ANY($2)"#,
+//                 format!("{:?}", f.error), &ids
+//             ).execute(&mut self.conn).await?;
+//         }
+//         Ok(())
+//     }
+
 //     async fn insert_batch_row(&mut self, h: &BlockHeader) -> Result<()> {
 //         sqlx::query!(
 //             r#"INSERT INTO batches
 //                (block_number, batch_id, parent_state_root, new_state_root,
-//                 markets_root, orders_commitment, fills_commitment, timestamp_ms)
-//                VALUES ($1,$2,$3,$4,$5,$6,$7,$8)"#,
+//                 markets_root, orders_commitment, fills_commitment, timestamp_ms, hash_scheme, commit_scheme)
+//                VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10)"#,
 //             h.block_number.0 as i64,
 //             h.batch_id.0 as i64,
 //             &h.parent_state_root[..],
@@ -153,7 +285,54 @@
 //             &h.markets_root[..],
 //             &h.orders_commitment[..],
 //             &h.fills_commitment[..],
-//             h.timestamp_ms as i64
+//             h.timestamp_ms as i64,
+//             h.hash_scheme.id() as i16,
+//             h.commit_scheme.id() as i16
+//         ).execute(&mut self.conn).await?;
+//         Ok(())
+//     }
+
+//     async fn insert_block_export(&mut self, block_num: BlockNumber, export: &BlockExport) -> Result<()> {
+//         sqlx::query!(
+//             r#"INSERT INTO block_inputs (block_number, export) VALUES ($1, $2::text::jsonb)"#,
+//             block_num.0 as i64, serde_json::to_string(export)?
+//         ).execute(&mut self.conn).await?;
+//         Ok(())
+//     }
+
+//     async fn load_block_export(&mut self, block_num: BlockNumber) -> Result<Option<BlockExport>> {
+//         let row = sqlx::query!(
+//             r#"SELECT export::text AS "export!" FROM block_inputs WHERE block_number = $1"#,
+//             block_num.0 as i64
+//         ).fetch_optional(&mut self.conn).await?;
+//         row.map(|r| serde_json::from_str(&r.export)).transpose().map_err(Into::into)
+//     }
+
+//     async fn load_unproven_blocks(&mut self) -> Result<Vec<(BlockNumber, u32)>> {
+//         let rows = sqlx::query!(
+//             r#"SELECT block_number, proof_attempts FROM batches
+//                WHERE proof_status IN (0, 1) ORDER BY block_number"#
+//         ).fetch_all(&mut self.conn).await?;
+//         Ok(rows.into_iter().map(|r| (BlockNumber(r.block_number as u64), r.proof_attempts as u32)).collect())
+//     }
+
+//     async fn set_proof_status(
+//         &mut self, block_num: BlockNumber, status: ProofStatus, attempts: u32, error: Option<&str>,
+//     ) -> Result<()> {
+//         sqlx::query!(
+//             r#"UPDATE batches SET proof_status = $2, proof_attempts = $3, proof_error = $4
+//                WHERE block_number = $1"#,
+//             block_num.0 as i64, status.id(), attempts as i32, error
+//         ).execute(&mut self.conn).await?;
+//         Ok(())
+//     }
+
+//     async fn store_proof(&mut self, block_num: BlockNumber, a: &ProofArtifact) -> Result<()> {
+//         sqlx::query!(
+//             r#"UPDATE batches SET proof_status = $2, proof_error = NULL,
+//                       proof = $3, proof_public_values = $4, proof_vkey = $5, proved_at = now()
+//                WHERE block_number = $1"#,
+//             block_num.0 as i64, ProofStatus::Proved.id(), &a.proof, &a.public_values, &a.vkey
 //         ).execute(&mut self.conn).await?;
 //         Ok(())
 //     }
@@ -1,10 +1,13 @@
-pub mod commit;     // Poseidon-like trait + commit helpers
+pub mod commit;     // commitments + fill inclusion proofs
 pub mod encode;     // canonical byte encoders for commitments
 pub mod block;      // block structs + builder
 pub mod db;         // database traits + Postgres impl
 pub mod match_loop;
 pub mod mempool;
 pub mod state;
+pub mod prover;     // background proving of built blocks
+#[cfg(test)]
+mod mem_db;         // in-memory Db for tests
 
 pub use block::{Block, BlockHeader, BlockNumber, BatchId, BlockBuilder};
 pub use engine::types::*;
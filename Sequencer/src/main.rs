@@ -73,11 +73,23 @@ pub struct BlockHeaderDTO {
     pub orders_commitment: String,   // hex
     pub fills_commitment: String,    // hex
     pub timestamp_ms: u64,
+    pub hash_scheme: String,         // e.g. "poseidon-bn254"
+    pub commit_scheme: String,       // "merkle" or "hash-chain"
+    pub proof_status: String,        // pending | proving | proved | failed
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProofDTO {
+    pub block_number: u64,
+    pub status: String,              // prover::ProofStatus name
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub vkey: Option<String>,        // hex, once proved
+    pub proof: Option<String>,       // hex, once proved
 }
 
 #[derive(Deserialize)]
 struct JsonRpcReq {
-    jsonrpc: String,
     method: String,
     #[serde(default)]
     params: Value,      // object or array
@@ -108,6 +120,8 @@ pub struct MockStore {
     pub fills: Vec<FillDTO>,
     pub orders: Vec<OrderDTO>,
     pub blocks: HashMap<u64, BlockHeaderDTO>,
+    pub proofs: HashMap<u64, ProofDTO>,
+    pub hash_scheme: String,
 }
 
 #[derive(Clone)]
@@ -115,7 +129,7 @@ pub struct AppState {
     pub store: Arc<RwLock<MockStore>>,
 }
 
-fn seed_mock() -> MockStore {
+fn seed_mock(hash_scheme: engine::HashScheme) -> MockStore {
     debug!(markets = 2, orderbooks = 2, fills = 2, blocks = 1, "seeding_mock_data");
     let markets = vec![
         MarketDTO { pair_id: 1, symbol: "POL-ETH".into(), price_tick: 1, size_step: 1, maker_bps: 0, taker_bps: 5, status: 0 },
@@ -148,6 +162,14 @@ fn seed_mock() -> MockStore {
         orders_commitment:  "33".repeat(32),
         fills_commitment:   "44".repeat(32),
         timestamp_ms: 1_700_000_000_000,
+        hash_scheme: hash_scheme.to_string(),
+        commit_scheme: "merkle".into(),
+        proof_status: "proved".into(),
+    });
+    let mut proofs = HashMap::new();
+    proofs.insert(1, ProofDTO {
+        block_number: 1, status: "proved".into(), attempts: 1, error: None,
+        vkey: Some("55".repeat(32)), proof: Some(String::new()), // the SP1 mock prover's empty proof
     });
 
     // seed some mock orders (between 12 and 20)
@@ -158,7 +180,7 @@ fn seed_mock() -> MockStore {
             orders.push(OrderDTO {
                 order_id: ingest_seq,
                 pair_id: pair,
-                side: if ingest_seq % 2 == 0 { 0 } else { 1 },
+                side: if ingest_seq.is_multiple_of(2) { 0 } else { 1 },
                 price_tick: if pair == 1 { 100 + (ingest_seq % 5) } else { 2000 + (ingest_seq % 5) } ,
                 amount: 50 + (ingest_seq % 25),
                 remaining: 50 + (ingest_seq % 25),
@@ -169,7 +191,7 @@ fn seed_mock() -> MockStore {
         }
     }
 
-    MockStore { markets, orderbooks, fills, orders, blocks }
+    MockStore { markets, orderbooks, fills, orders, blocks, proofs, hash_scheme: hash_scheme.to_string() }
 }
 
 #[derive(Deserialize, Debug)]
@@ -203,7 +225,7 @@ async fn get_fills(State(state): State<AppState>, Query(q): Query<FillsQuery>) -
 }
 
 #[tracing::instrument(level="info", skip(state), fields(block_number = n))]
-async fn get_block(State(state): State<AppState>, Path(n): Path<u64>) -> axum::response::Result<Json<BlockHeaderDTO>> {
+async fn get_block(State(state): State<AppState>, Path(n): Path<u64>) -> Result<Json<BlockHeaderDTO>, axum::http::StatusCode> {
     match state.store.read().await.blocks.get(&n).cloned() {
         Some(block) => {
             debug!("block_found");
@@ -211,21 +233,31 @@ async fn get_block(State(state): State<AppState>, Path(n): Path<u64>) -> axum::r
         },
         None => {
             warn!("block_not_found");
-            Err(axum::http::StatusCode::NOT_FOUND.into())
+            Err(axum::http::StatusCode::NOT_FOUND)
         },
     }
 }
 
-#[derive(Deserialize)]
-struct SubmitOrderReq {
-    pair_id: u32, side: u8, price_tick: u64, amount: u64,
-    time_bucket: u32, nonce: u64, order_hash: String, pk_hash: String
+#[tracing::instrument(level="info", skip(state), fields(block_number = n))]
+async fn get_block_proof(State(state): State<AppState>, Path(n): Path<u64>) -> Result<Json<ProofDTO>, axum::http::StatusCode> {
+    match state.store.read().await.proofs.get(&n).cloned() {
+        Some(proof) => {
+            debug!(status = %proof.status, "proof_found");
+            Ok(Json(proof))
+        },
+        None => {
+            warn!("proof_not_found");
+            Err(axum::http::StatusCode::NOT_FOUND)
+        },
+    }
 }
+
 #[derive(Serialize, Debug)]
 struct SubmitOrderRes { order_id: u64, ingest_seq: u64 }
 
+// mock: the order body is not read
 #[tracing::instrument(level="info", skip(_req))]
-async fn post_order(Json(_req): Json<SubmitOrderReq>) -> Json<SubmitOrderRes> {
+async fn post_order(Json(_req): Json<Value>) -> Json<SubmitOrderRes> {
     Json(SubmitOrderRes { order_id: 42, ingest_seq: 7 })
 }
 
@@ -255,6 +287,14 @@ async fn rpc_handler(State(state): State<AppState>, Json(req): Json<JsonRpcReq>)
                 None => Json(mk_err(-32602, "not found")),
             }
         }
+        "batch_getProofStatus" => {
+            #[derive(Deserialize)] struct P { block_number: u64 }
+            let p: P = serde_json::from_value(req.params.clone()).unwrap_or(P{ block_number: 1 });
+            match state.store.read().await.proofs.get(&p.block_number).cloned() {
+                Some(pr) => Json(mk_ok(serde_json::to_value(pr).unwrap())),
+                None => Json(mk_err(-32602, "not found")),
+            }
+        }
         "fills_getSince" => {
             #[derive(Deserialize, Default)] struct P { batch_id: Option<u64>, pair_id: Option<u32>, limit: Option<usize> }
             let p: P = serde_json::from_value(req.params.clone()).unwrap_or_default();
@@ -329,6 +369,7 @@ async fn start_seeder(state: AppState) {
 
         // --- Add a new block header ---
         let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let hash_scheme = store.hash_scheme.clone();
         store.blocks.insert(block_number, BlockHeaderDTO {
             block_number,
             batch_id: block_number,
@@ -338,7 +379,26 @@ async fn start_seeder(state: AppState) {
             orders_commitment: format!("{:064x}", rng.gen::<u128>()),
             fills_commitment: format!("{:064x}", rng.gen::<u128>()),
             timestamp_ms: ts,
+            hash_scheme,
+            commit_scheme: "merkle".into(),
+            proof_status: "pending".into(),
+        });
+        store.proofs.insert(block_number, ProofDTO {
+            block_number, status: "pending".into(), attempts: 0, error: None, vkey: None, proof: None,
         });
+        // blocks move through the mock prover a tick at a time
+        for (n, status) in [(block_number - 1, "proving"), (block_number.saturating_sub(2), "proved")] {
+            let Some(p) = store.proofs.get_mut(&n) else { continue };
+            if p.status == "proved" { continue; }
+            p.status = status.into();
+            if status == "proving" {
+                p.attempts += 1;
+            } else {
+                p.vkey = Some("55".repeat(32));
+                p.proof = Some(String::new());
+            }
+            if let Some(b) = store.blocks.get_mut(&n) { b.proof_status = status.into(); }
+        }
         block_number += 1;
     }
 }
@@ -357,7 +417,14 @@ async fn main() -> anyhow::Result<()> {
 
     debug!(markets = 2, orderbooks = 2, fills = 2, blocks = 1, "seeding_mock_data");
 
-    let state = AppState { store: Arc::new(RwLock::new(seed_mock())) };
+    // blake3 | poseidon-bn254 | sha256; stamped into every block header
+    let hash_scheme: engine::HashScheme = std::env::var("HASH_SCHEME")
+        .unwrap_or_else(|_| "poseidon-bn254".into())
+        .parse()
+        .map_err(|_| anyhow::anyhow!("HASH_SCHEME must be blake3, poseidon-bn254 or sha256"))?;
+    info!(%hash_scheme, "hash_backend");
+
+    let state = AppState { store: Arc::new(RwLock::new(seed_mock(hash_scheme))) };
 
     tokio::spawn(start_seeder(state.clone()));
 
@@ -369,6 +436,7 @@ async fn main() -> anyhow::Result<()> {
         .route("/v1/orderbook/:pair_id", get(get_orderbook))
         .route("/v1/fills", get(get_fills))
         .route("/v1/blocks/:block_number", get(get_block))
+        .route("/v1/blocks/:block_number/proof", get(get_block_proof))
         .route("/v1/orders", post(post_order))
         .route("/rpc", post(rpc_handler))
         .with_state(state)
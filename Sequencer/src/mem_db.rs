@@ -0,0 +1,260 @@
+//! In-memory `Db` for tests. A transaction works on a copy of the tables
+//! and writes it back on `commit`, so a failed block leaves nothing behind.
+//! `market` and `order` build the fixtures the tests share.
+use crate::block::{BlockExport, BlockHeader, BlockNumber, Db, DbTx, MarketFault};
+use crate::prover::{ProofArtifact, ProofStatus};
+use engine::types::*;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+pub struct Tables {
+    pub markets: Vec<MarketParams>,
+    pub orders: BTreeMap<OrderId, Order>, // ingest_seq is the current queue position
+    pub arrival: BTreeMap<OrderId, u64>,  // original ingest_seq, for cursors
+    pub owners: OwnerMap,
+    pub quarantined: BTreeMap<OrderId, String>, // with the fault
+    pub fills: Vec<FillDraft>,
+    pub legs: Vec<FillLeg>,
+    pub headers: BTreeMap<u64, BlockHeader>,
+    pub exports: BTreeMap<u64, BlockExport>,
+    pub proofs: BTreeMap<u64, (ProofStatus, u32, Option<String>)>,
+    pub artifacts: BTreeMap<u64, ProofArtifact>,
+    pub fail: Option<&'static str>, // DbTx method that errors
+}
+
+#[derive(Clone, Default)]
+pub struct MemDb(Arc<Mutex<Tables>>);
+
+impl MemDb {
+    pub fn new(markets: Vec<MarketParams>) -> Self {
+        Self(Arc::new(Mutex::new(Tables { markets, ..Default::default() })))
+    }
+
+    pub fn with<T>(&self, f: impl FnOnce(&mut Tables) -> T) -> T {
+        f(&mut self.0.lock().unwrap())
+    }
+
+    /// Add an order, owned by a key derived from its id.
+    pub fn add(&self, o: Order) {
+        self.with(|t| {
+            let mut pk = [0u8; 32];
+            pk[24..].copy_from_slice(&o.order_id.0.to_be_bytes());
+            t.owners.insert(o.order_id.0, pk);
+            t.arrival.insert(o.order_id, o.ingest_seq);
+            t.orders.insert(o.order_id, o);
+        })
+    }
+
+    pub fn cancel(&self, id: OrderId) {
+        self.with(|t| t.orders.remove(&id));
+    }
+
+    pub fn amend(&self, id: OrderId, price_tick: u64, remaining: u64, requeue_seq: u64) {
+        self.with(|t| match t.orders[&id].amended(price_tick, remaining, requeue_seq) {
+            Some(o) => t.orders.insert(id, o),
+            None => t.orders.remove(&id),
+        });
+    }
+
+    /// Make `op` fail in every transaction from now on.
+    pub fn fail_on(&self, op: Option<&'static str>) {
+        self.with(|t| t.fail = op);
+    }
+}
+
+/// An Active market with unit tick and step and no fees.
+pub fn market(pair: u32) -> MarketParams {
+    MarketParams {
+        pair_id: PairId(pair),
+        price_tick: 1, size_step: 1,
+        notional_min: 0, notional_max: u128::MAX,
+        maker_bps: 0, taker_bps: 0,
+        status: MarketStatus::Active,
+        clearing: ClearingMode::default(),
+        allocation: AllocationPolicy::default(),
+        stp: StpMode::default(),
+    }
+}
+
+/// A plain GTC order on pair 1.
+pub fn order(id: u64, side: Side, price_tick: u64, qty: u64, seq: u64) -> Order {
+    Order {
+        order_id: OrderId(id), order_hash: [id as u8; 32], pair_id: PairId(1), side, price_tick,
+        amount: qty, remaining: qty, time_bucket: 0, nonce: id, ingest_seq: seq,
+        tif: TimeInForce::Gtc, stop: None, display_qty: None,
+    }
+}
+
+pub struct MemTx {
+    db: Arc<Mutex<Tables>>,
+    t: Tables,
+}
+
+impl MemTx {
+    fn check(&self, op: &str) -> anyhow::Result<()> {
+        match self.t.fail {
+            Some(f) if f == op => anyhow::bail!("{op} failed"),
+            _ => Ok(()),
+        }
+    }
+
+    fn each(&mut self, ids: impl IntoIterator<Item = OrderId>, mut f: impl FnMut(&mut Order)) {
+        for id in ids {
+            if let Some(o) = self.t.orders.get_mut(&id) {
+                f(o);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Db for MemDb {
+    type Tx<'a> = MemTx;
+
+    async fn begin_repeatable_read(&self) -> anyhow::Result<MemTx> {
+        Ok(MemTx { db: self.0.clone(), t: self.0.lock().unwrap().clone() })
+    }
+}
+
+#[async_trait::async_trait]
+impl DbTx for MemTx {
+    async fn load_active_markets(&mut self) -> anyhow::Result<Vec<MarketParams>> {
+        self.check("load_active_markets")?;
+        Ok(self.t.markets.clone())
+    }
+
+    async fn load_open_orders_snapshot(&mut self) -> anyhow::Result<Vec<Order>> {
+        self.check("load_open_orders_snapshot")?;
+        let t = &self.t;
+        Ok(t.orders.values().filter(|o| o.is_open() && !t.quarantined.contains_key(&o.order_id)).cloned().collect())
+    }
+
+    async fn load_open_orders_after(&mut self, cursors: &BTreeMap<PairId, u64>) -> anyhow::Result<Vec<Order>> {
+        self.check("load_open_orders_after")?;
+        let t = &self.t;
+        Ok(t.orders.values()
+            .filter(|o| o.is_open() && !t.quarantined.contains_key(&o.order_id))
+            .filter(|o| cursors.get(&o.pair_id).is_none_or(|c| t.arrival[&o.order_id] > *c))
+            .cloned()
+            .collect())
+    }
+
+    async fn load_owner_pkhash_map_for_orders(&mut self, orders: &[Order]) -> anyhow::Result<OwnerMap> {
+        self.check("load_owner_pkhash_map_for_orders")?;
+        Ok(orders.iter().filter_map(|o| self.t.owners.get(&o.order_id.0).map(|pk| (o.order_id.0, *pk))).collect())
+    }
+
+    async fn insert_fills(&mut self, fills: &[FillDraft]) -> anyhow::Result<()> {
+        self.check("insert_fills")?;
+        self.t.fills.extend_from_slice(fills);
+        Ok(())
+    }
+
+    async fn insert_fill_legs(&mut self, legs: &[FillLeg]) -> anyhow::Result<()> {
+        self.check("insert_fill_legs")?;
+        self.t.legs.extend_from_slice(legs);
+        Ok(())
+    }
+
+    async fn apply_residuals(&mut self, residuals: &[OrderResidual]) -> anyhow::Result<()> {
+        self.check("apply_residuals")?;
+        for r in residuals {
+            self.each([r.order_id], |o| o.remaining = r.remaining_after);
+        }
+        Ok(())
+    }
+
+    async fn reject_orders(&mut self, rejects: &[OrderReject]) -> anyhow::Result<()> {
+        self.check("reject_orders")?;
+        for r in rejects {
+            self.t.orders.remove(&r.order_id);
+        }
+        Ok(())
+    }
+
+    async fn mark_triggered(&mut self, triggers: &[OrderTrigger]) -> anyhow::Result<()> {
+        self.check("mark_triggered")?;
+        for t in triggers {
+            self.each([t.order_id], |o| {
+                if let Some(stop) = o.stop.as_mut() {
+                    stop.triggered = true;
+                    if stop.kind == StopKind::Market {
+                        o.tif = TimeInForce::Ioc;
+                    }
+                }
+                o.ingest_seq = t.ingest_seq;
+            });
+        }
+        Ok(())
+    }
+
+    async fn requeue_orders(&mut self, refills: &[OrderRefill]) -> anyhow::Result<()> {
+        self.check("requeue_orders")?;
+        for r in refills {
+            self.each([r.order_id], |o| o.ingest_seq = r.ingest_seq);
+        }
+        Ok(())
+    }
+
+    async fn quarantine_orders(&mut self, faults: &[MarketFault]) -> anyhow::Result<()> {
+        self.check("quarantine_orders")?;
+        for f in faults {
+            let reason = format!("{:?}", f.error);
+            self.t.quarantined.extend(f.error.offending_orders().iter().map(|id| (*id, reason.clone())));
+        }
+        Ok(())
+    }
+
+    async fn insert_batch_row(&mut self, header: &BlockHeader) -> anyhow::Result<()> {
+        self.check("insert_batch_row")?;
+        self.t.headers.insert(header.block_number.0, header.clone());
+        Ok(())
+    }
+
+    async fn insert_block_export(&mut self, block_number: BlockNumber, export: &BlockExport) -> anyhow::Result<()> {
+        self.check("insert_block_export")?;
+        self.t.exports.insert(block_number.0, export.clone());
+        Ok(())
+    }
+
+    async fn link_fills_to_batch(&mut self, _block_num: BlockNumber, _fills: &[FillDraft]) -> anyhow::Result<()> {
+        self.check("link_fills_to_batch")
+    }
+
+    async fn load_block_export(&mut self, block_number: BlockNumber) -> anyhow::Result<Option<BlockExport>> {
+        self.check("load_block_export")?;
+        Ok(self.t.exports.get(&block_number.0).cloned())
+    }
+
+    async fn load_unproven_blocks(&mut self) -> anyhow::Result<Vec<(BlockNumber, u32)>> {
+        self.check("load_unproven_blocks")?;
+        let proof = |n: &u64| self.t.proofs.get(n).map_or((ProofStatus::Pending, 0), |p| (p.0, p.1));
+        Ok(self.t.headers.keys()
+            .filter(|n| matches!(proof(n).0, ProofStatus::Pending | ProofStatus::Proving))
+            .map(|n| (BlockNumber(*n), proof(n).1))
+            .collect())
+    }
+
+    async fn set_proof_status(
+        &mut self, block_number: BlockNumber, status: ProofStatus, attempts: u32, error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.check("set_proof_status")?;
+        self.t.proofs.insert(block_number.0, (status, attempts, error.map(str::to_owned)));
+        Ok(())
+    }
+
+    async fn store_proof(&mut self, block_number: BlockNumber, artifact: &ProofArtifact) -> anyhow::Result<()> {
+        self.check("store_proof")?;
+        let attempts = self.t.proofs.get(&block_number.0).map_or(0, |p| p.1);
+        self.t.proofs.insert(block_number.0, (ProofStatus::Proved, attempts, None));
+        self.t.artifacts.insert(block_number.0, artifact.clone());
+        Ok(())
+    }
+
+    async fn commit(self) -> anyhow::Result<()> {
+        self.check("commit")?;
+        *self.db.lock().unwrap() = self.t;
+        Ok(())
+    }
+}
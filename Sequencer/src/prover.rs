@@ -0,0 +1,479 @@
+//! Proves built blocks in the background. `ProverService::spawn` starts
+//! worker tasks fed from a queue of block numbers; each loads the block's
+//! stored `BlockExport`, hands it to a `Prover` and records the outcome on
+//! the block's `batches` row. Blocks left pending or proving by a previous
+//! run are queued again at startup.
+use crate::block::{BlockExport, BlockNumber, Db, DbTx};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tracing::{info, debug, warn, instrument};
+
+/// Where a block's proof stands. Stored as `batches.proof_status`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofStatus {
+    #[default]
+    Pending,
+    Proving,
+    Proved,
+    Failed, // gave up after `ProverOptions::max_attempts`, or the proof disagreed with the header
+}
+
+impl ProofStatus {
+    pub fn id(self) -> i16 {
+        match self {
+            ProofStatus::Pending => 0,
+            ProofStatus::Proving => 1,
+            ProofStatus::Proved => 2,
+            ProofStatus::Failed => 3,
+        }
+    }
+
+    pub fn from_id(id: i16) -> Option<Self> {
+        [ProofStatus::Pending, ProofStatus::Proving, ProofStatus::Proved, ProofStatus::Failed]
+            .into_iter()
+            .find(|s| s.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProofStatus::Pending => "pending",
+            ProofStatus::Proving => "proving",
+            ProofStatus::Proved => "proved",
+            ProofStatus::Failed => "failed",
+        }
+    }
+}
+
+/// A finished proof, as persisted.
+#[derive(Clone, Debug)]
+pub struct ProofArtifact {
+    pub proof: Vec<u8>,         // serialized SP1ProofWithPublicValues
+    pub public_values: Vec<u8>, // ABI BlockCommitment the guest committed
+    pub vkey: String,           // bytes32 of the guest verifying key
+}
+
+#[async_trait::async_trait]
+#[allow(clippy::double_must_use)]
+pub trait Prover: Send + Sync + 'static {
+    async fn prove(&self, export: &BlockExport) -> anyhow::Result<ProofArtifact>;
+}
+
+/// `SP1_PROVER` for the prover client: `mock` executes the guest and
+/// returns an empty proof, for local runs.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Sp1Mode {
+    #[default]
+    Mock,
+    Cpu,
+    Cuda,
+    Network,
+}
+
+impl Sp1Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sp1Mode::Mock => "mock",
+            Sp1Mode::Cpu => "cpu",
+            Sp1Mode::Cuda => "cuda",
+            Sp1Mode::Network => "network",
+        }
+    }
+}
+
+impl std::str::FromStr for Sp1Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        [Sp1Mode::Mock, Sp1Mode::Cpu, Sp1Mode::Cuda, Sp1Mode::Network]
+            .into_iter()
+            .find(|m| m.name() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown SP1 prover mode {s:?}"))
+    }
+}
+
+/// Runs the `prove-block` binary (`zkvm/script`) on each block. The SP1
+/// SDK stays out of the sequencer's build; only the binary links it.
+pub struct Sp1Prover {
+    pub bin: PathBuf,      // prove-block
+    pub work_dir: PathBuf, // block files and artifacts go here
+    pub mode: Sp1Mode,
+    pub system: String,    // core | compressed | plonk | groth16
+}
+
+impl Sp1Prover {
+    pub fn new(bin: impl Into<PathBuf>, work_dir: impl Into<PathBuf>) -> Self {
+        Self { bin: bin.into(), work_dir: work_dir.into(), mode: Sp1Mode::default(), system: "compressed".into() }
+    }
+
+    pub fn mode(mut self, mode: Sp1Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.system = system.into();
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Prover for Sp1Prover {
+    async fn prove(&self, export: &BlockExport) -> anyhow::Result<ProofArtifact> {
+        let n = export.input.block_number;
+        tokio::fs::create_dir_all(&self.work_dir).await?;
+        let file = self.work_dir.join(format!("block-{n}.json"));
+        tokio::fs::write(&file, serde_json::to_vec(export)?).await?;
+
+        let out = tokio::process::Command::new(&self.bin)
+            .arg("--file").arg(&file)
+            .args(["--prove", "--system", &self.system])
+            .env("SP1_PROVER", self.mode.name())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        if !out.status.success() {
+            anyhow::bail!("prove-block exited with {}: {}", out.status, String::from_utf8_lossy(&out.stderr).trim());
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Public { vkey: String, public_values: String }
+        let public: Public = serde_json::from_slice(&tokio::fs::read(self.work_dir.join(format!("block-{n}.public.json"))).await?)?;
+        Ok(ProofArtifact {
+            proof: tokio::fs::read(self.work_dir.join(format!("block-{n}.proof.bin"))).await?,
+            public_values: hex::decode(public.public_values.trim_start_matches("0x"))?,
+            vkey: public.vkey,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProverOptions {
+    pub workers: usize,
+    pub max_attempts: u32,
+    pub retry_backoff: Duration, // doubled after each failed attempt
+    pub max_backoff: Duration,   // up to this
+}
+
+impl Default for ProverOptions {
+    fn default() -> Self {
+        Self { workers: 1, max_attempts: 3, retry_backoff: Duration::from_secs(5), max_backoff: Duration::from_secs(600) }
+    }
+}
+
+/// In-memory view of a block's proof; the `batches` row is authoritative.
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct ProofState {
+    pub status: ProofStatus,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+/// Queue side of a running `ProverService`.
+#[derive(Clone)]
+pub struct ProverHandle {
+    queue: mpsc::UnboundedSender<BlockNumber>,
+    states: Arc<RwLock<BTreeMap<u64, ProofState>>>,
+}
+
+impl ProverHandle {
+    /// Queue a block whose export has been persisted (i.e. after
+    /// `BlockBuilder::build_block` returned it).
+    pub async fn submit(&self, block_number: BlockNumber) -> anyhow::Result<()> {
+        self.states.write().await.entry(block_number.0).or_default();
+        self.queue.send(block_number).map_err(|_| anyhow::anyhow!("prover service stopped"))
+    }
+
+    /// State of blocks submitted since startup; older blocks are on the
+    /// `batches` row.
+    pub async fn state(&self, block_number: BlockNumber) -> Option<ProofState> {
+        self.states.read().await.get(&block_number.0).cloned()
+    }
+}
+
+pub struct ProverService<D: Db, P: Prover> {
+    db: Arc<D>,
+    prover: Arc<P>,
+    opts: ProverOptions,
+    queue: Mutex<mpsc::UnboundedReceiver<BlockNumber>>,
+    retries: mpsc::UnboundedSender<BlockNumber>,
+    states: Arc<RwLock<BTreeMap<u64, ProofState>>>,
+}
+
+impl<D: Db, P: Prover> ProverService<D, P> {
+    /// Start `opts.workers` workers and queue every block a previous run
+    /// left unproven, counting the attempts it already made. Blocks out of
+    /// attempts are failed instead.
+    pub async fn spawn(db: Arc<D>, prover: Arc<P>, opts: ProverOptions) -> anyhow::Result<ProverHandle> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let handle = ProverHandle { queue: tx.clone(), states: Arc::default() };
+        let svc = Arc::new(Self {
+            db, prover, opts,
+            queue: Mutex::new(rx),
+            retries: tx,
+            states: handle.states.clone(),
+        });
+
+        let mut dbtx = svc.db.begin_repeatable_read().await?;
+        let unproven = dbtx.load_unproven_blocks().await?;
+        let mut queued = Vec::with_capacity(unproven.len());
+        for (n, attempts) in unproven {
+            let state = if attempts >= svc.opts.max_attempts {
+                let error = format!("gave up after {attempts} attempts");
+                warn!(block_number = n.0, attempts, "proof_failed");
+                dbtx.set_proof_status(n, ProofStatus::Failed, attempts, Some(&error)).await?;
+                ProofState { status: ProofStatus::Failed, attempts, error: Some(error) }
+            } else {
+                queued.push(n);
+                ProofState { status: ProofStatus::Pending, attempts, error: None }
+            };
+            handle.states.write().await.insert(n.0, state);
+        }
+        dbtx.commit().await?;
+        info!(unproven = queued.len(), workers = svc.opts.workers, "prover_started");
+        for n in queued {
+            handle.submit(n).await?;
+        }
+
+        for worker in 0..svc.opts.workers.max(1) {
+            tokio::spawn(svc.clone().run(worker));
+        }
+        Ok(handle)
+    }
+
+    async fn run(self: Arc<Self>, worker: usize) {
+        loop {
+            let Some(n) = self.queue.lock().await.recv().await else { break };
+            if let Err(e) = self.prove_one(n).await {
+                // bookkeeping failed, not the proof; leave it for the next start
+                warn!(worker, block_number = n.0, error = %e, "prover_bookkeeping_failed");
+            }
+        }
+        debug!(worker, "prover_worker_stopped");
+    }
+
+    #[instrument(level = "info", skip(self), fields(block_number = n.0))]
+    async fn prove_one(&self, n: BlockNumber) -> anyhow::Result<()> {
+        let attempts = {
+            let mut states = self.states.write().await;
+            let s = states.entry(n.0).or_default();
+            s.status = ProofStatus::Proving;
+            s.attempts += 1;
+            s.attempts
+        };
+        let mut tx = self.db.begin_repeatable_read().await?;
+        let export = tx.load_block_export(n).await?;
+        tx.set_proof_status(n, ProofStatus::Proving, attempts, None).await?;
+        tx.commit().await?;
+        let Some(export) = export else {
+            return self.finish(n, Err(anyhow::anyhow!("no stored block input")), attempts, false).await;
+        };
+
+        // a proof of something else will not get better on retry
+        match self.prover.prove(&export).await {
+            Ok(a) if a.public_values != export.public_values => {
+                let e = anyhow::anyhow!("proof public values differ from the stored header");
+                self.finish(n, Err(e), attempts, false).await
+            }
+            result => self.finish(n, result, attempts, true).await,
+        }
+    }
+
+    async fn finish(
+        &self, n: BlockNumber, result: anyhow::Result<ProofArtifact>, attempts: u32, retryable: bool,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin_repeatable_read().await?;
+        let state = match result {
+            Ok(artifact) => {
+                tx.store_proof(n, &artifact).await?;
+                info!(attempts, proof_len = artifact.proof.len(), "block_proved");
+                ProofState { status: ProofStatus::Proved, attempts, error: None }
+            }
+            Err(e) if retryable && attempts < self.opts.max_attempts => {
+                let backoff = 2u32.checked_pow(attempts - 1)
+                    .and_then(|m| self.opts.retry_backoff.checked_mul(m))
+                    .map_or(self.opts.max_backoff, |b| b.min(self.opts.max_backoff));
+                warn!(attempts, error = %e, ?backoff, "proof_failed_retrying");
+                tx.set_proof_status(n, ProofStatus::Pending, attempts, Some(&e.to_string())).await?;
+                let retries = self.retries.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(backoff).await;
+                    let _ = retries.send(n);
+                });
+                ProofState { status: ProofStatus::Pending, attempts, error: Some(e.to_string()) }
+            }
+            Err(e) => {
+                warn!(attempts, error = %e, "proof_failed");
+                tx.set_proof_status(n, ProofStatus::Failed, attempts, Some(&e.to_string())).await?;
+                ProofState { status: ProofStatus::Failed, attempts, error: Some(e.to_string()) }
+            }
+        };
+        tx.commit().await?;
+        self.states.write().await.insert(n.0, state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BatchId, BlockBuilder};
+    use crate::commit::HashScheme;
+    use crate::mem_db::MemDb;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails its first `fail_first` calls, and notes the stored status of
+    /// every block it is asked to prove.
+    struct MockProver {
+        db: MemDb,
+        fail_first: u32,
+        wrong_values: bool,
+        calls: AtomicU32,
+        seen: std::sync::Mutex<Vec<ProofStatus>>,
+    }
+
+    impl MockProver {
+        fn new(db: &MemDb, fail_first: u32, wrong_values: bool) -> Arc<Self> {
+            Arc::new(Self { db: db.clone(), fail_first, wrong_values, calls: AtomicU32::new(0), seen: Default::default() })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Prover for MockProver {
+        async fn prove(&self, export: &BlockExport) -> anyhow::Result<ProofArtifact> {
+            let status = self.db.with(|t| t.proofs.get(&export.input.block_number).map(|p| p.0));
+            self.seen.lock().unwrap().extend(status);
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.fail_first {
+                anyhow::bail!("prover unavailable");
+            }
+            let mut public_values = export.public_values.clone();
+            if self.wrong_values {
+                public_values[31] ^= 1;
+            }
+            Ok(ProofArtifact { proof: vec![1], public_values, vkey: "0x01".into() })
+        }
+    }
+
+    async fn db_with_blocks(n: u64) -> MemDb {
+        let db = MemDb::new(Vec::new());
+        let b = BlockBuilder::new(db.clone(), HashScheme::Blake3);
+        for i in 1..=n {
+            let parent = b.state_root().await;
+            b.build_block(BlockNumber(i), BatchId(i), parent, 0, false, |_, _| [0u8; 32]).await.unwrap();
+        }
+        db
+    }
+
+    fn opts(max_attempts: u32) -> ProverOptions {
+        ProverOptions { workers: 1, max_attempts, retry_backoff: Duration::from_millis(1), max_backoff: Duration::from_millis(4) }
+    }
+
+    /// Wait until the block is proved or failed.
+    async fn settled(h: &ProverHandle, n: u64) -> ProofState {
+        for _ in 0..1000 {
+            if let Some(s) = h.state(BlockNumber(n)).await {
+                if matches!(s.status, ProofStatus::Proved | ProofStatus::Failed) {
+                    return s;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        panic!("block {n} never settled");
+    }
+
+    #[tokio::test]
+    async fn a_failed_attempt_is_retried_until_proved() {
+        let db = db_with_blocks(1).await;
+        let prover = MockProver::new(&db, 1, false);
+        let h = ProverService::spawn(Arc::new(db.clone()), prover.clone(), opts(3)).await.unwrap();
+
+        let s = settled(&h, 1).await;
+        assert_eq!((s.status, s.attempts, s.error), (ProofStatus::Proved, 2, None));
+        // each attempt is marked proving before the prover runs
+        assert_eq!(*prover.seen.lock().unwrap(), [ProofStatus::Proving, ProofStatus::Proving]);
+        assert_eq!(db.with(|t| t.proofs[&1].clone()), (ProofStatus::Proved, 2, None));
+        assert!(db.with(|t| t.artifacts.contains_key(&1)));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let db = db_with_blocks(1).await;
+        let prover = MockProver::new(&db, u32::MAX, false);
+        let h = ProverService::spawn(Arc::new(db.clone()), prover.clone(), opts(2)).await.unwrap();
+
+        let s = settled(&h, 1).await;
+        assert_eq!((s.status, s.attempts), (ProofStatus::Failed, 2));
+        assert_eq!(prover.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(db.with(|t| t.proofs[&1].0), ProofStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn a_proof_of_another_header_fails_without_retry() {
+        let db = db_with_blocks(1).await;
+        let prover = MockProver::new(&db, 0, true);
+        let h = ProverService::spawn(Arc::new(db.clone()), prover.clone(), opts(3)).await.unwrap();
+
+        let s = settled(&h, 1).await;
+        assert_eq!((s.status, s.attempts), (ProofStatus::Failed, 1));
+        assert!(s.error.unwrap().contains("public values"));
+        assert_eq!(prover.calls.load(Ordering::SeqCst), 1);
+        assert!(db.with(|t| t.artifacts.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn startup_queues_only_unproven_blocks() {
+        let db = db_with_blocks(3).await;
+        db.with(|t| {
+            t.proofs.insert(1, (ProofStatus::Proved, 1, None));
+            t.proofs.insert(2, (ProofStatus::Proving, 1, None)); // interrupted run
+        });
+        let prover = MockProver::new(&db, 0, false);
+        let h = ProverService::spawn(Arc::new(db.clone()), prover.clone(), opts(3)).await.unwrap();
+
+        assert_eq!(settled(&h, 2).await.status, ProofStatus::Proved);
+        assert_eq!(settled(&h, 3).await.status, ProofStatus::Proved);
+        assert!(h.state(BlockNumber(1)).await.is_none());
+        assert_eq!(prover.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn attempts_carry_over_a_restart() {
+        let db = db_with_blocks(2).await;
+        db.with(|t| {
+            t.proofs.insert(1, (ProofStatus::Pending, 1, Some("prover unavailable".into())));
+            t.proofs.insert(2, (ProofStatus::Proving, 2, None)); // out of attempts
+        });
+        let prover = MockProver::new(&db, u32::MAX, false);
+        let h = ProverService::spawn(Arc::new(db.clone()), prover.clone(), opts(2)).await.unwrap();
+
+        assert_eq!(settled(&h, 1).await.attempts, 2);
+        let s = settled(&h, 2).await;
+        assert_eq!((s.status, s.attempts), (ProofStatus::Failed, 2));
+        assert_eq!(prover.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(db.with(|t| t.proofs[&2].0), ProofStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn backoff_is_capped_past_32_attempts() {
+        let db = db_with_blocks(1).await;
+        let prover = MockProver::new(&db, 40, false);
+        let h = ProverService::spawn(Arc::new(db.clone()), prover.clone(), opts(50)).await.unwrap();
+
+        assert_eq!(settled(&h, 1).await.attempts, 41);
+    }
+
+    #[tokio::test]
+    async fn a_block_without_stored_input_fails() {
+        let db = db_with_blocks(1).await;
+        db.with(|t| t.exports.clear());
+        let prover = MockProver::new(&db, 0, false);
+        let h = ProverService::spawn(Arc::new(db.clone()), prover.clone(), opts(3)).await.unwrap();
+
+        assert_eq!(settled(&h, 1).await.status, ProofStatus::Failed);
+        assert_eq!(prover.calls.load(Ordering::SeqCst), 0);
+    }
+}
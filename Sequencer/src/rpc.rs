@@ -54,6 +54,14 @@ pub async fn rpc_handler(State(state): State<crate::main::AppState>, Json(req):
                 None => Json(mk_err(-32602, "not found")),
             }
         }
+        "batch_getProofStatus" => {
+            #[derive(Deserialize)] struct P { block_number: u64 }
+            let p: P = serde_json::from_value(req.params.clone()).unwrap_or(P{ block_number: 1 });
+            match state.store.read().await.proofs.get(&p.block_number).cloned() {
+                Some(pr) => Json(mk_ok(serde_json::to_value(pr).unwrap())),
+                None => Json(mk_err(-32602, "not found")),
+            }
+        }
         "fills_getSince" => {
             #[derive(Deserialize, Default)] struct P { batch_id: Option<u64>, pair_id: Option<u32>, limit: Option<usize> }
             let p: P = serde_json::from_value(req.params.clone()).unwrap_or_default();
@@ -0,0 +1,37 @@
+//! Open-order state behind `BlockHeader::new_state_root`. The transition is
+//! `engine::state`, shared with the zk guest; this applies whole blocks.
+use crate::block::Block;
+use crate::commit::Hasher;
+use tracing::debug;
+
+pub use engine::state::{BatchEffects, BookUpdate, OrderState, StateError};
+
+/// The state after `block`, whose root is the block's `new_state_root`:
+/// `state` with the block's cancels and amends, new orders and matching
+/// effects applied. Fails if `state` is not at `parent_state_root` or the
+/// block names orders the state does not hold; `state` is never changed.
+pub fn apply_block<H: Hasher + Clone>(state: &OrderState<H>, block: &Block) -> anyhow::Result<OrderState<H>> {
+    anyhow::ensure!(
+        state.root() == block.header.parent_state_root,
+        "block {} does not extend the current state root", block.header.block_number.0,
+    );
+    let quarantined = block.quarantined();
+    let effects = BatchEffects {
+        rejected: &block.rejected,
+        quarantined: &quarantined,
+        triggered: &block.triggered,
+        refills: &block.refills,
+        residuals: &block.residuals,
+    };
+    let mut next = pre_batch(state, &block.updates)?;
+    next.apply(&block.new_orders, &effects).map_err(|e| anyhow::anyhow!("state apply: {e:?}"))?;
+    debug!(open = next.len(), "state_applied");
+    Ok(next)
+}
+
+/// `state` with `updates` applied: the book a batch is matched against.
+pub fn pre_batch<H: Hasher + Clone>(state: &OrderState<H>, updates: &[BookUpdate]) -> anyhow::Result<OrderState<H>> {
+    let mut next = state.clone();
+    next.update(updates).map_err(|e| anyhow::anyhow!("state update: {e:?}"))?;
+    Ok(next)
+}
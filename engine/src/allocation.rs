@@ -0,0 +1,107 @@
+use crate::types::AllocationPolicy;
+use alloc::{vec, vec::Vec};
+
+/// Split `qty` across the resting orders of one price level.
+///
+/// `resting` holds each order's remaining quantity in FIFO (`ingest_seq`)
+/// order; the result is index-aligned with it. Pro-rata shares are rounded
+/// down to `size_step`, and the leftover is handed out one step at a time in
+/// FIFO order, so the split is deterministic and never exceeds an order's
+/// remaining.
+pub fn allocate(policy: AllocationPolicy, qty: u64, resting: &[u64], size_step: u64) -> Vec<u64> {
+    let total: u128 = resting.iter().map(|&r| r as u128).sum();
+    let qty = (qty as u128).min(total) as u64;
+    let mut out = vec![0u64; resting.len()];
+
+    match policy {
+        AllocationPolicy::Fifo => fifo_into(&mut out, qty, resting),
+        AllocationPolicy::ProRata => pro_rata_into(&mut out, qty, resting, size_step),
+        AllocationPolicy::FifoProRata { fifo_pct } => {
+            let step = size_step.max(1);
+            let fifo_qty = (qty as u128 * fifo_pct.min(100) as u128 / 100) as u64;
+            let fifo_qty = fifo_qty - fifo_qty % step;
+            fifo_into(&mut out, fifo_qty, resting);
+            let left: Vec<u64> = resting.iter().zip(&out).map(|(r, a)| r - a).collect();
+            let mut rest = vec![0u64; resting.len()];
+            pro_rata_into(&mut rest, qty - fifo_qty, &left, size_step);
+            for (a, b) in out.iter_mut().zip(rest) { *a += b; }
+        }
+    }
+    out
+}
+
+fn fifo_into(out: &mut [u64], mut qty: u64, resting: &[u64]) {
+    for (a, &r) in out.iter_mut().zip(resting) {
+        if qty == 0 { break; }
+        let take = r.min(qty);
+        *a += take;
+        qty -= take;
+    }
+}
+
+fn pro_rata_into(out: &mut [u64], qty: u64, resting: &[u64], size_step: u64) {
+    let step = size_step.max(1);
+    let total: u128 = resting.iter().map(|&r| r as u128).sum();
+    if total == 0 || qty == 0 { return; }
+
+    let mut assigned = 0u64;
+    for (a, &r) in out.iter_mut().zip(resting) {
+        let share = (qty as u128 * r as u128 / total) as u64;
+        let share = (share - share % step).min(r);
+        *a = share;
+        assigned += share;
+    }
+
+    // leftover lots, one step per order per pass, FIFO first
+    let mut left = qty - assigned;
+    while left > 0 {
+        for (a, &r) in out.iter_mut().zip(resting) {
+            if left == 0 { break; }
+            let take = (r - *a).min(step).min(left);
+            *a += take;
+            left -= take;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_fills_in_order() {
+        assert_eq!(allocate(AllocationPolicy::Fifo, 7, &[3, 5, 2], 1), vec![3, 4, 0]);
+    }
+
+    #[test]
+    fn pro_rata_splits_by_size() {
+        assert_eq!(allocate(AllocationPolicy::ProRata, 6, &[2, 4, 6], 1), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn pro_rata_leftover_goes_fifo_in_steps() {
+        // raw shares 3.33 each -> 3,3,3 ; one lot left -> first order
+        assert_eq!(allocate(AllocationPolicy::ProRata, 10, &[10, 10, 10], 1), vec![4, 3, 3]);
+        // step 2: shares 3.33 -> 2,2,2 ; 4 left -> +2 first, +2 second
+        assert_eq!(allocate(AllocationPolicy::ProRata, 10, &[10, 10, 10], 2), vec![4, 4, 2]);
+    }
+
+    #[test]
+    fn pro_rata_never_exceeds_remaining() {
+        let alloc = allocate(AllocationPolicy::ProRata, 9, &[1, 1, 8], 4);
+        assert_eq!(alloc.iter().sum::<u64>(), 9);
+        assert!(alloc.iter().zip([1, 1, 8]).all(|(a, r)| *a <= r));
+    }
+
+    #[test]
+    fn hybrid_gives_fifo_share_first() {
+        // 50% of 8 = 4 FIFO to the first order, remaining 4 pro-rata over [0, 4, 4]
+        let alloc = allocate(AllocationPolicy::FifoProRata { fifo_pct: 50 }, 8, &[4, 4, 4], 1);
+        assert_eq!(alloc, vec![4, 2, 2]);
+    }
+
+    #[test]
+    fn qty_above_level_is_capped() {
+        assert_eq!(allocate(AllocationPolicy::ProRata, 100, &[3, 4], 1), vec![3, 4]);
+    }
+}
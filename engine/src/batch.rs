@@ -0,0 +1,356 @@
+use crate::{
+    types::Order, FillDraft, FillLeg, MarketParams, MarketStatus, OrderId, OrderReject, OwnerMap, PairId,
+    hash::Hasher,
+    implied::{match_implied, ImpliedRoute},
+    r#match::{match_market_from, max_open_seq, ExecutionPlan, MatchError},
+    validate::validate_order,
+};
+use alloc::{collections::{BTreeMap, BTreeSet}, vec::Vec};
+
+/// First match id of `pair_id` is `match_id_base(pair_id) + 1`. Each market
+/// gets its own 2^32 range, so `(batch_id, match_id)` is unique across a
+/// block whichever markets trade and in whatever order they are matched.
+#[inline]
+pub fn match_id_base(pair_id: PairId) -> u64 {
+    (pair_id.0 as u64) << 32
+}
+
+/// A market that could not be matched as given. Orders named by
+/// `error.offending_orders()` were quarantined and the rest matched; a
+/// market-level error skips the market for this batch.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarketFault {
+    pub pair_id: PairId,
+    pub error: MatchError,
+}
+
+/// Knobs for `match_batch`; the default matches markets one at a time with
+/// no implied liquidity.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchOptions {
+    pub threads: usize,              // markets matched at once (needs `std`)
+    pub implied: Vec<ImpliedRoute>,  // triangles traded after outright matching
+}
+
+/// Everything one batch did across all markets.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchPlan {
+    pub batch_id: u64,
+    pub plans: Vec<ExecutionPlan>,  // one per Active market, by `PairId`
+    pub rejected: Vec<OrderReject>, // failed `validate_order`, by `PairId` then input order
+    pub faults: Vec<MarketFault>,   // by `PairId`, then retry order
+}
+
+impl BatchPlan {
+    /// All fills, by `PairId` then `match_id`.
+    pub fn fills(&self) -> impl Iterator<Item = &FillDraft> {
+        self.plans.iter().flat_map(|p| &p.fills)
+    }
+
+    /// All implied legs, by `PairId` then `match_id`.
+    pub fn legs(&self) -> impl Iterator<Item = &FillLeg> {
+        self.plans.iter().flat_map(|p| &p.legs)
+    }
+
+    /// Orders dropped because matching objected to them.
+    pub fn quarantined(&self) -> Vec<OrderId> {
+        self.faults.iter().flat_map(|f| f.error.offending_orders().iter().copied()).collect()
+    }
+}
+
+/// What matching one market produced, before it is merged into the batch.
+struct MarketOutcome {
+    plan: Option<ExecutionPlan>,
+    resting: Vec<Order>, // what the plan leaves on the book
+    rejected: Vec<OrderReject>,
+    faults: Vec<MarketFault>,
+}
+
+/// Match every market of a batch.
+///
+/// `resting` were open before this batch and `incoming` arrived since; both
+/// are grouped by `PairId`, and orders for markets missing from `markets`
+/// are left alone. An Active market validates all of its orders, then
+/// matches with match ids from `match_id_base(pair_id)`. An order the
+/// engine objects to (foreign pair, missing owner) is quarantined and the
+/// market matched again without it. Other markets do not match: only their
+/// `incoming` orders are validated, so CancelOnly and Delisted markets
+/// reject them and Paused ones keep the valid ones waiting.
+///
+/// Markets share no state, so with `opts.threads > 1` (and the `std`
+/// feature) they are matched on up to that many scoped threads. Results
+/// are merged in `PairId` order, so the plan is the same for any thread
+/// count. `opts.implied` routes then trade what is left, in turn.
+#[allow(clippy::too_many_arguments)]
+pub fn match_batch<H, F>(
+    batch_id: u64,
+    markets: &[MarketParams],
+    resting: Vec<Order>,
+    incoming: Vec<Order>,
+    owners: &OwnerMap,
+    hasher: &H,
+    use_fill_salt: bool,
+    fill_salt_fn: F,
+    opts: &BatchOptions,
+) -> BatchPlan
+where
+    H: Hasher + Sync,
+    F: Fn(u64, u64) -> [u8; 32] + Sync,
+{
+    let mut by_pair: BTreeMap<PairId, (&MarketParams, Vec<Order>, Vec<Order>)> = BTreeMap::new();
+    for m in markets {
+        by_pair.insert(m.pair_id, (m, Vec::new(), Vec::new()));
+    }
+    for o in resting {
+        if let Some((_, v, _)) = by_pair.get_mut(&o.pair_id) { v.push(o); }
+    }
+    for o in incoming {
+        if let Some((_, _, v)) = by_pair.get_mut(&o.pair_id) { v.push(o); }
+    }
+
+    let jobs: Vec<_> = by_pair.into_values().collect();
+    let one = |(mkt, resting, incoming): (&MarketParams, Vec<Order>, Vec<Order>)| {
+        match_one(batch_id, mkt, resting, incoming, owners, hasher, use_fill_salt, &fill_salt_fn)
+    };
+    let outcomes = run_jobs(jobs, opts.threads, &one);
+
+    let mut batch = BatchPlan { batch_id, plans: Vec::new(), rejected: Vec::new(), faults: Vec::new() };
+    let mut resting = BTreeMap::new();
+    for out in outcomes {
+        if let Some(plan) = out.plan {
+            resting.insert(plan.pair_id, out.resting);
+            batch.plans.push(plan);
+        }
+        batch.rejected.extend(out.rejected);
+        batch.faults.extend(out.faults);
+    }
+    if !opts.implied.is_empty() {
+        match_implied(
+            batch_id, &opts.implied, markets, &mut resting, &mut batch.plans, owners, hasher, use_fill_salt,
+            &fill_salt_fn,
+        );
+    }
+    batch
+}
+
+#[allow(clippy::too_many_arguments)]
+fn match_one<H: Hasher>(
+    batch_id: u64,
+    mkt: &MarketParams,
+    resting: Vec<Order>,
+    incoming: Vec<Order>,
+    owners: &OwnerMap,
+    hasher: &H,
+    use_fill_salt: bool,
+    fill_salt_fn: &impl Fn(u64, u64) -> [u8; 32],
+) -> MarketOutcome {
+    let mut out = MarketOutcome { plan: None, resting: Vec::new(), rejected: Vec::new(), faults: Vec::new() };
+    let active = mkt.status == MarketStatus::Active;
+    let ords = if active { resting.into_iter().chain(incoming).collect() } else { incoming };
+    let mut valid = Vec::with_capacity(ords.len());
+    for o in ords {
+        match validate_order(mkt, &o) {
+            Ok(()) => valid.push(o),
+            Err(reason) => out.rejected.push(OrderReject { order_id: o.order_id, reason }),
+        }
+    }
+    if !active {
+        return out;
+    }
+
+    loop {
+        let res = match_market_from(
+            mkt.pair_id, batch_id, match_id_base(mkt.pair_id), max_open_seq(&valid), mkt,
+            valid.clone(), owners, hasher, use_fill_salt, fill_salt_fn,
+        );
+        match res {
+            Ok(plan) => {
+                out.resting = plan.resting_after(valid);
+                out.plan = Some(plan);
+                return out;
+            }
+            Err(error) => {
+                let bad: BTreeSet<OrderId> = error.offending_orders().iter().copied().collect();
+                out.faults.push(MarketFault { pair_id: mkt.pair_id, error });
+                if bad.is_empty() {
+                    return out;
+                }
+                valid.retain(|o| !bad.contains(&o.order_id));
+            }
+        }
+    }
+}
+
+/// Run `f` over `jobs`, keeping their order in the result.
+#[cfg(feature = "std")]
+fn run_jobs<J: Send, R: Send>(jobs: Vec<J>, threads: usize, f: &(impl Fn(J) -> R + Sync)) -> Vec<R> {
+    if threads <= 1 || jobs.len() <= 1 {
+        return jobs.into_iter().map(f).collect();
+    }
+    let per = jobs.len().div_ceil(threads);
+    let mut chunks: Vec<Vec<J>> = Vec::new();
+    let mut it = jobs.into_iter();
+    loop {
+        let c: Vec<J> = it.by_ref().take(per).collect();
+        if c.is_empty() { break; }
+        chunks.push(c);
+    }
+    std::thread::scope(|s| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|c| s.spawn(move || c.into_iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Without `std` there are no threads; markets are matched in turn.
+#[cfg(not(feature = "std"))]
+fn run_jobs<J, R>(jobs: Vec<J>, _threads: usize, f: &impl Fn(J) -> R) -> Vec<R> {
+    jobs.into_iter().map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{types::*, pid::StubPoseidon};
+    use std::collections::BTreeMap;
+
+    fn market(pair: u32) -> MarketParams {
+        MarketParams {
+            pair_id: PairId(pair),
+            price_tick: 1, size_step: 1,
+            notional_min: 0, notional_max: u128::MAX,
+            maker_bps: 0, taker_bps: 0, status: MarketStatus::Active,
+            clearing: ClearingMode::Continuous,
+            allocation: AllocationPolicy::Fifo,
+            stp: StpMode::None,
+        }
+    }
+
+    fn order(id: u64, pair: u32, side: Side, px: u64, qty: u64) -> Order {
+        Order {
+            order_id: OrderId(id), order_hash: [id as u8; 32], pair_id: PairId(pair), side,
+            price_tick: px, amount: qty, remaining: qty, time_bucket: 0, nonce: id,
+            ingest_seq: id, tif: TimeInForce::Gtc, stop: None, display_qty: None,
+        }
+    }
+
+    /// Two crosses in each of `pairs` markets, owners for all of them.
+    fn book(pairs: u32) -> (Vec<MarketParams>, Vec<Order>, OwnerMap) {
+        let mut markets = Vec::new();
+        let mut orders = Vec::new();
+        let mut owners: OwnerMap = BTreeMap::new();
+        for p in 1..=pairs {
+            markets.push(market(p));
+            let id = p as u64 * 10;
+            orders.push(order(id + 1, p, Side::Bid, 100, 10));
+            orders.push(order(id + 2, p, Side::Ask, 100, 4));
+            orders.push(order(id + 3, p, Side::Ask, 99, 6));
+        }
+        for o in &orders { owners.insert(o.order_id.0, [o.order_id.0 as u8; 32]); }
+        // listed out of order on purpose
+        markets.reverse();
+        (markets, orders, owners)
+    }
+
+    #[test]
+    fn match_ids_are_unique_across_markets() {
+        let (markets, orders, owners) = book(3);
+        let plan = match_batch(5, &markets, orders, Vec::new(), &owners, &StubPoseidon, false, |_, _| [0; 32], &BatchOptions::default());
+
+        assert_eq!(plan.plans.iter().map(|p| p.pair_id.0).collect::<Vec<_>>(), vec![1, 2, 3]);
+        let ids: Vec<u64> = plan.fills().map(|f| f.match_id).collect();
+        assert_eq!(ids.len(), 6);
+        assert_eq!(ids.iter().collect::<BTreeSet<_>>().len(), ids.len());
+        assert_eq!(ids[0], match_id_base(PairId(1)) + 1);
+        assert_eq!(ids[2], match_id_base(PairId(2)) + 1);
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_plan() {
+        let (markets, orders, owners) = book(7);
+        let salt = |b: u64, m: u64| { let mut s = [0u8; 32]; s[..8].copy_from_slice(&(b ^ m).to_le_bytes()); s };
+        let digests = |threads| {
+            let plan = match_batch(9, &markets, orders.clone(), Vec::new(), &owners, &StubPoseidon, true, salt, &BatchOptions { threads, ..Default::default() });
+            plan.plans.iter().map(|p| p.digest(&StubPoseidon)).collect::<Vec<_>>()
+        };
+        let one = digests(1);
+        assert_eq!(one.len(), 7);
+        for t in [2, 3, 8, 64] {
+            assert_eq!(digests(t), one, "threads = {t}");
+        }
+    }
+
+    #[test]
+    fn rejects_quarantines_and_skips() {
+        let (mut markets, mut orders, mut owners) = book(3);
+        markets.iter_mut().find(|m| m.pair_id == PairId(3)).unwrap().status = MarketStatus::Paused;
+        orders.push(order(90, 1, Side::Bid, 100, 0)); // zero amount
+        owners.remove(&22);                           // no owner row
+        orders.push(order(91, 4, Side::Bid, 100, 1)); // unknown market
+
+        let plan = match_batch(5, &markets, orders, Vec::new(), &owners, &StubPoseidon, false, |_, _| [0; 32], &BatchOptions { threads: 2, ..Default::default() });
+
+        assert_eq!(plan.plans.iter().map(|p| p.pair_id.0).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(plan.rejected.len(), 1);
+        assert_eq!(plan.rejected[0].order_id, OrderId(90));
+        assert_eq!(plan.faults.len(), 1);
+        assert_eq!(plan.faults[0].pair_id, PairId(2));
+        assert_eq!(plan.quarantined(), vec![OrderId(22)]);
+        // pair 2 still matched without the quarantined ask
+        assert_eq!(plan.plans[1].fills.len(), 1);
+        assert!(plan.fills().all(|f| f.seller_order_id != OrderId(22)));
+    }
+
+    #[test]
+    fn closed_markets_reject_only_incoming_orders() {
+        let (mut markets, orders, owners) = book(3);
+        for (m, status) in markets.iter_mut().zip([MarketStatus::Delisted, MarketStatus::CancelOnly, MarketStatus::Paused]) {
+            m.status = status;
+        }
+        let incoming = vec![
+            order(91, 1, Side::Bid, 100, 1), // paused
+            order(92, 2, Side::Bid, 100, 1), // cancel-only
+            order(93, 3, Side::Bid, 100, 1), // delisted
+            order(94, 1, Side::Bid, 100, 0), // paused, but malformed
+        ];
+
+        let plan = match_batch(5, &markets, orders, incoming, &owners, &StubPoseidon, false, |_, _| [0; 32], &BatchOptions::default());
+
+        assert!(plan.plans.is_empty());
+        let rejected: Vec<_> = plan.rejected.iter().map(|r| (r.order_id.0, r.reason)).collect();
+        assert_eq!(rejected, vec![
+            (94, RejectReason::Zero),
+            (92, RejectReason::MarketClosed(MarketStatus::CancelOnly)),
+            (93, RejectReason::MarketClosed(MarketStatus::Delisted)),
+        ]);
+    }
+
+    #[test]
+    fn implied_routes_trade_after_outright_matching() {
+        let markets = vec![market(1), market(2), market(3)];
+        let orders = vec![
+            order(1, 2, Side::Ask, 10, 8),   // A/B
+            order(2, 3, Side::Ask, 20, 100), // B/C
+            order(3, 1, Side::Bid, 200, 5),  // A/C
+        ];
+        let owners: OwnerMap = orders.iter().map(|o| (o.order_id.0, [o.order_id.0 as u8; 32])).collect();
+        let opts = BatchOptions {
+            threads: 2,
+            implied: vec![ImpliedRoute { outright: PairId(1), first: PairId(2), second: PairId(3) }],
+        };
+        let plan = match_batch(5, &markets, orders, Vec::new(), &owners, &StubPoseidon, false, |_, _| [0; 32], &opts);
+
+        assert_eq!(plan.fills().count(), 0);
+        let legs: Vec<_> = plan.legs().collect();
+        assert_eq!(legs.len(), 3);
+        assert!(legs.iter().all(|l| l.group_id == legs[0].match_id));
+    }
+}
@@ -1,12 +1,11 @@
 use crate::{Order, OrderKey, Side};
-use std::collections::BinaryHeap;
-use std::cmp::Ordering;
+use alloc::{collections::BinaryHeap, vec::Vec};
+use core::cmp::Ordering;
 
 /// Min-heap for asks,
 ///  max-heap for bids using OrderKey rules.
 // #[derive(Default)]
 pub struct SideBook {
-    side: Side,
     heap: BinaryHeap<BookItem>,
 }
 
@@ -20,6 +19,7 @@ impl PartialEq for BookItem {
     fn eq(&self, other: &Self) -> bool {
         self.key.price_tick == other.key.price_tick &&
         self.key.ingest_seq == other.key.ingest_seq &&
+        self.key.order_id == other.key.order_id &&
         self.key.side == other.key.side
     }
 }
@@ -32,11 +32,9 @@ impl PartialOrd for BookItem {
 }
 impl Ord for BookItem {
     fn cmp(&self, other: &Self) -> Ordering {
-        // BinaryHeap is max-heap; invert for asks
-        match self.key.side {
-            Side::Bid => OrderKey::cmp(&self.key, &other.key),
-            Side::Ask => OrderKey::cmp(&self.key, &other.key).reverse(),
-        }
+        // OrderKey::cmp sorts best-first (Less = better) on both sides, and
+        // BinaryHeap pops its max, so invert for bids and asks alike.
+        OrderKey::cmp(&self.key, &other.key).reverse()
     }
 }
 
@@ -44,24 +42,57 @@ pub struct OrderBook {
     pub bids: SideBook,
     pub asks: SideBook,
     pub orders: Vec<Order>, // indexed by BookItem.idx
+    queue_seq: Vec<u64>,    // current queue position per order; older heap items are stale
 }
 
 impl OrderBook {
     pub fn from_orders(mut orders: Vec<Order>) -> Self {
-        let mut bids = SideBook { side: Side::Bid, heap: BinaryHeap::new() };
-        let mut asks = SideBook { side: Side::Ask, heap: BinaryHeap::new() };
+        let mut bids = SideBook { heap: BinaryHeap::new() };
+        let mut asks = SideBook { heap: BinaryHeap::new() };
 
         orders.retain(|o| o.remaining > 0);
 
         for (idx, o) in orders.iter().enumerate() {
-            let key = OrderKey { side: o.side, price_tick: o.price_tick, ingest_seq: o.ingest_seq };
+            let key = OrderKey { side: o.side, price_tick: o.price_tick, ingest_seq: o.ingest_seq, order_id: o.order_id };
             let item = BookItem { key, idx };
             match o.side {
                 Side::Bid => bids.heap.push(item),
                 Side::Ask => asks.heap.push(item),
             }
         }
-        Self { bids, asks, orders }
+        let queue_seq = orders.iter().map(|o| o.ingest_seq).collect();
+        Self { bids, asks, orders, queue_seq }
+    }
+
+    /// Move an order to the back of its price level under `ingest_seq`.
+    /// The order's own `ingest_seq` is left alone, so taker/maker roles in
+    /// the current pass do not change; see `queue_seq`.
+    pub fn requeue(&mut self, idx: usize, ingest_seq: u64) {
+        let o = &self.orders[idx];
+        let key = OrderKey { side: o.side, price_tick: o.price_tick, ingest_seq, order_id: o.order_id };
+        let item = BookItem { key, idx };
+        self.queue_seq[idx] = ingest_seq;
+        match o.side {
+            Side::Bid => self.bids.heap.push(item),
+            Side::Ask => self.asks.heap.push(item),
+        }
+    }
+
+    /// Queue position of `orders[idx]`, after any `requeue`.
+    pub fn queue_seq(&self, idx: usize) -> u64 { self.queue_seq[idx] }
+
+    /// Hand the orders back with queue positions folded into `ingest_seq`.
+    pub fn into_orders(self) -> Vec<Order> {
+        let mut orders = self.orders;
+        for (o, seq) in orders.iter_mut().zip(self.queue_seq) {
+            o.ingest_seq = seq;
+        }
+        orders
+    }
+
+    #[inline]
+    fn is_dead(&self, it: &BookItem) -> bool {
+        self.orders[it.idx].remaining == 0 || it.key.ingest_seq != self.queue_seq[it.idx]
     }
 
     pub fn best_bid_idx(&self) -> Option<usize> {
@@ -73,7 +104,7 @@ impl OrderBook {
 
     /// Pop & reinsert if order still has remaining; else drop it.
     pub fn consume_bid_top(&mut self) {
-        if let Some(mut it) = self.bids.heap.pop() {
+        if let Some(it) = self.bids.heap.pop() {
             if self.orders[it.idx].remaining > 0 {
                 // reinsert to maintain heap (ingest_seq/price unchanged)
                 self.bids.heap.push(it);
@@ -81,27 +112,28 @@ impl OrderBook {
         }
     }
     pub fn consume_ask_top(&mut self) {
-        if let Some(mut it) = self.asks.heap.pop() {
+        if let Some(it) = self.asks.heap.pop() {
             if self.orders[it.idx].remaining > 0 {
                 self.asks.heap.push(it);
             }
         }
     }
 
-    /// After mutating an order's remaining, call this to drop it if empty and advance the heap.
+    /// After mutating an order's remaining (or re-queueing it), call this to
+    /// drop empty or stale entries and advance the heap.
     pub fn on_fill(&mut self, side: Side) {
         match side {
             Side::Bid => {
                 // If top is now empty, pop it
-                while let Some(idx) = self.best_bid_idx() {
-                    if self.orders[idx].remaining == 0 {
+                while let Some(it) = self.bids.heap.peek() {
+                    if self.is_dead(it) {
                         self.bids.heap.pop();
                     } else { break; }
                 }
             }
             Side::Ask => {
-                while let Some(idx) = self.best_ask_idx() {
-                    if self.orders[idx].remaining == 0 {
+                while let Some(it) = self.asks.heap.peek() {
+                    if self.is_dead(it) {
                         self.asks.heap.pop();
                     } else { break; }
                 }
@@ -109,3 +141,63 @@ impl OrderBook {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::*;
+
+    fn order(id: u64, side: Side, px: u64, seq: u64) -> Order {
+        Order {
+            order_id: OrderId(id), order_hash: [0; 32], pair_id: PairId(1), side, price_tick: px,
+            amount: 1, remaining: 1, time_bucket: 0, nonce: id, ingest_seq: seq, tif: TimeInForce::Gtc,
+            stop: None, display_qty: None,
+        }
+    }
+
+    /// Drain one side through the heap, best first.
+    fn drain(book: &mut OrderBook, side: Side) -> Vec<u64> {
+        let mut out = Vec::new();
+        loop {
+            let top = match side {
+                Side::Bid => book.best_bid_idx(),
+                Side::Ask => book.best_ask_idx(),
+            };
+            let Some(idx) = top else { break };
+            out.push(book.orders[idx].order_id.0);
+            book.orders[idx].remaining = 0;
+            book.on_fill(side);
+        }
+        out
+    }
+
+    // Regression: bids used to pop lowest price / latest ingest_seq first.
+    #[test]
+    fn both_sides_pop_best_price_then_earliest_seq() {
+        let mut book = OrderBook::from_orders(vec![
+            order(1, Side::Bid, 99, 1),
+            order(2, Side::Bid, 101, 3),
+            order(3, Side::Bid, 101, 2),
+            order(4, Side::Bid, 100, 4),
+            order(5, Side::Ask, 103, 5),
+            order(6, Side::Ask, 102, 7),
+            order(7, Side::Ask, 102, 6),
+            order(8, Side::Ask, 104, 8),
+        ]);
+        assert_eq!(drain(&mut book, Side::Bid), vec![3, 2, 4, 1]);
+        assert_eq!(drain(&mut book, Side::Ask), vec![7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn equal_ingest_seq_falls_back_to_order_id() {
+        // e.g. duplicate sequence numbers from the database
+        let mut book = OrderBook::from_orders(vec![
+            order(9, Side::Bid, 100, 1),
+            order(4, Side::Bid, 100, 1),
+            order(6, Side::Ask, 101, 1),
+            order(3, Side::Ask, 101, 1),
+        ]);
+        assert_eq!(drain(&mut book, Side::Bid), vec![4, 9]);
+        assert_eq!(drain(&mut book, Side::Ask), vec![3, 6]);
+    }
+}
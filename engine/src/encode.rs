@@ -0,0 +1,677 @@
+//! Canonical byte encodings hashed into commitments and plan digests.
+//! Host and guest both use these, so any change here is a format change.
+//!
+//! Wire format v1: every record starts with `ENCODING_VERSION`, followed by
+//! fixed-width little-endian fields in declaration order. Optional values are
+//! a one-byte tag (0 absent, 1 present) plus a payload that is all zeros when
+//! absent, so each record type has one length and every value one encoding.
+//! Decoders accept exactly that: unknown versions, tags out of range,
+//! non-zero padding and trailing bytes are errors. Golden vectors live in
+//! `testdata/encoding-v1.json`.
+use crate::r#match::ExecutionPlan;
+use crate::types::*;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const ENCODING_VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    UnsupportedVersion(u8),
+    Truncated,
+    TrailingBytes(usize),
+    /// A tag, flag or narrowed integer outside its range, or non-zero
+    /// padding behind an absent option.
+    Invalid { field: &'static str, value: u128 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported encoding version {v}"),
+            DecodeError::Truncated => f.write_str("truncated record"),
+            DecodeError::TrailingBytes(n) => write!(f, "{n} trailing bytes"),
+            DecodeError::Invalid { field, value } => write!(f, "invalid {field}: {value}"),
+        }
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn new(len: usize) -> Self {
+        let mut v = Vec::with_capacity(len);
+        v.push(ENCODING_VERSION);
+        Writer(v)
+    }
+    fn u8(&mut self, x: u8) -> &mut Self {
+        self.0.push(x);
+        self
+    }
+    fn u16(&mut self, x: u16) -> &mut Self {
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+    fn u32(&mut self, x: u32) -> &mut Self {
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+    fn u64(&mut self, x: u64) -> &mut Self {
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+    fn u128(&mut self, x: u128) -> &mut Self {
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+    fn b32(&mut self, x: &[u8; 32]) -> &mut Self {
+        self.0.extend_from_slice(x);
+        self
+    }
+    fn opt_u64(&mut self, x: Option<u64>) -> &mut Self {
+        self.u8(x.is_some() as u8).u64(x.unwrap_or(0))
+    }
+    fn opt_b32(&mut self, x: &Option<[u8; 32]>) -> &mut Self {
+        self.u8(x.is_some() as u8).b32(&x.unwrap_or([0; 32]))
+    }
+    fn finish(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.0)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader(buf);
+        match r.u8()? {
+            ENCODING_VERSION => Ok(r),
+            v => Err(DecodeError::UnsupportedVersion(v)),
+        }
+    }
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let (head, rest) = self.0.split_first_chunk::<N>().ok_or(DecodeError::Truncated)?;
+        self.0 = rest;
+        Ok(*head)
+    }
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.take().map(u16::from_le_bytes)
+    }
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.take().map(u32::from_le_bytes)
+    }
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.take().map(u64::from_le_bytes)
+    }
+    fn u128(&mut self) -> Result<u128, DecodeError> {
+        self.take().map(u128::from_le_bytes)
+    }
+    fn b32(&mut self) -> Result<[u8; 32], DecodeError> {
+        self.take()
+    }
+    fn bool(&mut self, field: &'static str) -> Result<bool, DecodeError> {
+        tag(field, self.u8()? as u64, &[false, true])
+    }
+    fn bool64(&mut self, field: &'static str) -> Result<bool, DecodeError> {
+        tag(field, self.u64()?, &[false, true])
+    }
+    fn pair_id(&mut self) -> Result<PairId, DecodeError> {
+        let x = self.u64()?;
+        u32::try_from(x).map(PairId).map_err(|_| invalid("pair_id", x))
+    }
+    fn opt_u64(&mut self, field: &'static str) -> Result<Option<u64>, DecodeError> {
+        let present = self.bool(field)?;
+        let x = self.u64()?;
+        match (present, x) {
+            (true, x) => Ok(Some(x)),
+            (false, 0) => Ok(None),
+            (false, x) => Err(invalid(field, x)),
+        }
+    }
+    fn opt_b32(&mut self, field: &'static str) -> Result<Option<[u8; 32]>, DecodeError> {
+        let present = self.bool(field)?;
+        let x = self.b32()?;
+        match present {
+            true => Ok(Some(x)),
+            false if x == [0; 32] => Ok(None),
+            false => Err(invalid(field, 1u8)),
+        }
+    }
+    fn finish<T>(self, value: T) -> Result<T, DecodeError> {
+        match self.0.len() {
+            0 => Ok(value),
+            n => Err(DecodeError::TrailingBytes(n)),
+        }
+    }
+}
+
+fn invalid(field: &'static str, value: impl Into<u128>) -> DecodeError {
+    DecodeError::Invalid { field, value: value.into() }
+}
+
+/// `values[x]`, the inverse of encoding a C-like enum by its index.
+fn tag<T: Copy>(field: &'static str, x: u64, values: &[T]) -> Result<T, DecodeError> {
+    usize::try_from(x).ok().and_then(|i| values.get(i).copied()).ok_or(invalid(field, x))
+}
+
+const SIDES: [Side; 2] = [Side::Bid, Side::Ask];
+const TIFS: [TimeInForce; 4] = [TimeInForce::Gtc, TimeInForce::Ioc, TimeInForce::Fok, TimeInForce::PostOnly];
+const STATUSES: [MarketStatus; 4] =
+    [MarketStatus::Active, MarketStatus::Paused, MarketStatus::CancelOnly, MarketStatus::Delisted];
+const CLEARINGS: [ClearingMode; 2] = [ClearingMode::Continuous, ClearingMode::UniformPrice];
+const STPS: [StpMode; 5] =
+    [StpMode::None, StpMode::CancelNewest, StpMode::CancelOldest, StpMode::CancelBoth, StpMode::DecrementAndCancel];
+const CANCEL_REASONS: [CancelReason; 4] = [
+    CancelReason::IocRemainder,
+    CancelReason::FokUnfilled,
+    CancelReason::PostOnlyWouldCross,
+    CancelReason::SelfTrade,
+];
+
+fn index_of<T: PartialEq>(values: &[T], x: &T) -> u8 {
+    values.iter().position(|v| v == x).expect("listed above") as u8
+}
+
+pub const ORDER_LEN: usize = 1 + 8 * 13 + 4 + 32 + 1;
+
+pub fn encode_order(o: &Order) -> Vec<u8> {
+    // stop: kind (0 none, 1 market, 2 limit), trigger_tick, triggered flag
+    let (kind, trigger_tick, triggered) = match o.stop {
+        None => (0, 0, 0),
+        Some(s) => (match s.kind { StopKind::Market => 1, StopKind::Limit => 2 }, s.trigger_tick, s.triggered as u64),
+    };
+    Writer::new(ORDER_LEN)
+        .u64(o.order_id.0)
+        .b32(&o.order_hash)
+        .u64(o.pair_id.0 as u64)
+        .u64(index_of(&SIDES, &o.side) as u64)
+        .u64(o.price_tick)
+        .u64(o.amount)
+        .u64(o.remaining)
+        .u32(o.time_bucket)
+        .u64(o.nonce)
+        .u64(o.ingest_seq)
+        .u64(index_of(&TIFS, &o.tif) as u64)
+        .u64(kind)
+        .u64(trigger_tick)
+        .u64(triggered)
+        .opt_u64(o.display_qty)
+        .finish()
+}
+
+pub fn decode_order(bytes: &[u8]) -> Result<Order, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let order_id = OrderId(r.u64()?);
+    let order_hash = r.b32()?;
+    let pair_id = r.pair_id()?;
+    let side = tag("side", r.u64()?, &SIDES)?;
+    let (price_tick, amount, remaining) = (r.u64()?, r.u64()?, r.u64()?);
+    let time_bucket = r.u32()?;
+    let (nonce, ingest_seq) = (r.u64()?, r.u64()?);
+    let tif = tag("tif", r.u64()?, &TIFS)?;
+    let kind = tag("stop_kind", r.u64()?, &[None, Some(StopKind::Market), Some(StopKind::Limit)])?;
+    let trigger_tick = r.u64()?;
+    let triggered = r.bool64("stop_triggered")?;
+    let stop = match kind {
+        Some(kind) => Some(StopTrigger { kind, trigger_tick, triggered }),
+        None if trigger_tick == 0 && !triggered => None,
+        None => return Err(invalid("stop_kind", 0u8)),
+    };
+    let display_qty = r.opt_u64("display_qty")?;
+    r.finish(Order {
+        order_id, order_hash, pair_id, side, price_tick, amount, remaining, time_bucket, nonce, ingest_seq, tif, stop,
+        display_qty,
+    })
+}
+
+pub const FILL_LEN: usize = 1 + 8 * 7 + 4 + 32 * 4 + 1 + 16 * 2 + 1 + 32;
+
+pub fn encode_fill(f: &FillDraft) -> Vec<u8> {
+    Writer::new(FILL_LEN)
+        .u64(f.batch_id)
+        .u64(f.match_id)
+        .u64(f.pair_id.0 as u64)
+        .u64(f.price_tick)
+        .u64(f.fill_qty)
+        .u32(f.time_bucket)
+        .u64(f.buyer_order_id.0)
+        .u64(f.seller_order_id.0)
+        .b32(&f.buyer_order_hash)
+        .b32(&f.seller_order_hash)
+        .b32(&f.buyer_pid)
+        .b32(&f.seller_pid)
+        .u8(index_of(&SIDES, &f.maker_side))
+        .u128(f.maker_fee)
+        .u128(f.taker_fee)
+        .opt_b32(&f.fill_salt)
+        .finish()
+}
+
+pub fn decode_fill(bytes: &[u8]) -> Result<FillDraft, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let (batch_id, match_id) = (r.u64()?, r.u64()?);
+    let pair_id = r.pair_id()?;
+    let (price_tick, fill_qty) = (r.u64()?, r.u64()?);
+    let time_bucket = r.u32()?;
+    let (buyer_order_id, seller_order_id) = (OrderId(r.u64()?), OrderId(r.u64()?));
+    let (buyer_order_hash, seller_order_hash) = (r.b32()?, r.b32()?);
+    let (buyer_pid, seller_pid) = (r.b32()?, r.b32()?);
+    let maker_side = tag("maker_side", r.u8()? as u64, &SIDES)?;
+    let (maker_fee, taker_fee) = (r.u128()?, r.u128()?);
+    let fill_salt = r.opt_b32("fill_salt")?;
+    r.finish(FillDraft {
+        batch_id, match_id, pair_id, price_tick, fill_qty, time_bucket, buyer_order_id, seller_order_id,
+        buyer_order_hash, seller_order_hash, buyer_pid, seller_pid, maker_side, maker_fee, taker_fee, fill_salt,
+    })
+}
+
+pub const LEG_LEN: usize = 1 + 8 * 7 + 4 + 32 + 1 + 32 + 1 + 16 + 1 + 32;
+
+pub fn encode_leg(l: &FillLeg) -> Vec<u8> {
+    Writer::new(LEG_LEN)
+        .u64(l.batch_id)
+        .u64(l.match_id)
+        .u64(l.group_id)
+        .u64(l.pair_id.0 as u64)
+        .u64(l.price_tick)
+        .u64(l.fill_qty)
+        .u32(l.time_bucket)
+        .u64(l.order_id.0)
+        .b32(&l.order_hash)
+        .u8(index_of(&SIDES, &l.side))
+        .b32(&l.pid)
+        .u8(l.is_maker as u8)
+        .u128(l.fee)
+        .opt_b32(&l.fill_salt)
+        .finish()
+}
+
+pub fn decode_leg(bytes: &[u8]) -> Result<FillLeg, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let (batch_id, match_id, group_id) = (r.u64()?, r.u64()?, r.u64()?);
+    let pair_id = r.pair_id()?;
+    let (price_tick, fill_qty) = (r.u64()?, r.u64()?);
+    let time_bucket = r.u32()?;
+    let order_id = OrderId(r.u64()?);
+    let order_hash = r.b32()?;
+    let side = tag("side", r.u8()? as u64, &SIDES)?;
+    let pid = r.b32()?;
+    let is_maker = r.bool("is_maker")?;
+    let fee = r.u128()?;
+    let fill_salt = r.opt_b32("fill_salt")?;
+    r.finish(FillLeg {
+        batch_id, match_id, group_id, pair_id, price_tick, fill_qty, time_bucket, order_id, order_hash, side, pid,
+        is_maker, fee, fill_salt,
+    })
+}
+
+pub const MARKET_LEN: usize = 1 + 8 * 3 + 16 * 2 + 2 * 7;
+
+pub fn encode_market(m: &MarketParams) -> Vec<u8> {
+    let (alloc_kind, fifo_pct) = match m.allocation {
+        AllocationPolicy::Fifo => (0, 0),
+        AllocationPolicy::ProRata => (1, 0),
+        AllocationPolicy::FifoProRata { fifo_pct } => (2, fifo_pct as u16),
+    };
+    Writer::new(MARKET_LEN)
+        .u64(m.pair_id.0 as u64)
+        .u64(m.price_tick)
+        .u64(m.size_step)
+        .u128(m.notional_min)
+        .u128(m.notional_max)
+        .u16(m.maker_bps)
+        .u16(m.taker_bps)
+        .u16(index_of(&STATUSES, &m.status) as u16)
+        .u16(index_of(&CLEARINGS, &m.clearing) as u16)
+        .u16(alloc_kind)
+        .u16(fifo_pct)
+        .u16(index_of(&STPS, &m.stp) as u16)
+        .finish()
+}
+
+pub fn decode_market(bytes: &[u8]) -> Result<MarketParams, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let pair_id = r.pair_id()?;
+    let (price_tick, size_step) = (r.u64()?, r.u64()?);
+    let (notional_min, notional_max) = (r.u128()?, r.u128()?);
+    let (maker_bps, taker_bps) = (r.u16()?, r.u16()?);
+    let status = tag("status", r.u16()? as u64, &STATUSES)?;
+    let clearing = tag("clearing", r.u16()? as u64, &CLEARINGS)?;
+    let (alloc_kind, fifo_pct) = (r.u16()?, r.u16()?);
+    let allocation = match (alloc_kind, u8::try_from(fifo_pct)) {
+        (0, Ok(0)) => AllocationPolicy::Fifo,
+        (1, Ok(0)) => AllocationPolicy::ProRata,
+        (2, Ok(fifo_pct)) => AllocationPolicy::FifoProRata { fifo_pct },
+        (0..=2, _) => return Err(invalid("fifo_pct", fifo_pct)),
+        _ => return Err(invalid("allocation", alloc_kind)),
+    };
+    let stp = tag("stp", r.u16()? as u64, &STPS)?;
+    r.finish(MarketParams {
+        pair_id, price_tick, size_step, notional_min, notional_max, maker_bps, taker_bps, status, clearing,
+        allocation, stp,
+    })
+}
+
+pub const RESIDUAL_LEN: usize = 1 + 8 * 3 + 1;
+
+pub fn encode_residual(r: &OrderResidual) -> Vec<u8> {
+    Writer::new(RESIDUAL_LEN)
+        .u64(r.order_id.0)
+        .u64(r.remaining_before)
+        .u64(r.remaining_after)
+        .u8(r.now_filled as u8)
+        .finish()
+}
+
+pub fn decode_residual(bytes: &[u8]) -> Result<OrderResidual, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let order_id = OrderId(r.u64()?);
+    let (remaining_before, remaining_after) = (r.u64()?, r.u64()?);
+    let now_filled = r.bool("now_filled")?;
+    r.finish(OrderResidual { order_id, remaining_before, remaining_after, now_filled })
+}
+
+pub const CANCEL_LEN: usize = 1 + 8 * 2 + 1;
+
+pub fn encode_cancel(c: &OrderCancel) -> Vec<u8> {
+    Writer::new(CANCEL_LEN)
+        .u64(c.order_id.0)
+        .u64(c.cancelled_qty)
+        .u8(index_of(&CANCEL_REASONS, &c.reason))
+        .finish()
+}
+
+pub fn decode_cancel(bytes: &[u8]) -> Result<OrderCancel, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let order_id = OrderId(r.u64()?);
+    let cancelled_qty = r.u64()?;
+    let reason = tag("reason", r.u8()? as u64, &CANCEL_REASONS)?;
+    r.finish(OrderCancel { order_id, cancelled_qty, reason })
+}
+
+pub const TRIGGER_LEN: usize = 1 + 8 * 3;
+
+pub fn encode_trigger(t: &OrderTrigger) -> Vec<u8> {
+    Writer::new(TRIGGER_LEN).u64(t.order_id.0).u64(t.ingest_seq).u64(t.last_price).finish()
+}
+
+pub fn decode_trigger(bytes: &[u8]) -> Result<OrderTrigger, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let (order_id, ingest_seq, last_price) = (OrderId(r.u64()?), r.u64()?, r.u64()?);
+    r.finish(OrderTrigger { order_id, ingest_seq, last_price })
+}
+
+pub const REFILL_LEN: usize = 1 + 8 * 2;
+
+pub fn encode_refill(r: &OrderRefill) -> Vec<u8> {
+    Writer::new(REFILL_LEN).u64(r.order_id.0).u64(r.ingest_seq).finish()
+}
+
+pub fn decode_refill(bytes: &[u8]) -> Result<OrderRefill, DecodeError> {
+    let mut r = Reader::new(bytes)?;
+    let (order_id, ingest_seq) = (OrderId(r.u64()?), r.u64()?);
+    r.finish(OrderRefill { order_id, ingest_seq })
+}
+
+/// Plan identity plus the length of every output list, so two plans can
+/// only share a digest if they also agree on where each list ends. Only
+/// hashed, never decoded.
+pub fn encode_plan_header(p: &ExecutionPlan) -> Vec<u8> {
+    let mut w = Writer::new(1 + 8 * 2 + 1 + 8 + 8 * 6);
+    w.u64(p.pair_id.0 as u64).u64(p.batch_id).opt_u64(p.clearing_price);
+    for len in [p.fills.len(), p.residuals.len(), p.cancels.len(), p.triggered.len(), p.refills.len(), p.legs.len()] {
+        w.u64(len as u64);
+    }
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn order(stop: Option<StopTrigger>, display_qty: Option<u64>) -> Order {
+        Order {
+            order_id: OrderId(7), order_hash: [0x11; 32], pair_id: PairId(3), side: Side::Ask, price_tick: 1_000,
+            amount: 50, remaining: 20, time_bucket: 9, nonce: 4, ingest_seq: 12, tif: TimeInForce::PostOnly, stop,
+            display_qty,
+        }
+    }
+
+    fn fill(fill_salt: Option<[u8; 32]>) -> FillDraft {
+        FillDraft {
+            batch_id: 2, match_id: (3 << 32) + 1, pair_id: PairId(3), price_tick: 1_000, fill_qty: 30, time_bucket: 9,
+            buyer_order_id: OrderId(8), seller_order_id: OrderId(7), buyer_order_hash: [0x22; 32],
+            seller_order_hash: [0x11; 32], buyer_pid: [0x33; 32], seller_pid: [0x44; 32], maker_side: Side::Ask,
+            maker_fee: 3, taker_fee: 9, fill_salt,
+        }
+    }
+
+    fn leg() -> FillLeg {
+        FillLeg {
+            batch_id: 2, match_id: (5 << 32) + 2, group_id: (3 << 32) + 2, pair_id: PairId(5), price_tick: 20,
+            fill_qty: 50, time_bucket: 9, order_id: OrderId(9), order_hash: [0x55; 32], side: Side::Bid,
+            pid: [0x66; 32], is_maker: true, fee: 1, fill_salt: Some([0x77; 32]),
+        }
+    }
+
+    fn market() -> MarketParams {
+        MarketParams {
+            pair_id: PairId(3), price_tick: 10, size_step: 5, notional_min: 100, notional_max: u128::MAX,
+            maker_bps: 2, taker_bps: 5, status: MarketStatus::CancelOnly, clearing: ClearingMode::UniformPrice,
+            allocation: AllocationPolicy::FifoProRata { fifo_pct: 40 }, stp: StpMode::DecrementAndCancel,
+        }
+    }
+
+    /// Named encodings of the samples above; must match testdata byte for byte.
+    fn encoded_samples() -> Vec<(&'static str, Vec<u8>)> {
+        let stop = StopTrigger { kind: StopKind::Limit, trigger_tick: 990, triggered: false };
+        vec![
+            ("order", encode_order(&order(None, None))),
+            ("order_stop_iceberg", encode_order(&order(Some(stop), Some(10)))),
+            ("fill", encode_fill(&fill(None))),
+            ("fill_salted", encode_fill(&fill(Some([0x88; 32])))),
+            ("leg", encode_leg(&leg())),
+            ("market", encode_market(&market())),
+            ("residual", encode_residual(&OrderResidual {
+                order_id: OrderId(7), remaining_before: 50, remaining_after: 20, now_filled: false,
+            })),
+            ("cancel", encode_cancel(&OrderCancel { order_id: OrderId(8), cancelled_qty: 5, reason: CancelReason::SelfTrade })),
+            ("trigger", encode_trigger(&OrderTrigger { order_id: OrderId(9), ingest_seq: 40, last_price: 985 })),
+            ("refill", encode_refill(&OrderRefill { order_id: OrderId(10), ingest_seq: 41 })),
+        ]
+    }
+
+    /// Decode with the decoder named by the vector, then encode again.
+    fn reencode(name: &str, bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        Ok(match name.split('_').next().unwrap() {
+            "order" => encode_order(&decode_order(bytes)?),
+            "fill" => encode_fill(&decode_fill(bytes)?),
+            "leg" => encode_leg(&decode_leg(bytes)?),
+            "market" => encode_market(&decode_market(bytes)?),
+            "residual" => encode_residual(&decode_residual(bytes)?),
+            "cancel" => encode_cancel(&decode_cancel(bytes)?),
+            "trigger" => encode_trigger(&decode_trigger(bytes)?),
+            "refill" => encode_refill(&decode_refill(bytes)?),
+            other => panic!("no decoder for {other}"),
+        })
+    }
+
+    #[test]
+    fn golden_vectors_are_frozen() {
+        let golden: serde_json::Value = serde_json::from_str(include_str!("../testdata/encoding-v1.json")).unwrap();
+        assert_eq!(golden["version"], ENCODING_VERSION);
+        let vectors = golden["vectors"].as_object().unwrap();
+        let samples = encoded_samples();
+        assert_eq!(vectors.len(), samples.len());
+        for (name, bytes) in samples {
+            assert_eq!(vectors[name]["hex"].as_str(), Some(hex(&bytes).as_str()), "{name}");
+            assert_eq!(reencode(name, &bytes), Ok(bytes), "{name}");
+        }
+    }
+
+    #[test]
+    fn records_have_fixed_lengths() {
+        assert_eq!(encode_order(&order(None, None)).len(), ORDER_LEN);
+        assert_eq!(encode_fill(&fill(None)).len(), FILL_LEN);
+        assert_eq!(encode_fill(&fill(Some([1; 32]))).len(), FILL_LEN);
+        assert_eq!(encode_leg(&leg()).len(), LEG_LEN);
+        assert_eq!(encode_market(&market()).len(), MARKET_LEN);
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        let bytes = encode_fill(&fill(None));
+        let mut v2 = bytes.clone();
+        v2[0] = 2;
+        assert_eq!(decode_fill(&v2).err(), Some(DecodeError::UnsupportedVersion(2)));
+        assert_eq!(decode_fill(&bytes[..FILL_LEN - 1]).err(), Some(DecodeError::Truncated));
+        assert_eq!(decode_fill(&[bytes.as_slice(), &[0]].concat()).err(), Some(DecodeError::TrailingBytes(1)));
+
+        // absent salt with a non-zero payload
+        let mut padded = bytes.clone();
+        padded[FILL_LEN - 1] = 1;
+        assert!(matches!(decode_fill(&padded), Err(DecodeError::Invalid { field: "fill_salt", .. })));
+        // maker_side sits right after the four hashes
+        let mut side = bytes;
+        side[1 + 8 * 7 + 4 + 32 * 4] = 2;
+        assert_eq!(decode_fill(&side).err(), Some(DecodeError::Invalid { field: "maker_side", value: 2 }));
+
+        // a present display_qty of 0 is kept apart from no iceberg
+        let iceberg = encode_order(&order(None, Some(0)));
+        assert_ne!(iceberg, encode_order(&order(None, None)));
+        assert_eq!(decode_order(&iceberg).unwrap().display_qty, Some(0));
+
+        let mut stopless = encode_order(&order(None, None));
+        stopless[ORDER_LEN - 9 - 8 - 8] = 1; // trigger_tick without a stop kind
+        assert!(matches!(decode_order(&stopless), Err(DecodeError::Invalid { field: "stop_kind", .. })));
+    }
+
+    fn side() -> impl Strategy<Value = Side> {
+        prop::sample::select(SIDES.to_vec())
+    }
+
+    fn arb_order() -> impl Strategy<Value = Order> {
+        let stop = prop::option::of((prop::bool::ANY, any::<u64>(), any::<bool>())).prop_map(|s| {
+            s.map(|(market, trigger_tick, triggered)| StopTrigger {
+                kind: if market { StopKind::Market } else { StopKind::Limit },
+                trigger_tick,
+                triggered,
+            })
+        });
+        (
+            (any::<u64>(), any::<[u8; 32]>(), any::<u32>(), side(), any::<u64>(), any::<u64>(), any::<u64>()),
+            (any::<u32>(), any::<u64>(), any::<u64>(), prop::sample::select(TIFS.to_vec()), stop, any::<Option<u64>>()),
+        )
+            .prop_map(|((id, hash, pair, side, price_tick, amount, remaining), (tb, nonce, seq, tif, stop, display_qty))| Order {
+                order_id: OrderId(id), order_hash: hash, pair_id: PairId(pair), side, price_tick, amount, remaining,
+                time_bucket: tb, nonce, ingest_seq: seq, tif, stop, display_qty,
+            })
+    }
+
+    fn arb_fill() -> impl Strategy<Value = FillDraft> {
+        (
+            (any::<u64>(), any::<u64>(), any::<u32>(), any::<u64>(), any::<u64>(), any::<u32>(), any::<u64>(), any::<u64>()),
+            (any::<[[u8; 32]; 4]>(), side(), any::<u128>(), any::<u128>(), any::<Option<[u8; 32]>>()),
+        )
+            .prop_map(|((batch_id, match_id, pair, price_tick, fill_qty, time_bucket, buyer, seller), (h, maker_side, maker_fee, taker_fee, fill_salt))| FillDraft {
+                batch_id, match_id, pair_id: PairId(pair), price_tick, fill_qty, time_bucket,
+                buyer_order_id: OrderId(buyer), seller_order_id: OrderId(seller), buyer_order_hash: h[0],
+                seller_order_hash: h[1], buyer_pid: h[2], seller_pid: h[3], maker_side, maker_fee, taker_fee, fill_salt,
+            })
+    }
+
+    fn arb_leg() -> impl Strategy<Value = FillLeg> {
+        (
+            (any::<u64>(), any::<u64>(), any::<u64>(), any::<u32>(), any::<u64>(), any::<u64>(), any::<u32>()),
+            (any::<u64>(), any::<[u8; 32]>(), side(), any::<[u8; 32]>(), any::<bool>(), any::<u128>(), any::<Option<[u8; 32]>>()),
+        )
+            .prop_map(|((batch_id, match_id, group_id, pair, price_tick, fill_qty, time_bucket), (id, order_hash, side, pid, is_maker, fee, fill_salt))| FillLeg {
+                batch_id, match_id, group_id, pair_id: PairId(pair), price_tick, fill_qty, time_bucket,
+                order_id: OrderId(id), order_hash, side, pid, is_maker, fee, fill_salt,
+            })
+    }
+
+    fn arb_market() -> impl Strategy<Value = MarketParams> {
+        let allocation = prop_oneof![
+            Just(AllocationPolicy::Fifo),
+            Just(AllocationPolicy::ProRata),
+            any::<u8>().prop_map(|fifo_pct| AllocationPolicy::FifoProRata { fifo_pct }),
+        ];
+        (
+            (any::<u32>(), any::<u64>(), any::<u64>(), any::<u128>(), any::<u128>(), any::<u16>(), any::<u16>()),
+            (
+                prop::sample::select(STATUSES.to_vec()),
+                prop::sample::select(CLEARINGS.to_vec()),
+                allocation,
+                prop::sample::select(STPS.to_vec()),
+            ),
+        )
+            .prop_map(|((pair, price_tick, size_step, notional_min, notional_max, maker_bps, taker_bps), (status, clearing, allocation, stp))| MarketParams {
+                pair_id: PairId(pair), price_tick, size_step, notional_min, notional_max, maker_bps, taker_bps,
+                status, clearing, allocation, stp,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn orders_round_trip(o in arb_order()) {
+            let bytes = encode_order(&o);
+            prop_assert_eq!(bytes.len(), ORDER_LEN);
+            prop_assert_eq!(encode_order(&decode_order(&bytes).unwrap()), bytes);
+        }
+
+        #[test]
+        fn fills_round_trip(f in arb_fill()) {
+            let bytes = encode_fill(&f);
+            prop_assert_eq!(bytes.len(), FILL_LEN);
+            prop_assert_eq!(encode_fill(&decode_fill(&bytes).unwrap()), bytes);
+        }
+
+        #[test]
+        fn legs_round_trip(l in arb_leg()) {
+            let bytes = encode_leg(&l);
+            prop_assert_eq!(bytes.len(), LEG_LEN);
+            prop_assert_eq!(encode_leg(&decode_leg(&bytes).unwrap()), bytes);
+        }
+
+        #[test]
+        fn markets_round_trip(m in arb_market()) {
+            let bytes = encode_market(&m);
+            prop_assert_eq!(bytes.len(), MARKET_LEN);
+            prop_assert_eq!(encode_market(&decode_market(&bytes).unwrap()), bytes);
+        }
+
+        #[test]
+        fn small_records_round_trip(
+            (id, a, b, flag) in any::<(u64, u64, u64, bool)>(),
+            reason in prop::sample::select(CANCEL_REASONS.to_vec()),
+        ) {
+            let r = OrderResidual { order_id: OrderId(id), remaining_before: a, remaining_after: b, now_filled: flag };
+            prop_assert_eq!(encode_residual(&decode_residual(&encode_residual(&r)).unwrap()), encode_residual(&r));
+            let c = OrderCancel { order_id: OrderId(id), cancelled_qty: a, reason };
+            prop_assert_eq!(encode_cancel(&decode_cancel(&encode_cancel(&c)).unwrap()), encode_cancel(&c));
+            let t = OrderTrigger { order_id: OrderId(id), ingest_seq: a, last_price: b };
+            prop_assert_eq!(encode_trigger(&decode_trigger(&encode_trigger(&t)).unwrap()), encode_trigger(&t));
+            let f = OrderRefill { order_id: OrderId(id), ingest_seq: a };
+            prop_assert_eq!(encode_refill(&decode_refill(&encode_refill(&f)).unwrap()), encode_refill(&f));
+        }
+
+        /// Anything the decoder accepts is canonical: it encodes back to the
+        /// same bytes.
+        #[test]
+        fn accepted_bytes_are_canonical(mut bytes in prop::collection::vec(any::<u8>(), FILL_LEN)) {
+            bytes[0] = ENCODING_VERSION;
+            if let Ok(f) = decode_fill(&bytes) {
+                prop_assert_eq!(encode_fill(&f), bytes);
+            }
+        }
+    }
+}
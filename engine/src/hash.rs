@@ -0,0 +1,226 @@
+//! Hash interface for PIDs, commitments and plan digests. The sequencer and
+//! the zk guest plug in the same backend, so both sides agree on every
+//! digest; `HashScheme` names the backend in block headers.
+use core::{fmt, str::FromStr};
+
+pub trait Hasher {
+    fn h_bytes(&self, domain_tag: u64, bytes: &[u8]) -> [u8; 32];
+    fn h2(&self, domain_tag: u64, a: [u8; 32], b: [u8; 32]) -> [u8; 32];
+    /// One digest over a short list of 32-byte values (PIDs).
+    fn hash_many32(&self, domain_tag: u64, elems: &[[u8; 32]]) -> [u8; 32];
+}
+
+/// Which function produced a block's commitments. The id is what goes into
+/// headers and encodings, so existing ids never change meaning.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashScheme {
+    Blake3,        // fast on the host
+    PoseidonBn254, // circomlib-compatible, for SNARK verifiers
+    Sha256,        // precompiled in the zkVM
+}
+
+impl HashScheme {
+    pub const ALL: [HashScheme; 3] = [HashScheme::Blake3, HashScheme::PoseidonBn254, HashScheme::Sha256];
+
+    pub fn id(self) -> u16 {
+        match self {
+            HashScheme::Blake3 => 1,
+            HashScheme::PoseidonBn254 => 2,
+            HashScheme::Sha256 => 3,
+        }
+    }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashScheme::Blake3 => "blake3",
+            HashScheme::PoseidonBn254 => "poseidon-bn254",
+            HashScheme::Sha256 => "sha256",
+        }
+    }
+}
+
+impl fmt::Display for HashScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnknownHashScheme;
+
+impl FromStr for HashScheme {
+    type Err = UnknownHashScheme;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|x| x.name().eq_ignore_ascii_case(s)).ok_or(UnknownHashScheme)
+    }
+}
+
+/// Byte-oriented hashes (Blake3, SHA-256) all hash `le64(tag) || input`;
+/// `h2` and `hash_many32` concatenate their 32-byte inputs.
+#[cfg(any(feature = "blake3", feature = "sha256"))]
+macro_rules! byte_hasher {
+    ($name:ident, $digest:expr) => {
+        impl Hasher for $name {
+            fn h_bytes(&self, domain_tag: u64, bytes: &[u8]) -> [u8; 32] {
+                $digest(&[&domain_tag.to_le_bytes()[..], bytes])
+            }
+            fn h2(&self, domain_tag: u64, a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+                $digest(&[&domain_tag.to_le_bytes()[..], &a, &b])
+            }
+            fn hash_many32(&self, domain_tag: u64, elems: &[[u8; 32]]) -> [u8; 32] {
+                let tag = domain_tag.to_le_bytes();
+                let mut parts: alloc::vec::Vec<&[u8]> = alloc::vec![&tag[..]];
+                parts.extend(elems.iter().map(|e| &e[..]));
+                $digest(&parts)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "blake3")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Blake3Hasher;
+
+#[cfg(feature = "blake3")]
+fn blake3_parts(parts: &[&[u8]]) -> [u8; 32] {
+    let mut h = blake3::Hasher::new();
+    parts.iter().for_each(|p| { h.update(p); });
+    *h.finalize().as_bytes()
+}
+
+#[cfg(feature = "blake3")]
+byte_hasher!(Blake3Hasher, blake3_parts);
+
+#[cfg(feature = "sha256")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Sha256Hasher;
+
+#[cfg(feature = "sha256")]
+fn sha256_parts(parts: &[&[u8]]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    let mut h = Sha256::new();
+    parts.iter().for_each(|p| h.update(p));
+    h.finalize().into()
+}
+
+#[cfg(feature = "sha256")]
+byte_hasher!(Sha256Hasher, sha256_parts);
+
+/// Backend picked at runtime from a `HashScheme` (configuration, or the
+/// scheme recorded in a header being verified).
+#[cfg(all(feature = "blake3", feature = "sha256", feature = "poseidon"))]
+#[derive(Copy, Clone, Debug)]
+pub enum HashBackend {
+    Blake3(Blake3Hasher),
+    PoseidonBn254(crate::poseidon::PoseidonBn254),
+    Sha256(Sha256Hasher),
+}
+
+#[cfg(all(feature = "blake3", feature = "sha256", feature = "poseidon"))]
+impl HashBackend {
+    pub fn new(scheme: HashScheme) -> Self {
+        match scheme {
+            HashScheme::Blake3 => HashBackend::Blake3(Blake3Hasher),
+            HashScheme::PoseidonBn254 => HashBackend::PoseidonBn254(crate::poseidon::PoseidonBn254),
+            HashScheme::Sha256 => HashBackend::Sha256(Sha256Hasher),
+        }
+    }
+
+    pub fn scheme(&self) -> HashScheme {
+        match self {
+            HashBackend::Blake3(_) => HashScheme::Blake3,
+            HashBackend::PoseidonBn254(_) => HashScheme::PoseidonBn254,
+            HashBackend::Sha256(_) => HashScheme::Sha256,
+        }
+    }
+}
+
+#[cfg(all(feature = "blake3", feature = "sha256", feature = "poseidon"))]
+impl Hasher for HashBackend {
+    fn h_bytes(&self, domain_tag: u64, bytes: &[u8]) -> [u8; 32] {
+        match self {
+            HashBackend::Blake3(h) => h.h_bytes(domain_tag, bytes),
+            HashBackend::PoseidonBn254(h) => h.h_bytes(domain_tag, bytes),
+            HashBackend::Sha256(h) => h.h_bytes(domain_tag, bytes),
+        }
+    }
+    fn h2(&self, domain_tag: u64, a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        match self {
+            HashBackend::Blake3(h) => h.h2(domain_tag, a, b),
+            HashBackend::PoseidonBn254(h) => h.h2(domain_tag, a, b),
+            HashBackend::Sha256(h) => h.h2(domain_tag, a, b),
+        }
+    }
+    fn hash_many32(&self, domain_tag: u64, elems: &[[u8; 32]]) -> [u8; 32] {
+        match self {
+            HashBackend::Blake3(h) => h.hash_many32(domain_tag, elems),
+            HashBackend::PoseidonBn254(h) => h.hash_many32(domain_tag, elems),
+            HashBackend::Sha256(h) => h.hash_many32(domain_tag, elems),
+        }
+    }
+}
+
+pub mod domains {
+    // "plan" + list tag
+    pub const PLAN_HEADER: u64   = 0x706c_616e_0000_0001;
+    pub const PLAN_FILL: u64     = 0x706c_616e_0000_0002;
+    pub const PLAN_RESIDUAL: u64 = 0x706c_616e_0000_0003;
+    pub const PLAN_CANCEL: u64   = 0x706c_616e_0000_0004;
+    pub const PLAN_TRIGGER: u64  = 0x706c_616e_0000_0005;
+    pub const PLAN_REFILL: u64   = 0x706c_616e_0000_0006;
+    pub const PLAN_LEG: u64      = 0x706c_616e_0000_0007;
+    pub const PLAN_ACC: u64      = 0x706c_616e_0000_00ff;
+    // "mrkl" + 1: seals a Merkle top node with the leaf count
+    pub const MERKLE_ROOT: u64   = 0x6d72_6b6c_0000_0001;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheme_ids_and_names_round_trip() {
+        for s in HashScheme::ALL {
+            assert_eq!(HashScheme::from_id(s.id()), Some(s));
+            assert_eq!(s.name().parse::<HashScheme>(), Ok(s));
+        }
+        assert_eq!(HashScheme::from_id(0), None);
+        assert_eq!("SHA256".parse::<HashScheme>(), Ok(HashScheme::Sha256));
+        assert!("md5".parse::<HashScheme>().is_err());
+    }
+
+    #[cfg(feature = "sha256")]
+    #[test]
+    fn sha256_hashes_tag_then_input() {
+        // sha256(le64(0) || "abc")
+        let mut input = [0u8; 11];
+        input[8..].copy_from_slice(b"abc");
+        let want: [u8; 32] = {
+            use sha2::{Digest, Sha256};
+            Sha256::digest(input).into()
+        };
+        assert_eq!(Sha256Hasher.h_bytes(0, b"abc"), want);
+        assert_eq!(Sha256Hasher.h2(9, [1; 32], [2; 32]), Sha256Hasher.hash_many32(9, &[[1; 32], [2; 32]]));
+    }
+
+    #[cfg(all(feature = "blake3", feature = "sha256", feature = "poseidon"))]
+    #[test]
+    fn backends_differ_and_report_their_scheme() {
+        let digests: alloc::vec::Vec<[u8; 32]> = HashScheme::ALL
+            .into_iter()
+            .map(|s| {
+                let b = HashBackend::new(s);
+                assert_eq!(b.scheme(), s);
+                b.h_bytes(domains::PLAN_HEADER, b"block")
+            })
+            .collect();
+        assert_ne!(digests[0], digests[1]);
+        assert_ne!(digests[1], digests[2]);
+        assert_ne!(digests[0], digests[2]);
+    }
+}
@@ -0,0 +1,409 @@
+//! Implied liquidity across a triangle of markets.
+//!
+//! With A/C, A/B and B/C all listed, a bid on A/C can be filled by buying A
+//! on A/B and paying for that B on B/C, and an ask on A/C by the reverse.
+//! Either way one order from each market trades, so the same cycle also
+//! lets A/C and B/C orders fill one on A/B and so on. Prices are quote
+//! units per base unit, so the A/C price of a cycle is the product of its
+//! A/B and B/C prices.
+use crate::{
+    types::Order, FillLeg, MarketParams, MarketStatus, OrderRefill, OwnerMap, PairId, Side, TimeInForce,
+    batch::match_id_base,
+    hash::Hasher,
+    pid::derive_pid,
+    r#match::{fee_amount, ExecutionPlan},
+};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::cmp::Reverse;
+
+/// Three markets closing a cycle: `outright` is A/C, `first` A/B and
+/// `second` B/C.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImpliedRoute {
+    pub outright: PairId,
+    pub first: PairId,
+    pub second: PairId,
+}
+
+impl ImpliedRoute {
+    fn pairs(&self) -> [PairId; 3] {
+        [self.outright, self.first, self.second]
+    }
+}
+
+/// Run implied matching over what rests after outright matching.
+///
+/// Routes are worked in the order given; each needs three distinct Active
+/// markets that all have a plan in `plans`. Within a route, cycles that buy
+/// A on A/C go first, then cycles that sell it, each time between the best
+/// order of every book (price, then `ingest_seq`, then `order_id`). A side
+/// stops at the first cycle that cannot trade.
+///
+/// The latest of the three orders is the taker: the other two trade at
+/// their own prices and the taker's price follows from them, rounded in
+/// the makers' favour. Implied prints need not sit on a tick. A post-only
+/// taker, two orders of one owner or a missing owner end the side, as does
+/// a size that cannot be cut to every market's `size_step`. Makers offer
+/// their displayed slice; an iceberg whose slice a leg uses up shows the
+/// next one from the back of its level, as in outright matching, under an
+/// `ingest_seq` past every one its market has used so far.
+///
+/// Legs go into the plan of their market with match ids continuing that
+/// market's range, residuals and refills are updated to match and
+/// `resting` loses the traded quantity.
+#[allow(clippy::too_many_arguments)]
+pub fn match_implied<H: Hasher>(
+    batch_id: u64,
+    routes: &[ImpliedRoute],
+    markets: &[MarketParams],
+    resting: &mut BTreeMap<PairId, Vec<Order>>,
+    plans: &mut [ExecutionPlan],
+    owners: &OwnerMap,
+    hasher: &H,
+    use_fill_salt: bool,
+    mut fill_salt_fn: impl FnMut(u64, u64) -> [u8; 32],
+) {
+    for route in routes {
+        let pairs = route.pairs();
+        if pairs[0] == pairs[1] || pairs[0] == pairs[2] || pairs[1] == pairs[2] {
+            continue;
+        }
+        let mkts = pairs.map(|p| markets.iter().find(|m| m.pair_id == p && m.status == MarketStatus::Active));
+        let idx = pairs.map(|p| plans.iter().position(|pl| pl.pair_id == p));
+        let (Some(m0), Some(m1), Some(m2)) = (mkts[0], mkts[1], mkts[2]) else { continue };
+        let (Some(i0), Some(i1), Some(i2)) = (idx[0], idx[1], idx[2]) else { continue };
+
+        let books = pairs.map(|p| resting.remove(&p).unwrap_or_default());
+        let next_seq = [0, 1, 2].map(|k| {
+            let plan = &plans[[i0, i1, i2][k]];
+            let stamped = plan.triggered.iter().map(|t| t.ingest_seq).chain(plan.refills.iter().map(|r| r.ingest_seq));
+            books[k].iter().map(|o| o.ingest_seq).chain(stamped).max().unwrap_or(0)
+        });
+        let mut cycle = Cycle {
+            batch_id,
+            markets: [m0, m1, m2],
+            books,
+            match_seq: [i0, i1, i2].map(|i| plans[i].last_match_id(match_id_base(plans[i].pair_id))),
+            next_seq,
+            owners,
+            hasher,
+            use_fill_salt,
+            fill_salt_fn: &mut fill_salt_fn,
+            legs: Vec::new(),
+            refills: Vec::new(),
+        };
+        for dir in [Side::Bid, Side::Ask] {
+            while cycle.trade(dir) {}
+        }
+
+        let Cycle { books, legs, refills, .. } = cycle;
+        for (leg, before) in legs {
+            let i = [i0, i1, i2][pairs.iter().position(|p| *p == leg.pair_id).unwrap_or(0)];
+            plans[i].push_leg(leg, before);
+        }
+        for (k, refill) in refills {
+            plans[[i0, i1, i2][k]].refills.push(refill);
+        }
+        for i in [i0, i1, i2] {
+            plans[i].canonicalize();
+        }
+        for (p, book) in pairs.into_iter().zip(books) {
+            resting.insert(p, book);
+        }
+    }
+}
+
+/// One route's books while its cycles are traded. Index 0 is the outright
+/// market, 1 and 2 the legs.
+struct Cycle<'a, H, F> {
+    batch_id: u64,
+    markets: [&'a MarketParams; 3],
+    books: [Vec<Order>; 3],
+    match_seq: [u64; 3],
+    next_seq: [u64; 3], // last ingest_seq each market has handed out
+    owners: &'a OwnerMap,
+    hasher: &'a H,
+    use_fill_salt: bool,
+    fill_salt_fn: &'a mut F,
+    legs: Vec<(FillLeg, u64)>, // with the order's remaining before the leg
+    refills: Vec<(usize, OrderRefill)>, // with the book they happened in
+}
+
+/// Best order on `side`: price first, then arrival.
+fn best(book: &[Order], side: Side) -> Option<usize> {
+    let on_side = book.iter().enumerate().filter(|(_, o)| o.side == side && o.remaining > 0 && !o.is_pending_stop());
+    match side {
+        Side::Bid => on_side.min_by_key(|(_, o)| (Reverse(o.price_tick), o.ingest_seq, o.order_id)),
+        Side::Ask => on_side.min_by_key(|(_, o)| (o.price_tick, o.ingest_seq, o.order_id)),
+    }
+    .map(|(i, _)| i)
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn lcm(a: u64, b: u64) -> Option<u64> {
+    (a / gcd(a, b)).checked_mul(b)
+}
+
+/// Prices `[A/C, A/B, B/C]` for a cycle where the outright order is on
+/// `dir` and `taker` (0..3) sets no price of its own, or `None` if the
+/// three limits do not overlap.
+fn cycle_prices(dir: Side, p: [u64; 3], taker: usize) -> Option<[u64; 3]> {
+    let (q1, q2) = match (dir, taker) {
+        // outright bid against two asks: q1 >= p1, q2 >= p2, q1 * q2 <= p0
+        (Side::Bid, 0) => (p[1], p[2]),
+        (Side::Bid, 1) => (p[0].checked_div(p[2])?, p[2]),
+        (Side::Bid, _) => (p[1], p[0].checked_div(p[1])?),
+        // outright ask against two bids: q1 <= p1, q2 <= p2, q1 * q2 >= p0
+        (Side::Ask, 0) => (p[1], p[2]),
+        (Side::Ask, 1) => ((p[2] > 0).then(|| p[0].div_ceil(p[2]))?, p[2]),
+        (Side::Ask, _) => (p[1], (p[1] > 0).then(|| p[0].div_ceil(p[1]))?),
+    };
+    let q0 = q1.checked_mul(q2)?;
+    let ok = match dir {
+        Side::Bid => q1 >= p[1] && q2 >= p[2] && q0 <= p[0],
+        Side::Ask => q1 <= p[1] && q2 <= p[2] && q0 >= p[0],
+    };
+    (ok && q1 > 0).then_some([q0, q1, q2])
+}
+
+impl<H: Hasher, F: FnMut(u64, u64) -> [u8; 32]> Cycle<'_, H, F> {
+    /// Trade one cycle with the outright order on `dir`. Returns false when
+    /// none can trade.
+    fn trade(&mut self, dir: Side) -> bool {
+        let leg_side = match dir { Side::Bid => Side::Ask, Side::Ask => Side::Bid };
+        let (Some(i0), Some(i1), Some(i2)) =
+            (best(&self.books[0], dir), best(&self.books[1], leg_side), best(&self.books[2], leg_side))
+        else {
+            return false;
+        };
+        let at = [i0, i1, i2];
+        let o: [&Order; 3] = [&self.books[0][i0], &self.books[1][i1], &self.books[2][i2]];
+
+        let taker = (0..3).max_by_key(|&k| (o[k].ingest_seq, o[k].order_id)).unwrap_or(0);
+        if o[taker].tif == TimeInForce::PostOnly {
+            return false;
+        }
+        let (Some(pk0), Some(pk1), Some(pk2)) =
+            (self.owners.get(&o[0].order_id.0), self.owners.get(&o[1].order_id.0), self.owners.get(&o[2].order_id.0))
+        else {
+            return false;
+        };
+        if pk0 == pk1 || pk0 == pk2 || pk1 == pk2 {
+            return false;
+        }
+        let pks = [*pk0, *pk1, *pk2];
+
+        let Some(px) = cycle_prices(dir, o.map(|x| x.price_tick), taker) else { return false };
+
+        // sizes are in A on A/C and A/B, in B on B/C
+        let avail = |k: usize| if k == taker { o[k].remaining } else { o[k].displayed() };
+        let max_a = avail(0).min(avail(1)).min(avail(2) / px[1]);
+        let steps = self.markets.map(|m| m.size_step.max(1));
+        let lot = lcm(steps[0], steps[1])
+            .and_then(|l| lcm(l, steps[2] / gcd(steps[2], px[1])));
+        let Some(lot) = lot else { return false };
+        let qty_a = max_a / lot * lot;
+        if qty_a == 0 {
+            return false;
+        }
+        let qty = [qty_a, qty_a, qty_a * px[1]];
+
+        let shown = o.map(|x| x.displayed());
+        let time_bucket = o.iter().map(|x| x.time_bucket).max().unwrap_or(0);
+        let ids = o.map(|x| (x.order_id, x.order_hash, x.side, x.remaining));
+        let group_id = self.match_seq[0] + 1;
+        for k in 0..3 {
+            self.match_seq[k] += 1;
+            let match_id = self.match_seq[k];
+            let salt = if self.use_fill_salt { Some((self.fill_salt_fn)(self.batch_id, match_id)) } else { None };
+            let m = self.markets[k];
+            let bps = if k == taker { m.taker_bps } else { m.maker_bps };
+            let (order_id, order_hash, side, before) = ids[k];
+            let leg = FillLeg {
+                batch_id: self.batch_id,
+                match_id,
+                group_id,
+                pair_id: m.pair_id,
+                price_tick: px[k],
+                fill_qty: qty[k],
+                time_bucket,
+                order_id,
+                order_hash,
+                side,
+                pid: derive_pid(self.hasher, &pks[k], self.batch_id, match_id, salt),
+                is_maker: k != taker,
+                fee: fee_amount(px[k], qty[k], bps),
+                fill_salt: salt,
+            };
+            self.legs.push((leg, before));
+
+            let order = &mut self.books[k][at[k]];
+            order.remaining = before - qty[k];
+            if order.remaining == 0 {
+                self.books[k].remove(at[k]);
+            } else if k != taker && order.display_qty.is_some() && qty[k] == shown[k] {
+                self.next_seq[k] += 1;
+                order.ingest_seq = self.next_seq[k];
+                self.refills.push((k, OrderRefill { order_id, ingest_seq: self.next_seq[k] }));
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{types::*, pid::StubPoseidon, match_market_from};
+    use std::collections::BTreeMap;
+
+    const AC: PairId = PairId(1);
+    const AB: PairId = PairId(2);
+    const BC: PairId = PairId(3);
+    const ROUTE: ImpliedRoute = ImpliedRoute { outright: AC, first: AB, second: BC };
+
+    fn market(pair: PairId) -> MarketParams {
+        MarketParams {
+            pair_id: pair,
+            price_tick: 1, size_step: 1,
+            notional_min: 0, notional_max: u128::MAX,
+            maker_bps: 10, taker_bps: 20, status: MarketStatus::Active,
+            clearing: ClearingMode::Continuous,
+            allocation: AllocationPolicy::Fifo,
+            stp: StpMode::None,
+        }
+    }
+
+    fn order(id: u64, pair: PairId, side: Side, px: u64, qty: u64) -> Order {
+        Order {
+            order_id: OrderId(id), order_hash: [id as u8; 32], pair_id: pair, side,
+            price_tick: px, amount: qty, remaining: qty, time_bucket: 0, nonce: id,
+            ingest_seq: id, tif: TimeInForce::Gtc, stop: None, display_qty: None,
+        }
+    }
+
+    /// Match each market outright, then run the route.
+    fn run(orders: Vec<Order>) -> (Vec<ExecutionPlan>, BTreeMap<PairId, Vec<Order>>) {
+        let markets = [market(AC), market(AB), market(BC)];
+        let owners: OwnerMap = orders.iter().map(|o| (o.order_id.0, [o.order_id.0 as u8; 32])).collect();
+        let mut plans = Vec::new();
+        let mut resting = BTreeMap::new();
+        for m in &markets {
+            let ords: Vec<Order> = orders.iter().filter(|o| o.pair_id == m.pair_id).cloned().collect();
+            let plan = match_market_from(
+                m.pair_id, 7, match_id_base(m.pair_id), 100, m, ords.clone(), &owners, &StubPoseidon,
+                false, |_, _| [0; 32],
+            ).unwrap();
+            resting.insert(m.pair_id, plan.resting_after(ords));
+            plans.push(plan);
+        }
+        match_implied(7, &[ROUTE], &markets, &mut resting, &mut plans, &owners, &StubPoseidon, false, |_, _| [0; 32]);
+        (plans, resting)
+    }
+
+    #[test]
+    fn outright_bid_fills_against_two_legs() {
+        // buy 5 A on A/C at 210 C; A/B asks 10 B per A, B/C asks 20 C per B
+        let (plans, resting) = run(vec![
+            order(1, AB, Side::Ask, 10, 8),
+            order(2, BC, Side::Ask, 20, 100),
+            order(3, AC, Side::Bid, 210, 5),
+        ]);
+        let legs: Vec<&FillLeg> = plans.iter().flat_map(|p| &p.legs).collect();
+        assert_eq!(legs.len(), 3);
+        assert!(legs.iter().all(|l| l.group_id == match_id_base(AC) + 1));
+
+        let ac = &plans[0].legs[0];
+        assert_eq!((ac.order_id, ac.side, ac.price_tick, ac.fill_qty, ac.is_maker), (OrderId(3), Side::Bid, 200, 5, false));
+        assert_eq!(ac.fee, fee_amount(200, 5, 20));
+        let ab = &plans[1].legs[0];
+        assert_eq!((ab.side, ab.price_tick, ab.fill_qty, ab.is_maker), (Side::Ask, 10, 5, true));
+        let bc = &plans[2].legs[0];
+        assert_eq!((bc.side, bc.price_tick, bc.fill_qty, bc.match_id), (Side::Ask, 20, 50, match_id_base(BC) + 1));
+
+        // C paid on A/C equals C received on B/C; B bought equals B sold
+        assert_eq!(ac.price_tick * ac.fill_qty, bc.price_tick * bc.fill_qty);
+        assert_eq!(ab.price_tick * ab.fill_qty, bc.fill_qty);
+
+        let r = |p: &ExecutionPlan, id| p.residuals.iter().find(|r| r.order_id == OrderId(id)).copied().unwrap();
+        assert!(r(&plans[0], 3).now_filled);
+        assert_eq!(r(&plans[1], 1).remaining_after, 3);
+        assert_eq!(r(&plans[2], 2).remaining_after, 50);
+        assert!(resting[&AC].is_empty());
+        assert_eq!(resting[&AB][0].remaining, 3);
+    }
+
+    #[test]
+    fn leg_order_can_take_from_outright_and_other_leg() {
+        // A/B bid arrives last: A/C ask 300, B/C bid 20 -> A/B pays 15 at most
+        let (plans, _) = run(vec![
+            order(1, AC, Side::Ask, 300, 4),
+            order(2, BC, Side::Bid, 20, 1_000),
+            order(3, AB, Side::Bid, 16, 10),
+        ]);
+        let ab = &plans[1].legs[0];
+        assert_eq!((ab.order_id, ab.price_tick, ab.fill_qty, ab.is_maker), (OrderId(3), 15, 4, false));
+        assert_eq!(plans[0].legs[0].price_tick, 300);
+        assert_eq!(plans[2].legs[0].fill_qty, 60);
+    }
+
+    #[test]
+    fn iceberg_maker_refills_behind_its_level() {
+        // the iceberg's slice of 3 goes to the first cycle; the rest of the
+        // bid then takes the plain ask that was queued behind it
+        let (plans, resting) = run(vec![
+            Order { display_qty: Some(3), ..order(1, AB, Side::Ask, 10, 10) },
+            order(2, AB, Side::Ask, 10, 5),
+            order(3, BC, Side::Ask, 20, 100),
+            order(4, AC, Side::Bid, 210, 5),
+        ]);
+        let ab: Vec<_> = plans[1].legs.iter().map(|l| (l.order_id, l.fill_qty)).collect();
+        assert_eq!(ab, [(OrderId(1), 3), (OrderId(2), 2)]);
+        assert_eq!(plans[1].refills, [OrderRefill { order_id: OrderId(1), ingest_seq: 3 }]);
+        let iceberg = resting[&AB].iter().find(|o| o.order_id == OrderId(1)).unwrap();
+        assert_eq!((iceberg.remaining, iceberg.ingest_seq), (7, 3));
+    }
+
+    #[test]
+    fn no_cycle_when_prices_do_not_meet() {
+        let (plans, resting) = run(vec![
+            order(1, AB, Side::Ask, 10, 8),
+            order(2, BC, Side::Ask, 20, 100),
+            order(3, AC, Side::Bid, 199, 5),
+        ]);
+        assert!(plans.iter().all(|p| p.legs.is_empty()));
+        assert_eq!(resting.values().map(Vec::len).sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn outright_matching_goes_first() {
+        // the A/C ask takes the bid outright, nothing left to imply
+        let (plans, _) = run(vec![
+            order(1, AB, Side::Ask, 10, 8),
+            order(2, BC, Side::Ask, 20, 100),
+            order(3, AC, Side::Ask, 205, 5),
+            order(4, AC, Side::Bid, 210, 5),
+        ]);
+        assert_eq!(plans[0].fills.len(), 1);
+        assert!(plans.iter().all(|p| p.legs.is_empty()));
+    }
+
+    #[test]
+    fn same_owner_blocks_the_cycle() {
+        let markets = [market(AC), market(AB), market(BC)];
+        let orders = [order(1, AB, Side::Ask, 10, 8), order(2, BC, Side::Ask, 20, 100), order(3, AC, Side::Bid, 210, 5)];
+        let mut owners: OwnerMap = BTreeMap::new();
+        for o in &orders { owners.insert(o.order_id.0, [9; 32]); }
+        let mut resting: BTreeMap<PairId, Vec<Order>> =
+            orders.iter().map(|o| (o.pair_id, vec![o.clone()])).collect();
+        let mut plans: Vec<ExecutionPlan> = markets.iter().map(|m| match_market_from(
+            m.pair_id, 7, match_id_base(m.pair_id), 0, m, Vec::new(), &owners, &StubPoseidon, false, |_, _| [0; 32],
+        ).unwrap()).collect();
+        match_implied(7, &[ROUTE], &markets, &mut resting, &mut plans, &owners, &StubPoseidon, false, |_, _| [0; 32]);
+        assert!(plans.iter().all(|p| p.legs.is_empty()));
+    }
+}
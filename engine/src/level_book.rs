@@ -0,0 +1,470 @@
+use crate::{
+    Order, OrderId, OwnerMap, PairId, Side, MarketParams, TimeInForce,
+    hash::Hasher,
+    r#match::{match_market_from, ExecutionPlan, MatchError},
+};
+use alloc::{collections::{BTreeMap, BTreeSet, VecDeque}, vec, vec::Vec};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BookError {
+    DuplicateOrder(OrderId),
+    UnknownOrder(OrderId),
+    WrongPair(OrderId),
+    Empty(OrderId),     // inserted with nothing remaining
+    Match(MatchError),  // book is left as it was before matching
+}
+
+/// Long-lived book for one market, carried over between batches.
+///
+/// Each side maps price -> FIFO queue of order ids (by `ingest_seq`), and
+/// every resting order is reachable by id. Unlike `OrderBook`, which is
+/// rebuilt from a full snapshot, this one is updated in place, so a batch
+/// only costs the new orders plus whatever part of the book they cross.
+/// Pending stop orders are indexed but sit outside the price levels.
+#[derive(Clone, Debug)]
+pub struct LevelBook {
+    pair_id: PairId,
+    bids: BTreeMap<u64, VecDeque<OrderId>>,
+    asks: BTreeMap<u64, VecDeque<OrderId>>,
+    stops: BTreeMap<(u64, u64), OrderId>, // (ingest_seq, order_id) of pending stops
+    index: BTreeMap<OrderId, Order>,
+}
+
+impl LevelBook {
+    pub fn new(pair_id: PairId) -> Self {
+        Self { pair_id, bids: BTreeMap::new(), asks: BTreeMap::new(), stops: BTreeMap::new(), index: BTreeMap::new() }
+    }
+
+    /// Warm a book from an open-orders snapshot (any order).
+    pub fn from_orders(pair_id: PairId, orders: Vec<Order>) -> Result<Self, BookError> {
+        let mut book = Self::new(pair_id);
+        for o in orders.into_iter().filter(|o| o.remaining > 0) {
+            book.insert(o)?;
+        }
+        Ok(book)
+    }
+
+    pub fn pair_id(&self) -> PairId { self.pair_id }
+    pub fn len(&self) -> usize { self.index.len() }
+    pub fn is_empty(&self) -> bool { self.index.is_empty() }
+    pub fn get(&self, id: OrderId) -> Option<&Order> { self.index.get(&id) }
+
+    fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<u64, VecDeque<OrderId>> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    /// Rest an order at the back of its level, or at its `ingest_seq`
+    /// position if it is older than the level's tail (snapshot warm-up).
+    /// Pending stops are held aside until a batch triggers them.
+    pub fn insert(&mut self, o: Order) -> Result<(), BookError> {
+        if o.pair_id != self.pair_id {
+            return Err(BookError::WrongPair(o.order_id));
+        }
+        if o.remaining == 0 {
+            return Err(BookError::Empty(o.order_id));
+        }
+        if self.index.contains_key(&o.order_id) {
+            return Err(BookError::DuplicateOrder(o.order_id));
+        }
+
+        let key = (o.ingest_seq, o.order_id.0);
+        let (id, side, px) = (o.order_id, o.side, o.price_tick);
+        if o.is_pending_stop() {
+            self.stops.insert(key, id);
+            self.index.insert(id, o);
+            return Ok(());
+        }
+        self.index.insert(id, o);
+        let index = &self.index;
+        let queue = match side {
+            Side::Bid => self.bids.entry(px).or_default(),
+            Side::Ask => self.asks.entry(px).or_default(),
+        };
+        let seq_of = |q: &OrderId| (index[q].ingest_seq, q.0);
+        if queue.back().is_none_or(|b| seq_of(b) < key) {
+            queue.push_back(id);
+        } else {
+            let pos = queue.partition_point(|q| seq_of(q) < key);
+            queue.insert(pos, id);
+        }
+        Ok(())
+    }
+
+    /// Remove an order entirely; returns it as it stood.
+    pub fn cancel(&mut self, id: OrderId) -> Result<Order, BookError> {
+        let o = self.index.remove(&id).ok_or(BookError::UnknownOrder(id))?;
+        if o.is_pending_stop() {
+            self.stops.remove(&(o.ingest_seq, id.0));
+            return Ok(o);
+        }
+        let levels = self.levels_mut(o.side);
+        if let Some(queue) = levels.get_mut(&o.price_tick) {
+            queue.retain(|q| *q != id);
+            if queue.is_empty() {
+                levels.remove(&o.price_tick);
+            }
+        }
+        Ok(o)
+    }
+
+    /// Change price and/or remaining size.
+    ///
+    /// Shrinking at the same price keeps time priority. A price change or a
+    /// size increase re-queues the order behind its new level with
+    /// `requeue_seq` as its `ingest_seq`. Amending to zero cancels.
+    pub fn amend(
+        &mut self,
+        id: OrderId,
+        price_tick: u64,
+        remaining: u64,
+        requeue_seq: u64,
+    ) -> Result<(), BookError> {
+        let cur = self.index.get(&id).ok_or(BookError::UnknownOrder(id))?;
+        let Some(o) = cur.amended(price_tick, remaining, requeue_seq) else {
+            return self.cancel(id).map(|_| ());
+        };
+        if (o.price_tick, o.ingest_seq) == (cur.price_tick, cur.ingest_seq) {
+            self.index.insert(id, o);
+            return Ok(());
+        }
+        self.cancel(id)?;
+        self.insert(o)
+    }
+
+    /// Take `qty` off a resting order outside a matching pass (an implied
+    /// leg). It keeps its place in the queue and leaves the book once empty.
+    pub fn apply_fill(&mut self, id: OrderId, qty: u64) -> Result<(), BookError> {
+        let cur = self.index.get_mut(&id).ok_or(BookError::UnknownOrder(id))?;
+        if qty >= cur.remaining {
+            return self.cancel(id).map(|_| ());
+        }
+        cur.remaining -= qty;
+        Ok(())
+    }
+
+    /// Move a resting order behind its level under `ingest_seq` (an
+    /// iceberg refilled by an implied leg). Nothing moves if it is there
+    /// already.
+    pub fn requeue(&mut self, id: OrderId, ingest_seq: u64) -> Result<(), BookError> {
+        let cur = self.index.get(&id).ok_or(BookError::UnknownOrder(id))?;
+        if cur.ingest_seq == ingest_seq {
+            return Ok(());
+        }
+        let o = self.cancel(id)?;
+        self.insert(Order { ingest_seq, ..o })
+    }
+
+    /// Best price and visible quantity resting there.
+    pub fn best_bid(&self) -> Option<(u64, u64)> {
+        self.bids.iter().next_back().map(|(px, q)| (*px, self.level_qty(q)))
+    }
+    pub fn best_ask(&self) -> Option<(u64, u64)> {
+        self.asks.iter().next().map(|(px, q)| (*px, self.level_qty(q)))
+    }
+
+    /// Up to `n` levels from the top of `side`, best first.
+    pub fn depth(&self, side: Side, n: usize) -> Vec<(u64, u64)> {
+        let level = |(px, q): (&u64, &VecDeque<OrderId>)| (*px, self.level_qty(q));
+        match side {
+            Side::Bid => self.bids.iter().rev().take(n).map(level).collect(),
+            Side::Ask => self.asks.iter().take(n).map(level).collect(),
+        }
+    }
+
+    /// Visible quantity only; iceberg reserve stays hidden.
+    fn level_qty(&self, q: &VecDeque<OrderId>) -> u64 {
+        q.iter().map(|id| self.index[id].displayed()).sum()
+    }
+
+    /// All resting orders: bids best-first, then asks best-first, FIFO
+    /// within a level, then pending stops by `ingest_seq`. Deterministic,
+    /// so it can back a commitment.
+    pub fn snapshot(&self) -> Vec<Order> {
+        let bids = self.bids.values().rev().flatten();
+        let asks = self.asks.values().flatten();
+        let stops = self.stops.values();
+        bids.chain(asks).chain(stops).map(|id| self.index[id].clone()).collect()
+    }
+
+    /// Add `incoming` to the book and match.
+    ///
+    /// Only the crossed part of the book (bids at or above the best ask,
+    /// asks at or below the best bid) plus any IOC/FOK orders can change in
+    /// a batch, so just those orders are lifted out and run through
+    /// `match_market_from`; survivors go back with their new remaining.
+    /// A triggered stop can reach levels outside that region, so while any
+    /// stops are pending the whole book is lifted instead.
+    /// The plan equals `match_market` over the whole book. On a
+    /// `MatchError` nothing is matched and the book keeps `incoming`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn match_batch<H: Hasher>(
+        &mut self,
+        incoming: Vec<Order>,
+        market: &MarketParams,
+        batch_id: u64,
+        match_id_base: u64,
+        owner_map: &OwnerMap,
+        hasher: &H,
+        use_fill_salt: bool,
+        fill_salt_fn: impl FnMut(u64, u64) -> [u8; 32],
+    ) -> Result<ExecutionPlan, BookError> {
+        let mut transient = Vec::new();
+        for o in incoming {
+            if matches!(o.tif, TimeInForce::Ioc | TimeInForce::Fok) {
+                transient.push(o.order_id);
+            }
+            self.insert(o)?;
+        }
+
+        let trigger_seq_base = self.index.values().map(|o| o.ingest_seq).max().unwrap_or(0);
+        let mut region: Vec<OrderId> = Vec::new();
+        let mut seen: BTreeSet<OrderId> = BTreeSet::new();
+        if !self.stops.is_empty() {
+            let levels = self.bids.values().rev().chain(self.asks.values()).flatten();
+            region.extend(levels.chain(self.stops.values()).copied());
+        } else if let (Some((bb, _)), Some((ba, _))) = (self.best_bid(), self.best_ask()) {
+            if bb >= ba {
+                region.extend(self.bids.range(ba..).flat_map(|(_, q)| q.iter().copied()));
+                region.extend(self.asks.range(..=bb).flat_map(|(_, q)| q.iter().copied()));
+            }
+        }
+        seen.extend(region.iter().copied());
+        region.extend(transient.into_iter().filter(|id| seen.insert(*id)));
+
+        let mut lifted = Vec::with_capacity(region.len());
+        for id in region {
+            lifted.push(self.cancel(id)?);
+        }
+
+        let plan = match match_market_from(
+            self.pair_id, batch_id, match_id_base, trigger_seq_base, market, lifted.clone(),
+            owner_map, hasher, use_fill_salt, fill_salt_fn,
+        ) {
+            Ok(plan) => plan,
+            Err(e) => {
+                // incoming orders stay inserted; the caller decides what to drop
+                for o in lifted {
+                    self.insert(o)?;
+                }
+                return Err(BookError::Match(e));
+            }
+        };
+
+        for o in plan.resting_after(lifted) {
+            self.insert(o)?;
+        }
+        Ok(plan)
+    }
+
+    /// Continuous-market convenience: match a single arriving order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn match_incoming<H: Hasher>(
+        &mut self,
+        order: Order,
+        market: &MarketParams,
+        batch_id: u64,
+        match_id_base: u64,
+        owner_map: &OwnerMap,
+        hasher: &H,
+        use_fill_salt: bool,
+        fill_salt_fn: impl FnMut(u64, u64) -> [u8; 32],
+    ) -> Result<ExecutionPlan, BookError> {
+        self.match_batch(vec![order], market, batch_id, match_id_base, owner_map, hasher, use_fill_salt, fill_salt_fn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_market, pid::StubPoseidon, types::*};
+
+    fn mk(id: u64, side: Side, px: u64, qty: u64, seq: u64) -> Order {
+        Order {
+            order_id: OrderId(id), order_hash: [id as u8; 32], pair_id: PairId(1), side,
+            price_tick: px, amount: qty, remaining: qty, time_bucket: 0, nonce: id,
+            ingest_seq: seq, tif: TimeInForce::Gtc, stop: None, display_qty: None,
+        }
+    }
+
+    fn owners(n: u64) -> OwnerMap {
+        (1..=n).map(|i| (i, [i as u8; 32])).collect()
+    }
+
+    fn market() -> MarketParams {
+        MarketParams {
+            pair_id: PairId(1), price_tick: 1, size_step: 1,
+            notional_min: 0, notional_max: u128::MAX,
+            maker_bps: 3, taker_bps: 7, status: MarketStatus::Active,
+            clearing: ClearingMode::Continuous,
+            allocation: AllocationPolicy::Fifo,
+            stp: StpMode::None,
+        }
+    }
+
+    #[test]
+    fn insert_keeps_fifo_even_out_of_order() {
+        let mut b = LevelBook::new(PairId(1));
+        b.insert(mk(2, Side::Ask, 100, 5, 20)).unwrap();
+        b.insert(mk(1, Side::Ask, 100, 3, 10)).unwrap();
+        b.insert(mk(3, Side::Ask, 101, 1, 5)).unwrap();
+        let ids: Vec<u64> = b.snapshot().iter().map(|o| o.order_id.0).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(b.best_ask(), Some((100, 8)));
+        assert_eq!(b.insert(mk(1, Side::Ask, 100, 3, 10)), Err(BookError::DuplicateOrder(OrderId(1))));
+    }
+
+    #[test]
+    fn cancel_and_amend() {
+        let mut b = LevelBook::new(PairId(1));
+        b.insert(mk(1, Side::Bid, 100, 5, 1)).unwrap();
+        b.insert(mk(2, Side::Bid, 100, 5, 2)).unwrap();
+
+        // shrink keeps priority
+        b.amend(OrderId(1), 100, 2, 9).unwrap();
+        assert_eq!(b.snapshot()[0].order_id, OrderId(1));
+        assert_eq!(b.get(OrderId(1)).unwrap().remaining, 2);
+
+        // growing loses priority
+        b.amend(OrderId(1), 100, 6, 9).unwrap();
+        assert_eq!(b.snapshot()[0].order_id, OrderId(2));
+        assert_eq!(b.get(OrderId(1)).unwrap().ingest_seq, 9);
+
+        // reprice moves level
+        b.amend(OrderId(2), 101, 5, 10).unwrap();
+        assert_eq!(b.best_bid(), Some((101, 5)));
+
+        b.cancel(OrderId(2)).unwrap();
+        assert_eq!(b.best_bid(), Some((100, 6)));
+        assert_eq!(b.cancel(OrderId(2)), Err(BookError::UnknownOrder(OrderId(2))));
+    }
+
+    #[test]
+    fn match_incoming_fills_and_rests_remainder() {
+        let mut b = LevelBook::from_orders(PairId(1), vec![
+            mk(1, Side::Ask, 100, 3, 1),
+            mk(2, Side::Ask, 101, 3, 2),
+            mk(3, Side::Bid,  98, 4, 3),
+        ]).unwrap();
+
+        let p = b.match_incoming(mk(4, Side::Bid, 101, 8, 4), &market(), 1, 0, &owners(4), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.fills.iter().map(|f| (f.seller_order_id.0, f.fill_qty, f.price_tick)).collect::<Vec<_>>(),
+                   vec![(1, 3, 100), (2, 3, 101)]);
+        assert_eq!(b.best_bid(), Some((101, 2)));
+        assert_eq!(b.best_ask(), None);
+        assert!(b.get(OrderId(3)).is_some()); // untouched, never lifted
+    }
+
+    #[test]
+    fn match_id_base_offsets_ids() {
+        let mut b = LevelBook::from_orders(PairId(1), vec![mk(1, Side::Ask, 100, 3, 1)]).unwrap();
+        let p = b.match_incoming(mk(2, Side::Bid, 100, 3, 2), &market(), 1, 40, &owners(2), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.fills[0].match_id, 41);
+    }
+
+    #[test]
+    fn ioc_outside_cross_is_still_cancelled() {
+        let mut b = LevelBook::from_orders(PairId(1), vec![mk(1, Side::Ask, 105, 3, 1)]).unwrap();
+        let ioc = Order { tif: TimeInForce::Ioc, ..mk(2, Side::Bid, 100, 3, 2) };
+        let p = b.match_incoming(ioc, &market(), 1, 0, &owners(2), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.cancels.len(), 1);
+        assert!(b.get(OrderId(2)).is_none());
+    }
+
+    #[test]
+    fn batch_matches_like_full_rebuild() {
+        let resting = vec![
+            mk(1, Side::Ask, 100, 3, 1), mk(2, Side::Ask, 102, 4, 2), mk(3, Side::Ask, 110, 9, 3),
+            mk(4, Side::Bid,  99, 5, 4), mk(5, Side::Bid,  90, 2, 5),
+        ];
+        let incoming = vec![mk(6, Side::Bid, 102, 6, 6), mk(7, Side::Ask, 99, 4, 7)];
+        let owners = owners(7);
+
+        for clearing in [ClearingMode::Continuous, ClearingMode::UniformPrice] {
+            let m = MarketParams { clearing, ..market() };
+            let full = match_market(PairId(1), 5, &m, [resting.clone(), incoming.clone()].concat(), &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+
+            let mut b = LevelBook::from_orders(PairId(1), resting.clone()).unwrap();
+            let inc = b.match_batch(incoming.clone(), &m, 5, 0, &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+
+            let key = |p: &ExecutionPlan| p.fills.iter()
+                .map(|f| (f.match_id, f.buyer_order_id.0, f.seller_order_id.0, f.price_tick, f.fill_qty))
+                .collect::<Vec<_>>();
+            assert_eq!(key(&full), key(&inc), "{clearing:?}");
+            assert!(b.get(OrderId(3)).is_some() && b.get(OrderId(5)).is_some());
+        }
+    }
+
+    #[test]
+    fn equal_ingest_seq_matches_like_full_rebuild() {
+        // two makers share price and ingest_seq; only order_id orders them
+        let resting = vec![mk(9, Side::Ask, 100, 2, 1), mk(2, Side::Ask, 100, 2, 1), mk(3, Side::Ask, 100, 2, 1)];
+        let incoming = vec![mk(4, Side::Bid, 100, 3, 2)];
+        let owners = owners(9);
+        for order in [resting.clone(), resting.iter().rev().cloned().collect()] {
+            let full = match_market(PairId(1), 1, &market(), [order.clone(), incoming.clone()].concat(), &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+            let mut b = LevelBook::from_orders(PairId(1), order).unwrap();
+            let inc = b.match_batch(incoming.clone(), &market(), 1, 0, &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+
+            let makers = |p: &ExecutionPlan| p.fills.iter().map(|f| (f.seller_order_id.0, f.fill_qty)).collect::<Vec<_>>();
+            assert_eq!(makers(&full), vec![(2, 2), (3, 1)]);
+            assert_eq!(makers(&inc), makers(&full));
+        }
+    }
+
+    #[test]
+    fn stops_are_held_aside_and_trigger_like_full_rebuild() {
+        let stop = |o: Order, trigger_tick| Order {
+            stop: Some(StopTrigger { kind: StopKind::Limit, trigger_tick, triggered: false }),
+            ..o
+        };
+        let resting = vec![
+            mk(1, Side::Ask, 100, 2, 1), mk(2, Side::Ask, 110, 2, 2), mk(3, Side::Bid, 90, 1, 3),
+            stop(mk(4, Side::Bid, 110, 3, 4), 100),
+        ];
+        let mut b = LevelBook::from_orders(PairId(1), resting.clone()).unwrap();
+        assert_eq!(b.best_bid(), Some((90, 1)));
+        assert_eq!(b.snapshot().last().unwrap().order_id, OrderId(4));
+
+        let incoming = vec![mk(5, Side::Bid, 100, 2, 5)];
+        let owners = owners(5);
+        let full = match_market(PairId(1), 1, &market(), [resting, incoming.clone()].concat(), &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        let inc = b.match_batch(incoming, &market(), 1, 0, &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+
+        assert_eq!(full.triggered, inc.triggered);
+        assert_eq!(inc.fills.iter().map(|f| (f.buyer_order_id.0, f.price_tick)).collect::<Vec<_>>(), vec![(5, 100), (4, 110)]);
+        // triggered stop is back in the levels with its new sequence
+        let o = b.get(OrderId(4)).unwrap();
+        assert_eq!((o.ingest_seq, o.stop.unwrap().triggered), (6, true));
+        assert_eq!((b.best_bid(), b.best_ask()), (Some((110, 1)), None));
+    }
+
+    #[test]
+    fn depth_shows_only_displayed_iceberg_qty() {
+        let ice = Order { display_qty: Some(2), ..mk(1, Side::Ask, 100, 10, 1) };
+        let mut b = LevelBook::from_orders(PairId(1), vec![ice, mk(2, Side::Ask, 100, 3, 2)]).unwrap();
+        assert_eq!(b.best_ask(), Some((100, 5)));
+
+        let p = b.match_incoming(mk(3, Side::Bid, 100, 2, 3), &market(), 1, 0, &owners(3), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.refills.len(), 1);
+        // order 1 went behind order 2 with its next slice
+        let ids: Vec<u64> = b.snapshot().iter().map(|o| o.order_id.0).collect();
+        assert_eq!(ids, vec![2, 1]);
+        assert_eq!(b.depth(Side::Ask, 1), vec![(100, 5)]);
+    }
+
+    #[test]
+    fn match_error_leaves_book_intact() {
+        let mut b = LevelBook::from_orders(PairId(1), vec![mk(1, Side::Ask, 100, 3, 1)]).unwrap();
+        let err = b.match_incoming(mk(2, Side::Bid, 100, 3, 2), &market(), 1, 0, &owners(1), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap_err();
+        assert_eq!(err, BookError::Match(MatchError::MissingOwners(vec![OrderId(2)])));
+        assert_eq!((b.len(), b.best_bid(), b.best_ask()), (2, Some((100, 3)), Some((100, 3))));
+
+        // drop the offender and the rest still matches
+        b.cancel(OrderId(2)).unwrap();
+        let p = b.match_batch(Vec::new(), &market(), 1, 0, &owners(1), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty() && b.len() == 1);
+    }
+}
@@ -1,9 +1,34 @@
+//! Order matching for the sequencer and the zk guest. Builds under
+//! `no_std + alloc` with `default-features = false`; every collection is
+//! ordered so the same inputs give the same plan on every node.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod types;
 pub mod pid;
 pub mod book;
+pub mod level_book;
+pub mod allocation;
+pub mod validate;
+pub mod hash;
+#[cfg(feature = "poseidon")]
+pub mod poseidon;
+pub mod encode;
+pub mod merkle;
+pub mod commit;
+pub mod state;
 pub mod r#match;
+pub mod batch;
+pub mod implied;
 
-pub use r#match::{match_market, ExecutionPlan};
-pub use pid::{derive_pid, Poseidon32};
+pub use r#match::{match_market, match_market_from, max_open_seq, uniform_clearing_price, fee_amount, ExecutionPlan, MatchError};
+pub use batch::{match_batch, BatchOptions, BatchPlan, MarketFault};
+pub use implied::{match_implied, ImpliedRoute};
+pub use pid::derive_pid;
+pub use hash::{Hasher, HashScheme};
+pub use merkle::{merkle_root, merkle_proof, verify_merkle, MerkleProof, SparseMerkle};
+pub use validate::validate_order;
 pub use types::*;
 pub use  book::OrderBook;
+pub use level_book::{LevelBook, BookError};
@@ -1,61 +1,365 @@
 use crate::{
-    types::Order, OrderResidual, FillDraft, PkHash, PairId, Side, MarketParams,OrderKey,
+    types::Order, OrderId, OrderResidual, FillLeg, OrderCancel, CancelReason, FillDraft, OwnerMap, PairId, Side,
+    MarketParams, MarketStatus, TimeInForce, OrderTrigger, OrderRefill, StopKind, ClearingMode, AllocationPolicy, StpMode,
+    allocation::allocate,
     book::OrderBook,
-    pid::{derive_pid, Poseidon32},
+    pid::derive_pid,
+    hash::{domains, Hasher},
+    encode::{encode_plan_header, encode_fill, encode_leg, encode_residual, encode_cancel, encode_trigger, encode_refill},
 };
-use std::collections::HashMap;
+use alloc::{collections::{BTreeMap, BTreeSet}, vec::Vec};
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExecutionPlan {
     pub pair_id: PairId,
     pub batch_id: u64,
     pub fills: Vec<FillDraft>,
     pub residuals: Vec<OrderResidual>,
+    pub cancels: Vec<OrderCancel>, // IOC/FOK/post-only remainders removed this batch
+    pub triggered: Vec<OrderTrigger>, // stops that entered the book, in trigger order
+    pub refills: Vec<OrderRefill>,    // iceberg slices re-queued; last entry per order wins
+    pub legs: Vec<FillLeg>,           // implied-trade legs on this market, see `implied`
+    pub clearing_price: Option<u64>, // UniformPrice: the batch's one price when the book crosses
 }
 
-pub fn match_market<H: Poseidon32>(
+impl ExecutionPlan {
+    /// Canonical output order: fills by `match_id`, residuals and cancels by
+    /// `order_id` (cancels of one order keep their emission order), triggers
+    /// and refills by the `ingest_seq` they were given, legs by `match_id`.
+    pub(crate) fn canonicalize(&mut self) {
+        self.fills.sort_by_key(|f| f.match_id);
+        self.residuals.sort_by_key(|r| r.order_id);
+        self.cancels.sort_by_key(|c| c.order_id);
+        self.triggered.sort_by_key(|t| t.ingest_seq);
+        self.refills.sort_by_key(|r| r.ingest_seq);
+        self.legs.sort_by_key(|l| l.match_id);
+    }
+
+    /// Highest match id this plan used, or `base` if it has none.
+    pub(crate) fn last_match_id(&self, base: u64) -> u64 {
+        let fills = self.fills.iter().map(|f| f.match_id);
+        fills.chain(self.legs.iter().map(|l| l.match_id)).max().unwrap_or(base)
+    }
+
+    /// Record an implied leg against `order`, whose remaining was `before`
+    /// when the leg traded.
+    pub(crate) fn push_leg(&mut self, leg: FillLeg, before: u64) {
+        let after = before - leg.fill_qty;
+        match self.residuals.iter_mut().find(|r| r.order_id == leg.order_id) {
+            Some(r) => { r.remaining_after = after; r.now_filled = after == 0; }
+            None => self.residuals.push(OrderResidual {
+                order_id: leg.order_id, remaining_before: before, remaining_after: after, now_filled: after == 0,
+            }),
+        }
+        self.legs.push(leg);
+    }
+
+    /// What is left resting of `orders` (this plan's input) once the plan is
+    /// applied: remaining sizes, fired stops and re-queued icebergs updated,
+    /// emptied orders dropped.
+    pub(crate) fn resting_after(&self, orders: Vec<Order>) -> Vec<Order> {
+        let after: BTreeMap<OrderId, u64> =
+            self.residuals.iter().map(|r| (r.order_id, r.remaining_after)).collect();
+        let fired: BTreeMap<OrderId, u64> =
+            self.triggered.iter().map(|t| (t.order_id, t.ingest_seq)).collect();
+        let refilled: BTreeMap<OrderId, u64> =
+            self.refills.iter().map(|r| (r.order_id, r.ingest_seq)).collect();
+        let mut out = Vec::with_capacity(orders.len());
+        for mut o in orders {
+            if let (Some(&seq), Some(stop)) = (fired.get(&o.order_id), o.stop.as_mut()) {
+                stop.triggered = true;
+                if stop.kind == StopKind::Market {
+                    o.tif = TimeInForce::Ioc;
+                }
+                o.ingest_seq = seq;
+            }
+            if let Some(&seq) = refilled.get(&o.order_id) {
+                o.ingest_seq = seq;
+            }
+            if let Some(&rem) = after.get(&o.order_id) {
+                o.remaining = rem;
+            }
+            if o.remaining > 0 {
+                out.push(o);
+            }
+        }
+        out
+    }
+
+    /// Digest over the whole plan in canonical order: a header leaf, then one
+    /// leaf per fill, residual, cancel, trigger, refill and implied leg,
+    /// chained with `h2(PLAN_ACC, ..)`. Host and guest compare plans by this
+    /// value.
+    pub fn digest(&self, h: &impl Hasher) -> [u8; 32] {
+        let mut acc = h.h_bytes(domains::PLAN_HEADER, &encode_plan_header(self));
+        let mut absorb = |tag: u64, bytes: Vec<u8>| {
+            acc = h.h2(domains::PLAN_ACC, acc, h.h_bytes(tag, &bytes));
+        };
+        self.fills.iter().for_each(|f| absorb(domains::PLAN_FILL, encode_fill(f)));
+        self.residuals.iter().for_each(|r| absorb(domains::PLAN_RESIDUAL, encode_residual(r)));
+        self.cancels.iter().for_each(|c| absorb(domains::PLAN_CANCEL, encode_cancel(c)));
+        self.triggered.iter().for_each(|t| absorb(domains::PLAN_TRIGGER, encode_trigger(t)));
+        self.refills.iter().for_each(|r| absorb(domains::PLAN_REFILL, encode_refill(r)));
+        self.legs.iter().for_each(|l| absorb(domains::PLAN_LEG, encode_leg(l)));
+        acc
+    }
+}
+
+/// Why a market could not be matched. Order-level variants list every
+/// offending order so the caller can drop them and match the rest.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatchError {
+    PairMismatch { market: PairId, requested: PairId },
+    ForeignOrders(Vec<OrderId>), // order.pair_id is not the market's
+    MissingOwners(Vec<OrderId>), // no pk_hash in owner_map
+}
+
+impl MatchError {
+    /// Orders to quarantine before retrying; empty for market-level errors.
+    pub fn offending_orders(&self) -> &[OrderId] {
+        match self {
+            MatchError::PairMismatch { .. } => &[],
+            MatchError::ForeignOrders(ids) | MatchError::MissingOwners(ids) => ids,
+        }
+    }
+}
+
+/// Checks `match_market_from` makes before touching the book.
+fn check_inputs(
     pair_id: PairId,
-    batch_id: u64,
     market: &MarketParams,
-    orders: Vec<Order>, // not used after building the book; no `mut` needed
-    owner_map: &HashMap<u64, PkHash>,
-    hasher: &H,
+    orders: &[Order],
+    owner_map: &OwnerMap,
+) -> Result<(), MatchError> {
+    if market.pair_id != pair_id {
+        return Err(MatchError::PairMismatch { market: market.pair_id, requested: pair_id });
+    }
+    let foreign: Vec<OrderId> = orders.iter().filter(|o| o.pair_id != pair_id).map(|o| o.order_id).collect();
+    if !foreign.is_empty() {
+        return Err(MatchError::ForeignOrders(foreign));
+    }
+    let ownerless: Vec<OrderId> = orders.iter()
+        .filter(|o| o.remaining > 0 && !owner_map.contains_key(&o.order_id.0))
+        .map(|o| o.order_id)
+        .collect();
+    if !ownerless.is_empty() {
+        return Err(MatchError::MissingOwners(ownerless));
+    }
+    Ok(())
+}
+
+/// Fee on `qty` at `price_tick`, in quote units (price_tick * qty), rounded
+/// up so a non-zero rate never charges zero.
+pub fn fee_amount(price_tick: u64, qty: u64, bps: u16) -> u128 {
+    (price_tick as u128 * qty as u128 * bps as u128).div_ceil(10_000)
+}
+
+/// Later arrival takes liquidity; order_id breaks ties.
+#[inline]
+fn is_taker(o: &Order, other: &Order) -> bool {
+    (o.ingest_seq, o.order_id.0) > (other.ingest_seq, other.order_id.0)
+}
+
+#[inline]
+fn crosses(a: &Order, b: &Order) -> bool {
+    match a.side {
+        Side::Bid => a.price_tick >= b.price_tick,
+        Side::Ask => a.price_tick <= b.price_tick,
+    }
+}
+
+/// Whether `o` is willing to trade at the batch clearing price.
+#[inline]
+fn accepts(o: &Order, price: u64) -> bool {
+    match o.side {
+        Side::Bid => o.price_tick >= price,
+        Side::Ask => o.price_tick <= price,
+    }
+}
+
+/// Single price that maximises matched volume over all open orders.
+///
+/// Ties are broken by the smallest |demand - supply| imbalance, then by the
+/// lowest price, so every node derives the same price from the same book.
+/// Returns `None` when nothing crosses.
+pub fn uniform_clearing_price(orders: &[Order]) -> Option<u64> {
+    // price -> (bid qty, ask qty) at exactly that level
+    let mut levels: BTreeMap<u64, (u128, u128)> = BTreeMap::new();
+    for o in orders.iter().filter(|o| o.remaining > 0) {
+        let e = levels.entry(o.price_tick).or_default();
+        match o.side {
+            Side::Bid => e.0 += o.remaining as u128,
+            Side::Ask => e.1 += o.remaining as u128,
+        }
+    }
+
+    // supply(p) = asks priced <= p, demand(p) = bids priced >= p
+    let mut demand: u128 = levels.values().map(|l| l.0).sum();
+    let mut supply: u128 = 0;
+    let mut best: Option<(u128, u128, u64)> = None; // (volume, imbalance, price)
+    for (&px, &(bid_qty, ask_qty)) in &levels {
+        supply += ask_qty;
+        let volume = demand.min(supply);
+        let imbalance = demand.abs_diff(supply);
+        if volume > 0 && best.is_none_or(|(v, i, _)| volume > v || (volume == v && imbalance < i)) {
+            best = Some((volume, imbalance, px));
+        }
+        demand -= bid_qty;
+    }
+    best.map(|(_, _, px)| px)
+}
+
+#[inline]
+fn same_owner(owner_map: &OwnerMap, a: &Order, b: &Order) -> bool {
+    match (owner_map.get(&a.order_id.0), owner_map.get(&b.order_id.0)) {
+        (Some(x), Some(y)) => x == y,
+        _ => false,
+    }
+}
+
+/// Opposite-side quantity a FOK order can count on. Other FOK orders and
+/// post-only orders that would be rejected against it are excluded, since
+/// either may leave the book before the FOK order is done. With STP on,
+/// `stp_owners` is set and the FOK owner's own orders are excluded too.
+fn fok_liquidity(
+    orders: &[Order],
+    fok: &Order,
+    clearing: Option<u64>,
+    stp_owners: Option<&OwnerMap>,
+) -> u64 {
+    orders.iter()
+        .filter(|o| o.side != fok.side && o.remaining > 0 && crosses(fok, o))
+        .filter(|o| clearing.is_none_or(|px| accepts(o, px)))
+        .filter(|o| o.tif != TimeInForce::Fok)
+        .filter(|o| !(o.tif == TimeInForce::PostOnly && is_taker(o, fok)))
+        .filter(|o| stp_owners.is_none_or(|m| !same_owner(m, fok, o)))
+        .fold(0u64, |acc, o| acc.saturating_add(o.remaining))
+}
+
+/// Outputs and fill context threaded through one `match_market` call.
+struct Run<'a, H, F> {
+    pair_id: PairId,
+    batch_id: u64,
+    owner_map: &'a OwnerMap,
+    hasher: &'a H,
     use_fill_salt: bool,
-    mut fill_salt_fn: impl FnMut(u64,u64) -> [u8;32],
-) -> ExecutionPlan {
-    assert!(market.pair_id == pair_id, "market/pair mismatch");
+    fill_salt_fn: F,
+    maker_bps: u16,
+    taker_bps: u16,
+    match_seq: u64,
+    next_seq: u64, // last ingest_seq handed to a triggered stop or iceberg refill
+    fills: Vec<FillDraft>,
+    residuals: BTreeMap<u64, OrderResidual>,
+    cancels: Vec<OrderCancel>,
+    refills: Vec<OrderRefill>,
+}
 
-    let mut book = OrderBook::from_orders(orders);
-    let mut fills = Vec::new();
-    let mut residuals: HashMap<u64, OrderResidual> = HashMap::new();
-    let mut match_seq: u64 = 0;
-    let taker_fee = market.taker_bps;
+impl<H: Hasher, F: FnMut(u64, u64) -> [u8; 32]> Run<'_, H, F> {
+    /// Sequence for an order (re-)entering the queue during this batch.
+    fn stamp(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
 
-    loop {
-        let (bi, ai) = match (book.best_bid_idx(), book.best_ask_idx()) {
-            (Some(b), Some(a)) => (b, a),
-            _ => break,
+    /// An iceberg whose visible slice just traded away shows the next slice
+    /// from the back of its level.
+    fn refill(&mut self, book: &mut OrderBook, idx: usize) {
+        let o = &book.orders[idx];
+        if o.display_qty.is_none() || o.remaining == 0 {
+            return;
+        }
+        let order_id = o.order_id;
+        let seq = self.stamp();
+        book.requeue(idx, seq);
+        self.refills.push(OrderRefill { order_id, ingest_seq: seq });
+    }
+
+    fn record_residual(&mut self, o: &Order, before: u64) {
+        self.residuals.entry(o.order_id.0)
+            .and_modify(|r| { r.remaining_after = o.remaining; r.now_filled = r.remaining_after == 0 })
+            .or_insert(OrderResidual {
+                order_id: o.order_id,
+                remaining_before: before,
+                remaining_after: o.remaining,
+                now_filled: o.remaining == 0,
+            });
+    }
+
+    /// Zero the order's remaining and log why. The residual keeps the original
+    /// `remaining_before` but is not marked filled.
+    fn cancel(&mut self, o: &mut Order, reason: CancelReason) {
+        let qty = o.remaining;
+        self.reduce(o, qty, reason);
+    }
+
+    /// Remove `qty` from the order without trading it.
+    fn reduce(&mut self, o: &mut Order, qty: u64, reason: CancelReason) {
+        let before = o.remaining;
+        o.remaining = before - qty;
+        self.record_residual(o, before);
+        if let Some(r) = self.residuals.get_mut(&o.order_id.0) { r.now_filled = false; }
+        self.cancels.push(OrderCancel { order_id: o.order_id, cancelled_qty: qty, reason });
+    }
+
+    /// Resolve a cross between two orders of the same owner according to
+    /// `mode`. An armed FOK order is never the side given up: its
+    /// counterparty is cancelled instead. Returns whether the taker still has
+    /// quantity left.
+    fn prevent_self_trade(
+        &mut self,
+        orders: &mut [Order],
+        mode: StpMode,
+        taker: usize,
+        maker: usize,
+        fok_armed: &BTreeSet<u64>,
+    ) -> bool {
+        let armed = |o: &Order| o.tif == TimeInForce::Fok && fok_armed.contains(&o.order_id.0);
+        let mode = if armed(&orders[taker]) {
+            StpMode::CancelOldest
+        } else if armed(&orders[maker]) {
+            StpMode::CancelNewest
+        } else {
+            mode
         };
-        if book.orders[bi].price_tick < book.orders[ai].price_tick {
-            break;
+
+        match mode {
+            StpMode::None => {}
+            StpMode::CancelNewest => self.cancel(&mut orders[taker], CancelReason::SelfTrade),
+            StpMode::CancelOldest => self.cancel(&mut orders[maker], CancelReason::SelfTrade),
+            StpMode::CancelBoth => {
+                self.cancel(&mut orders[taker], CancelReason::SelfTrade);
+                self.cancel(&mut orders[maker], CancelReason::SelfTrade);
+            }
+            StpMode::DecrementAndCancel => {
+                let qty = orders[taker].remaining.min(orders[maker].remaining);
+                self.reduce(&mut orders[taker], qty, CancelReason::SelfTrade);
+                self.reduce(&mut orders[maker], qty, CancelReason::SelfTrade);
+            }
         }
+        orders[taker].remaining > 0
+    }
+
+    /// Trade `qty` between `orders[bi]` (bid) and `orders[ai]` (ask) at `price`.
+    fn fill(&mut self, orders: &mut [Order], bi: usize, ai: usize, qty: u64, price: u64) {
+        let (batch_id, pair_id) = (self.batch_id, self.pair_id);
+        let maker_side = if is_taker(&orders[bi], &orders[ai]) { Side::Ask } else { Side::Bid };
 
         // ---- snapshot needed fields as VALUES (no long-lived borrows) ----
-        let qty        = book.orders[bi].remaining.min(book.orders[ai].remaining);
-        let price      = book.orders[ai].price_tick; // maker = resting ask
-        let bid_id     = book.orders[bi].order_id;
-        let ask_id     = book.orders[ai].order_id;
-        let bid_hash   = book.orders[bi].order_hash;
-        let ask_hash   = book.orders[ai].order_hash;
-        let bid_tb     = book.orders[bi].time_bucket;
-        let ask_tb     = book.orders[ai].time_bucket;
-
-        match_seq += 1;
-        let match_id = match_seq;
+        let bid_id     = orders[bi].order_id;
+        let ask_id     = orders[ai].order_id;
+        let bid_hash   = orders[bi].order_hash;
+        let ask_hash   = orders[ai].order_hash;
+        let bid_tb     = orders[bi].time_bucket;
+        let ask_tb     = orders[ai].time_bucket;
+
+        self.match_seq += 1;
+        let match_id = self.match_seq;
 
         // ---- take disjoint &mut using split_at_mut ----
         let (lo, hi) = if bi < ai { (bi, ai) } else { (ai, bi) };
-        let (left, right) = book.orders.split_at_mut(hi);
+        let (left, right) = orders.split_at_mut(hi);
         let (bid_mut, ask_mut) = if bi < ai {
             (&mut left[lo], &mut right[0])
         } else {
@@ -67,14 +371,15 @@ pub fn match_market<H: Poseidon32>(
         ask_mut.remaining = a_before - qty;
 
         // PIDs
-        let buyer_pk  = owner_map.get(&bid_id.0).expect("missing buyer pk_hash");
-        let seller_pk = owner_map.get(&ask_id.0).expect("missing seller pk_hash");
-        let salt      = if use_fill_salt { Some(fill_salt_fn(batch_id, match_id)) } else { None };
-        let buyer_pid  = derive_pid(hasher, buyer_pk,  batch_id, match_id, salt);
-        let seller_pid = derive_pid(hasher, seller_pk, batch_id, match_id, salt);
+        // owners of every open order were checked by check_inputs
+        let buyer_pk  = &self.owner_map[&bid_id.0];
+        let seller_pk = &self.owner_map[&ask_id.0];
+        let salt      = if self.use_fill_salt { Some((self.fill_salt_fn)(batch_id, match_id)) } else { None };
+        let buyer_pid  = derive_pid(self.hasher, buyer_pk,  batch_id, match_id, salt);
+        let seller_pid = derive_pid(self.hasher, seller_pk, batch_id, match_id, salt);
 
         // record fill
-        fills.push(FillDraft {
+        self.fills.push(FillDraft {
             batch_id,
             match_id,
             pair_id,
@@ -87,45 +392,318 @@ pub fn match_market<H: Poseidon32>(
             seller_order_hash: ask_hash,
             buyer_pid,
             seller_pid,
-            fee_bps: taker_fee,
+            maker_side,
+            maker_fee: fee_amount(price, qty, self.maker_bps),
+            taker_fee: fee_amount(price, qty, self.taker_bps),
             fill_salt: salt,
         });
 
         // residuals
-        residuals.entry(bid_id.0)
-            .and_modify(|r| { r.remaining_after = bid_mut.remaining; r.now_filled = r.remaining_after == 0 })
-            .or_insert(OrderResidual {
-                order_id: bid_id,
-                remaining_before: b_before,
-                remaining_after: bid_mut.remaining,
-                now_filled: bid_mut.remaining == 0,
-            });
-        residuals.entry(ask_id.0)
-            .and_modify(|r| { r.remaining_after = ask_mut.remaining; r.now_filled = r.remaining_after == 0 })
-            .or_insert(OrderResidual {
-                order_id: ask_id,
-                remaining_before: a_before,
-                remaining_after: ask_mut.remaining,
-                now_filled: ask_mut.remaining == 0,
-            });
+        self.record_residual(bid_mut, b_before);
+        self.record_residual(ask_mut, a_before);
+    }
+}
 
-        book.on_fill(Side::Bid);
-        book.on_fill(Side::Ask);
+/// Resting orders per (side, price), each list in FIFO order. Only built for
+/// markets that allocate by something other than FIFO.
+fn level_index(orders: &[Order]) -> BTreeMap<(u8, u64), Vec<usize>> {
+    let mut levels: BTreeMap<(u8, u64), Vec<usize>> = BTreeMap::new();
+    for (idx, o) in orders.iter().enumerate().filter(|(_, o)| o.remaining > 0) {
+        levels.entry((o.side as u8, o.price_tick)).or_default().push(idx);
+    }
+    for v in levels.values_mut() {
+        v.sort_by_key(|&i| (orders[i].ingest_seq, orders[i].order_id.0));
+    }
+    levels
+}
+
+/// Match one market's open orders for a batch. Inconsistent inputs are
+/// reported as a `MatchError` before anything is matched.
+#[allow(clippy::too_many_arguments)]
+pub fn match_market<H: Hasher>(
+    pair_id: PairId,
+    batch_id: u64,
+    market: &MarketParams,
+    orders: Vec<Order>, // not used after building the book; no `mut` needed
+    owner_map: &OwnerMap,
+    hasher: &H,
+    use_fill_salt: bool,
+    fill_salt_fn: impl FnMut(u64,u64) -> [u8;32],
+) -> Result<ExecutionPlan, MatchError> {
+    let trigger_seq_base = max_open_seq(&orders);
+    match_market_from(
+        pair_id, batch_id, 0, trigger_seq_base, market, orders, owner_map, hasher, use_fill_salt,
+        fill_salt_fn,
+    )
+}
+
+/// Highest `ingest_seq` among open orders, pending stops included.
+pub fn max_open_seq(orders: &[Order]) -> u64 {
+    orders.iter().filter(|o| o.remaining > 0).map(|o| o.ingest_seq).max().unwrap_or(0)
+}
+
+/// Whether a trade at `last` fires this pending stop.
+#[inline]
+fn stop_fires(o: &Order, last: u64) -> bool {
+    match (o.stop, o.side) {
+        (Some(s), Side::Bid) => !s.triggered && last >= s.trigger_tick,
+        (Some(s), Side::Ask) => !s.triggered && last <= s.trigger_tick,
+        (None, _) => false,
+    }
+}
+
+/// `match_market` with match ids starting at `match_id_base + 1`, for callers
+/// that run several matches inside one batch.
+///
+/// Pending stop orders are held off the book. After each matching pass the
+/// last fill price is checked against their triggers; the ones that fire
+/// enter the book in (`ingest_seq`, `order_id`) order, re-stamped
+/// `trigger_seq_base + 1`, `+ 2`, ... so they queue behind every order that
+/// was open when the batch started, and matching runs again. This repeats
+/// until a pass fires nothing, so cascades settle inside the batch.
+/// Under `UniformPrice` the first pass fixes the batch's one clearing price:
+/// later passes trade only orders that accept it, and at it, so fired stops
+/// that don't accept it rest (limit) or are cancelled (market) rather than
+/// printing a second price.
+/// `trigger_seq_base` must be `max_open_seq` over the market's whole book.
+#[allow(clippy::too_many_arguments)]
+pub fn match_market_from<H: Hasher>(
+    pair_id: PairId,
+    batch_id: u64,
+    match_id_base: u64,
+    trigger_seq_base: u64,
+    market: &MarketParams,
+    orders: Vec<Order>,
+    owner_map: &OwnerMap,
+    hasher: &H,
+    use_fill_salt: bool,
+    fill_salt_fn: impl FnMut(u64,u64) -> [u8;32],
+) -> Result<ExecutionPlan, MatchError> {
+    check_inputs(pair_id, market, &orders, owner_map)?;
+
+    // only Active markets trade; anything else leaves the book untouched
+    if market.status != MarketStatus::Active {
+        return Ok(ExecutionPlan {
+            pair_id, batch_id,
+            fills: Vec::new(), residuals: Vec::new(), cancels: Vec::new(), triggered: Vec::new(),
+            refills: Vec::new(), legs: Vec::new(), clearing_price: None,
+        });
     }
 
-    ExecutionPlan {
+    let (mut pending, open): (Vec<Order>, Vec<Order>) =
+        orders.into_iter().filter(|o| o.remaining > 0).partition(Order::is_pending_stop);
+    pending.sort_by_key(|o| (o.ingest_seq, o.order_id.0));
+
+    let mut book = OrderBook::from_orders(open);
+    let mut run = Run {
         pair_id,
         batch_id,
-        fills,
-        residuals: residuals.into_values().collect(),
+        owner_map,
+        hasher,
+        use_fill_salt,
+        fill_salt_fn,
+        maker_bps: market.maker_bps,
+        taker_bps: market.taker_bps,
+        match_seq: match_id_base,
+        next_seq: trigger_seq_base,
+        fills: Vec::new(),
+        residuals: BTreeMap::new(),
+        cancels: Vec::new(),
+        refills: Vec::new(),
+    };
+    let mut fok_armed: BTreeSet<u64> = BTreeSet::new();
+    let mut triggered = Vec::new();
+    let mut clearing_price = None;
+
+    loop {
+        let fills_before = run.fills.len();
+        clearing_price = match_pass(&mut book, &mut run, market, &mut fok_armed, clearing_price);
+
+        let Some(last) = run.fills[fills_before..].last().map(|f| f.price_tick) else { break };
+        let (fired, waiting): (Vec<Order>, Vec<Order>) =
+            pending.into_iter().partition(|o| stop_fires(o, last));
+        pending = waiting;
+        if fired.is_empty() {
+            break;
+        }
+
+        // book is not crossed between passes, so refill queue positions can
+        // become the orders' ingest_seq without changing any taker/maker role
+        let mut orders = book.into_orders();
+        for mut o in fired {
+            let seq = run.stamp();
+            let stop = o.stop.as_mut().expect("pending stop");
+            stop.triggered = true;
+            if stop.kind == StopKind::Market {
+                o.tif = TimeInForce::Ioc;
+            }
+            o.ingest_seq = seq;
+            triggered.push(OrderTrigger { order_id: o.order_id, ingest_seq: seq, last_price: last });
+            orders.push(o);
+        }
+        book = OrderBook::from_orders(orders);
+    }
+
+    // IOC and FOK never rest past the batch they arrived in
+    for o in book.orders.iter_mut().filter(|o| o.remaining > 0) {
+        match o.tif {
+            TimeInForce::Ioc => run.cancel(o, CancelReason::IocRemainder),
+            TimeInForce::Fok => run.cancel(o, CancelReason::FokUnfilled),
+            TimeInForce::Gtc | TimeInForce::PostOnly => {}
+        }
+    }
+
+    let mut plan = ExecutionPlan {
+        pair_id,
+        batch_id,
+        fills: run.fills,
+        residuals: run.residuals.into_values().collect(),
+        cancels: run.cancels,
+        triggered,
+        refills: run.refills,
+        legs: Vec::new(),
+        clearing_price,
+    };
+    plan.canonicalize();
+    Ok(plan)
+}
+
+/// Match `book` until nothing crosses. Returns the uniform clearing price
+/// used, if any; `fixed_price` is an earlier pass's price and is reused.
+fn match_pass<H: Hasher, F: FnMut(u64, u64) -> [u8; 32]>(
+    book: &mut OrderBook,
+    run: &mut Run<'_, H, F>,
+    market: &MarketParams,
+    fok_armed: &mut BTreeSet<u64>,
+    fixed_price: Option<u64>,
+) -> Option<u64> {
+    let owner_map = run.owner_map;
+    let stp_owners = (market.stp != StpMode::None).then_some(owner_map);
+
+    // Uniform mode: only orders that accept the clearing price trade, and
+    // all of them trade at it. Priority among them is still price-time.
+    let clearing_price = match market.clearing {
+        ClearingMode::Continuous => None,
+        ClearingMode::UniformPrice => fixed_price.or_else(|| uniform_clearing_price(&book.orders)),
+    };
+    let levels = match market.allocation {
+        AllocationPolicy::Fifo => BTreeMap::new(),
+        _ => level_index(&book.orders),
+    };
+
+    while let (Some(bi), Some(ai)) = (book.best_bid_idx(), book.best_ask_idx()) {
+        let crossed = match clearing_price {
+            Some(px) => accepts(&book.orders[bi], px) && accepts(&book.orders[ai], px),
+            None => book.orders[bi].price_tick >= book.orders[ai].price_tick,
+        };
+        if !crossed {
+            break;
+        }
+
+        // post-only may never take: reject whichever side arrived later
+        let (taker_idx, taker_side) = if is_taker(&book.orders[bi], &book.orders[ai]) {
+            (bi, Side::Bid)
+        } else {
+            (ai, Side::Ask)
+        };
+        let maker_idx = if taker_idx == bi { ai } else { bi };
+        if book.orders[taker_idx].tif == TimeInForce::PostOnly {
+            run.cancel(&mut book.orders[taker_idx], CancelReason::PostOnlyWouldCross);
+            book.on_fill(taker_side);
+            continue;
+        }
+
+        // FOK is checked once, when it first reaches the top of a crossed book.
+        // While it stays on top it only trades against liquidity counted here.
+        let mut fok_rejected = false;
+        for (idx, side) in [(bi, Side::Bid), (ai, Side::Ask)] {
+            let o = &book.orders[idx];
+            if o.tif != TimeInForce::Fok || fok_armed.contains(&o.order_id.0) {
+                continue;
+            }
+            if fok_liquidity(&book.orders, o, clearing_price, stp_owners) < o.remaining {
+                run.cancel(&mut book.orders[idx], CancelReason::FokUnfilled);
+                book.on_fill(side);
+                fok_rejected = true;
+            } else {
+                fok_armed.insert(o.order_id.0);
+            }
+        }
+        if fok_rejected {
+            continue;
+        }
+
+        if stp_owners.is_some() && same_owner(owner_map, &book.orders[taker_idx], &book.orders[maker_idx]) {
+            run.prevent_self_trade(&mut book.orders, market.stp, taker_idx, maker_idx, fok_armed);
+            book.on_fill(Side::Bid);
+            book.on_fill(Side::Ask);
+            continue;
+        }
+
+        // every fill prices at the maker's limit (or the batch clearing price)
+        let price = clearing_price.unwrap_or(book.orders[maker_idx].price_tick);
+
+        // Non-FIFO policies share the taker's quantity across every order that
+        // was already resting at the maker's level. A FOK maker is armed for a
+        // complete fill, so it always trades FIFO.
+        if market.allocation != AllocationPolicy::Fifo && book.orders[maker_idx].tif != TimeInForce::Fok {
+            let maker = &book.orders[maker_idx];
+            let taker = &book.orders[taker_idx];
+            let mut makers: Vec<usize> = levels[&(maker.side as u8, maker.price_tick)].iter().copied()
+                .filter(|&i| {
+                    let o = &book.orders[i];
+                    o.remaining > 0 && o.tif != TimeInForce::Fok && is_taker(taker, o)
+                })
+                .collect();
+            makers.sort_by_key(|&i| (book.queue_seq(i), book.orders[i].order_id.0)); // refills moved back
+
+            // settle self-trades inside the level before splitting the rest
+            if stp_owners.is_some() {
+                for &mi in &makers {
+                    if book.orders[taker_idx].remaining > 0
+                        && same_owner(owner_map, &book.orders[taker_idx], &book.orders[mi])
+                    {
+                        run.prevent_self_trade(&mut book.orders, market.stp, taker_idx, mi, fok_armed);
+                    }
+                }
+                makers.retain(|&i| {
+                    book.orders[i].remaining > 0 && !same_owner(owner_map, &book.orders[taker_idx], &book.orders[i])
+                });
+            }
+
+            let taker = &book.orders[taker_idx];
+            // hidden iceberg reserve takes no part in the split
+            let shown: Vec<u64> = makers.iter().map(|&i| book.orders[i].displayed()).collect();
+            let allocs = allocate(market.allocation, taker.remaining, &shown, market.size_step);
+
+            for ((&mi, qty), shown) in makers.iter().zip(allocs).zip(shown).filter(|((_, q), _)| *q > 0) {
+                let (b, a) = if taker_idx == bi { (bi, mi) } else { (mi, ai) };
+                run.fill(&mut book.orders, b, a, qty, price);
+                if qty == shown {
+                    run.refill(book, mi);
+                }
+            }
+        } else {
+            // the maker only offers its visible slice; the taker brings everything
+            let shown = book.orders[maker_idx].displayed();
+            let qty = book.orders[taker_idx].remaining.min(shown);
+            run.fill(&mut book.orders, bi, ai, qty, price);
+            if qty == shown {
+                run.refill(book, maker_idx);
+            }
+        }
+
+        book.on_fill(Side::Bid);
+        book.on_fill(Side::Ask);
     }
+    clearing_price
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{types::*, pid::StubPoseidon};
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
     #[test]
     fn simple_cross() {
@@ -134,16 +712,19 @@ mod tests {
             pair_id: pair,
             price_tick: 1, size_step: 1,
             notional_min: 0, notional_max: u128::MAX,
-            maker_bps: 0, taker_bps: 0, status: MarketStatus::Active
+            maker_bps: 0, taker_bps: 0, status: MarketStatus::Active,
+            clearing: ClearingMode::Continuous,
+            allocation: AllocationPolicy::Fifo,
+            stp: StpMode::None,
         };
-        let mut orders = vec![
-            Order{ order_id: OrderId(1), order_hash:[1;32], pair_id:pair, side:Side::Bid, price_tick:100, amount:10, remaining:10, time_bucket:0, nonce:1, ingest_seq: 10 },
-            Order{ order_id: OrderId(2), order_hash:[2;32], pair_id:pair, side:Side::Ask, price_tick: 95, amount: 7, remaining: 7, time_bucket:0, nonce:2, ingest_seq: 11 },
-            Order{ order_id: OrderId(3), order_hash:[3;32], pair_id:pair, side:Side::Ask, price_tick:100, amount: 8, remaining: 8, time_bucket:0, nonce:3, ingest_seq: 12 },
+        let orders = vec![
+            Order{ order_id: OrderId(1), order_hash:[1;32], pair_id:pair, side:Side::Bid, price_tick:100, amount:10, remaining:10, time_bucket:0, nonce:1, ingest_seq: 10, tif: TimeInForce::Gtc, stop: None, display_qty: None },
+            Order{ order_id: OrderId(2), order_hash:[2;32], pair_id:pair, side:Side::Ask, price_tick: 95, amount: 7, remaining: 7, time_bucket:0, nonce:2, ingest_seq: 11, tif: TimeInForce::Gtc, stop: None, display_qty: None },
+            Order{ order_id: OrderId(3), order_hash:[3;32], pair_id:pair, side:Side::Ask, price_tick:100, amount: 8, remaining: 8, time_bucket:0, nonce:3, ingest_seq: 12, tif: TimeInForce::Gtc, stop: None, display_qty: None },
         ];
         // per-market ingest_seq matters only within same side & price—already set.
 
-        let mut owners: HashMap<u64, PkHash> = HashMap::new();
+        let mut owners: OwnerMap = BTreeMap::new();
         owners.insert(1, [0xAA;32]);
         owners.insert(2, [0xBB;32]);
         owners.insert(3, [0xCC;32]);
@@ -151,10 +732,11 @@ mod tests {
         let plan = match_market(
             pair, 42, &market, orders.clone(), &owners,
             &StubPoseidon, false, |_b,_m| [0u8;32]
-        );
+        ).unwrap();
 
-        // First fill: bid(100,ingest=10) vs ask(95,ingest=11) at price 95 for qty 7
-        assert_eq!(plan.fills[0].price_tick, 95);
+        // First fill: bid(100,ingest=10) vs ask(95,ingest=11); the bid rested first, so 100
+        assert_eq!(plan.fills[0].price_tick, 100);
+        assert_eq!(plan.fills[0].maker_side, Side::Bid);
         assert_eq!(plan.fills[0].fill_qty, 7);
         assert_eq!(plan.fills[0].buyer_order_id.0, 1);
         assert_eq!(plan.fills[0].seller_order_id.0, 2);
@@ -163,7 +745,7 @@ mod tests {
         assert_eq!(plan.fills[1].fill_qty, 3);
         assert_eq!(plan.fills[1].seller_order_id.0, 3);
 
-        let mut by_id = plan.residuals.iter().map(|r|(r.order_id.0, r)).collect::<std::collections::HashMap<_,_>>();
+        let by_id = plan.residuals.iter().map(|r|(r.order_id.0, r)).collect::<std::collections::BTreeMap<_,_>>();
         assert_eq!(by_id.get(&1).unwrap().remaining_after, 0);
         assert_eq!(by_id.get(&2).unwrap().remaining_after, 0);
         assert_eq!(by_id.get(&3).unwrap().remaining_after, 5);
@@ -192,11 +774,14 @@ mod exec_tests {
             time_bucket: tb,
             nonce: id,
             ingest_seq: seq,
+            tif: TimeInForce::Gtc,
+            stop: None,
+            display_qty: None,
         }
     }
 
-    fn owners(ids: &[u64]) -> HashMap<u64, PkHash> {
-        let mut m = HashMap::new();
+    fn owners(ids: &[u64]) -> OwnerMap {
+        let mut m = BTreeMap::new();
         for &id in ids {
             let mut pk = [0u8;32];
             pk[8..16].copy_from_slice(&id.to_be_bytes());
@@ -211,7 +796,10 @@ mod exec_tests {
             price_tick: 1, size_step: 1,
             notional_min: 0, notional_max: u128::MAX,
             maker_bps: 3, taker_bps: 7,
-            status: MarketStatus::Active
+            status: MarketStatus::Active,
+            clearing: ClearingMode::Continuous,
+            allocation: AllocationPolicy::Fifo,
+            stp: StpMode::None,
         }
     }
 
@@ -226,7 +814,7 @@ mod exec_tests {
         let plan = match_market(
             PairId(1), 42, &market(), vec![a2.clone(), a1.clone(), b.clone()], // note: order vec shuffled
             &owners, &StubPoseidon, false, |_b,_m| [0u8;32]
-        );
+        ).unwrap();
 
         // Fill must hit a1 (seq 10) before a2 (seq 11)
         assert_eq!(plan.fills.len(), 2);
@@ -239,25 +827,114 @@ mod exec_tests {
         assert!(plan.fills.iter().all(|f| f.price_tick == 100));
     }
 
-    // #[test]
-    // fn fifo_same_price_on_bid_side() {
-    //     // Two bids @100 with ingest_seq ordering; asks consume FIFO
-    //     let b1 = mk_order(1, Side::Bid, 100, 2, 2, 10, 0);
-    //     let b2 = mk_order(2, Side::Bid, 100, 4, 4, 11, 0);
-    //     let a  = mk_order(3, Side::Ask, 100, 5, 5, 20, 0);
+    fn pro_rata_market() -> MarketParams {
+        MarketParams { allocation: AllocationPolicy::ProRata, ..market() }
+    }
 
-    //     let owners = owners(&[1,2,3]);
-    //     let plan = match_market(
-    //         PairId(1), 7, &market(), vec![b2.clone(), a.clone(), b1.clone()],
-    //         &owners, &StubPoseidon, false, |_b,_m| [0u8;32]
-    //     );
+    #[test]
+    fn pro_rata_same_price_on_ask_side() {
+        // Two asks @100 (3 and 9); bid for 8 is split 2:6 by size, not FIFO
+        let a1 = mk_order(1, Side::Ask, 100, 3, 3, 10, 0);
+        let a2 = mk_order(2, Side::Ask, 100, 9, 9, 11, 0);
+        let b  = mk_order(3, Side::Bid, 100, 8, 8, 20, 0);
 
-    //     assert_eq!(plan.fills.len(), 2);
-    //     assert_eq!(plan.fills[0].buyer_order_id.0, 1);
-    //     assert_eq!(plan.fills[0].fill_qty, 2);
-    //     assert_eq!(plan.fills[1].buyer_order_id.0, 2);
-    //     assert_eq!(plan.fills[1].fill_qty, 3);
-    // }
+        let owners = owners(&[1,2,3]);
+        let plan = match_market(
+            PairId(1), 42, &pro_rata_market(), vec![a2, b, a1],
+            &owners, &StubPoseidon, false, |_b,_m| [0u8;32]
+        ).unwrap();
+
+        assert_eq!(plan.fills.len(), 2);
+        assert_eq!(plan.fills[0].seller_order_id.0, 1);
+        assert_eq!(plan.fills[0].fill_qty, 2);
+        assert_eq!(plan.fills[1].seller_order_id.0, 2);
+        assert_eq!(plan.fills[1].fill_qty, 6);
+        assert!(plan.fills.iter().all(|f| f.price_tick == 100));
+        assert_eq!(plan.fills.iter().map(|f| f.match_id).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn pro_rata_same_price_on_bid_side() {
+        let b1 = mk_order(1, Side::Bid, 100, 2, 2, 10, 0);
+        let b2 = mk_order(2, Side::Bid, 100, 4, 4, 11, 0);
+        let a  = mk_order(3, Side::Ask, 100, 3, 3, 20, 0);
+
+        let owners = owners(&[1,2,3]);
+        let plan = match_market(
+            PairId(1), 7, &pro_rata_market(), vec![b2, a, b1],
+            &owners, &StubPoseidon, false, |_b,_m| [0u8;32]
+        ).unwrap();
+
+        assert_eq!(plan.fills.len(), 2);
+        assert_eq!(plan.fills[0].buyer_order_id.0, 1);
+        assert_eq!(plan.fills[0].fill_qty, 1);
+        assert_eq!(plan.fills[1].buyer_order_id.0, 2);
+        assert_eq!(plan.fills[1].fill_qty, 2);
+    }
+
+    #[test]
+    fn pro_rata_rounds_to_size_step_and_gives_leftover_fifo() {
+        // three asks of 10 at one level, bid 10, step 2 -> 4/4/2
+        let a1 = mk_order(1, Side::Ask, 100, 10, 10, 1, 0);
+        let a2 = mk_order(2, Side::Ask, 100, 10, 10, 2, 0);
+        let a3 = mk_order(3, Side::Ask, 100, 10, 10, 3, 0);
+        let b  = mk_order(4, Side::Bid, 100, 10, 10, 4, 0);
+        let m = MarketParams { size_step: 2, ..pro_rata_market() };
+
+        let owners = owners(&[1,2,3,4]);
+        let plan = match_market(PairId(1), 1, &m, vec![a1,a2,a3,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        let qtys: Vec<(u64, u64)> = plan.fills.iter().map(|f| (f.seller_order_id.0, f.fill_qty)).collect();
+        assert_eq!(qtys, vec![(1, 4), (2, 4), (3, 2)]);
+    }
+
+    #[test]
+    fn pro_rata_ignores_orders_arriving_after_taker() {
+        // a2 joins the level after the bid, so it is not part of the split
+        let a1 = mk_order(1, Side::Ask, 100, 4, 4, 1, 0);
+        let b  = mk_order(2, Side::Bid, 100, 6, 6, 2, 0);
+        let a2 = mk_order(3, Side::Ask, 100, 4, 4, 3, 0);
+
+        let owners = owners(&[1,2,3]);
+        let plan = match_market(PairId(1), 1, &pro_rata_market(), vec![a1,b,a2], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(plan.fills[0].seller_order_id.0, 1);
+        assert_eq!(plan.fills[0].fill_qty, 4);
+        assert_eq!(plan.fills[1].seller_order_id.0, 3);
+        assert_eq!(plan.fills[1].fill_qty, 2);
+    }
+
+    #[test]
+    fn hybrid_fifo_pro_rata_on_ask_side() {
+        let a1 = mk_order(1, Side::Ask, 100, 4, 4, 10, 0);
+        let a2 = mk_order(2, Side::Ask, 100, 4, 4, 11, 0);
+        let a3 = mk_order(3, Side::Ask, 100, 4, 4, 12, 0);
+        let b  = mk_order(4, Side::Bid, 100, 8, 8, 20, 0);
+        let m = MarketParams { allocation: AllocationPolicy::FifoProRata { fifo_pct: 50 }, ..market() };
+
+        let owners = owners(&[1,2,3,4]);
+        let plan = match_market(PairId(1), 1, &m, vec![a3,a2,a1,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        let qtys: Vec<(u64, u64)> = plan.fills.iter().map(|f| (f.seller_order_id.0, f.fill_qty)).collect();
+        assert_eq!(qtys, vec![(1, 4), (2, 2), (3, 2)]);
+    }
+
+    #[test]
+    fn fifo_same_price_on_bid_side() {
+        // Two bids @100 with ingest_seq ordering; asks consume FIFO
+        let b1 = mk_order(1, Side::Bid, 100, 2, 2, 10, 0);
+        let b2 = mk_order(2, Side::Bid, 100, 4, 4, 11, 0);
+        let a  = mk_order(3, Side::Ask, 100, 5, 5, 20, 0);
+
+        let owners = owners(&[1,2,3]);
+        let plan = match_market(
+            PairId(1), 7, &market(), vec![b2.clone(), a.clone(), b1.clone()],
+            &owners, &StubPoseidon, false, |_b,_m| [0u8;32]
+        ).unwrap();
+
+        assert_eq!(plan.fills.len(), 2);
+        assert_eq!(plan.fills[0].buyer_order_id.0, 1);
+        assert_eq!(plan.fills[0].fill_qty, 2);
+        assert_eq!(plan.fills[1].buyer_order_id.0, 2);
+        assert_eq!(plan.fills[1].fill_qty, 3);
+    }
 
     #[test]
     fn multi_level_crossing_and_partials() {
@@ -270,7 +947,7 @@ mod exec_tests {
         let plan = match_market(
             PairId(1), 9, &market(), vec![a1.clone(), b.clone(), a2.clone()],
             &owners, &StubPoseidon, false, |_b,_m| [0u8;32]
-        );
+        ).unwrap();
 
         assert_eq!(plan.fills.len(), 2);
         assert_eq!(plan.fills[0].price_tick, 95);
@@ -279,7 +956,7 @@ mod exec_tests {
         assert_eq!(plan.fills[1].fill_qty, 3);
 
         // Residuals: bid 0, a1 0, a2 5
-        let map = plan.residuals.iter().map(|r|(r.order_id.0, r.remaining_after)).collect::<HashMap<_,_>>();
+        let map = plan.residuals.iter().map(|r|(r.order_id.0, r.remaining_after)).collect::<BTreeMap<_,_>>();
         assert_eq!(map.get(&3), Some(&0));
         assert_eq!(map.get(&1), Some(&0));
         assert_eq!(map.get(&2), Some(&5));
@@ -300,12 +977,12 @@ mod exec_tests {
         let plan = match_market(
             PairId(1), 100, &market(),
             vec![b1,b2,b3,a1,a2,a3], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]
-        );
+        ).unwrap();
 
         // Ensure we ran multiple fills and fully crossed
         assert!(!plan.fills.is_empty());
-        // Best ask 99 should fill first at 99
-        assert_eq!(plan.fills[0].price_tick, 99);
+        // bid 105 vs ask 99 tie on ingest_seq; lower order_id (the bid) is maker
+        assert_eq!(plan.fills[0].price_tick, 105);
         // Final residuals: check no negative and some orders remain plausible
         assert!(plan.residuals.iter().all(|r| r.remaining_after <= r.remaining_before));
     }
@@ -317,8 +994,8 @@ mod exec_tests {
         let b = mk_order(2, Side::Bid, 100, 5, 5, 2, 0);
         let owners = owners(&[1,2]);
 
-        let p1 = match_market(PairId(1), 1, &m, vec![a.clone(), b.clone()], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]);
-        let p2 = match_market(PairId(1), 2, &m, vec![a, b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]);
+        let p1 = match_market(PairId(1), 1, &m, vec![a.clone(), b.clone()], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        let p2 = match_market(PairId(1), 2, &m, vec![a, b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
 
         assert_eq!(p1.fills.len(), 1);
         assert_eq!(p2.fills.len(), 1);
@@ -333,10 +1010,10 @@ mod exec_tests {
         let b = mk_order(2, Side::Bid, 100, 5, 5, 2, 0);
         let owners = owners(&[1,2]);
 
-        let p_no = match_market(PairId(1), 11, &m, vec![a.clone(), b.clone()], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]);
+        let p_no = match_market(PairId(1), 11, &m, vec![a.clone(), b.clone()], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
         let p_s  = match_market(PairId(1), 11, &m, vec![a, b], &owners, &StubPoseidon, true, |_b,_m| {
             let mut s = [0u8;32]; s[31]=0xAB; s
-        });
+        }).unwrap();
 
         assert_eq!(p_no.fills[0].match_id, 1);
         assert_eq!(p_s.fills[0].match_id, 1);
@@ -350,7 +1027,7 @@ mod exec_tests {
         let b = mk_order(2, Side::Bid, 100, 2, 2, 2, 7);
         let owners = owners(&[1,2]);
 
-        let p = match_market(PairId(1), 77, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]);
+        let p = match_market(PairId(1), 77, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
 
         assert_eq!(p.fills.len(), 1);
         assert_eq!(p.fills[0].time_bucket, 7);
@@ -361,20 +1038,548 @@ mod exec_tests {
         let ask_first = mk_order(1, Side::Ask,  90, 3, 3, 1, 0);
         let bid_second= mk_order(2, Side::Bid, 100, 3, 3, 1, 0);
         let owners = owners(&[1,2]);
-        let p = match_market(PairId(1), 55, &market(), vec![ask_first, bid_second], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]);
+        let p = match_market(PairId(1), 55, &market(), vec![ask_first, bid_second], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
 
         assert_eq!(p.fills.len(), 1);
-        assert_eq!(p.fills[0].price_tick, 90); // maker = resting ask
+        assert_eq!(p.fills[0].price_tick, 90); // ask wins the ingest_seq tie on order_id -> maker
         assert_eq!(p.fills[0].fill_qty, 3);
     }
 
+    fn with_tif(o: Order, tif: TimeInForce) -> Order {
+        Order { tif, ..o }
+    }
+
+    fn cancel_of(p: &ExecutionPlan, id: u64) -> Option<OrderCancel> {
+        p.cancels.iter().copied().find(|c| c.order_id.0 == id)
+    }
+
+    #[test]
+    fn ioc_remainder_is_cancelled_at_end_of_batch() {
+        let a = mk_order(1, Side::Ask, 100, 3, 3, 1, 0);
+        let b = with_tif(mk_order(2, Side::Bid, 100, 5, 5, 2, 0), TimeInForce::Ioc);
+        let owners = owners(&[1,2]);
+
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.fills.len(), 1);
+        assert_eq!(p.fills[0].fill_qty, 3);
+
+        let c = cancel_of(&p, 2).expect("ioc remainder cancelled");
+        assert_eq!(c.cancelled_qty, 2);
+        assert_eq!(c.reason, CancelReason::IocRemainder);
+
+        let r = p.residuals.iter().find(|r| r.order_id.0 == 2).unwrap();
+        assert_eq!((r.remaining_before, r.remaining_after, r.now_filled), (5, 0, false));
+    }
+
+    #[test]
+    fn ioc_without_cross_is_cancelled_untouched() {
+        let a = mk_order(1, Side::Ask, 101, 3, 3, 1, 0);
+        let b = with_tif(mk_order(2, Side::Bid, 100, 5, 5, 2, 0), TimeInForce::Ioc);
+        let owners = owners(&[1,2]);
+
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        assert_eq!(p.cancels.len(), 1);
+        assert_eq!(cancel_of(&p, 2).unwrap().cancelled_qty, 5);
+    }
+
+    #[test]
+    fn fok_short_of_liquidity_produces_no_fills() {
+        let a1 = mk_order(1, Side::Ask, 99, 2, 2, 1, 0);
+        let a2 = mk_order(2, Side::Ask, 100, 2, 2, 2, 0);
+        let a3 = mk_order(3, Side::Ask, 101, 9, 9, 3, 0); // does not cross
+        let b  = with_tif(mk_order(4, Side::Bid, 100, 5, 5, 4, 0), TimeInForce::Fok);
+        let owners = owners(&[1,2,3,4]);
+
+        let p = match_market(PairId(1), 1, &market(), vec![a1,a2,a3,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        let c = cancel_of(&p, 4).unwrap();
+        assert_eq!((c.cancelled_qty, c.reason), (5, CancelReason::FokUnfilled));
+    }
+
+    #[test]
+    fn fok_with_enough_liquidity_fills_completely() {
+        let a1 = mk_order(1, Side::Ask, 99, 2, 2, 1, 0);
+        let a2 = mk_order(2, Side::Ask, 100, 4, 4, 2, 0);
+        let b  = with_tif(mk_order(3, Side::Bid, 100, 5, 5, 3, 0), TimeInForce::Fok);
+        let owners = owners(&[1,2,3]);
+
+        let p = match_market(PairId(1), 1, &market(), vec![a1,a2,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.fills.iter().map(|f| f.fill_qty).sum::<u64>(), 5);
+        assert!(p.cancels.is_empty());
+    }
+
+    #[test]
+    fn fok_does_not_count_other_fok_as_liquidity() {
+        let a = with_tif(mk_order(1, Side::Ask, 100, 5, 5, 1, 0), TimeInForce::Fok);
+        let b = with_tif(mk_order(2, Side::Bid, 100, 5, 5, 2, 0), TimeInForce::Fok);
+        let owners = owners(&[1,2]);
+
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        assert_eq!(p.cancels.len(), 2);
+    }
+
+    #[test]
+    fn post_only_taker_is_rejected() {
+        let a = mk_order(1, Side::Ask, 100, 5, 5, 1, 0);
+        let b = with_tif(mk_order(2, Side::Bid, 101, 5, 5, 2, 0), TimeInForce::PostOnly);
+        let owners = owners(&[1,2]);
+
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        let c = cancel_of(&p, 2).unwrap();
+        assert_eq!((c.cancelled_qty, c.reason), (5, CancelReason::PostOnlyWouldCross));
+    }
+
+    #[test]
+    fn post_only_maker_trades_and_rests() {
+        let a = with_tif(mk_order(1, Side::Ask, 100, 5, 5, 1, 0), TimeInForce::PostOnly);
+        let b = mk_order(2, Side::Bid, 100, 3, 3, 2, 0);
+        let owners = owners(&[1,2]);
+
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.fills.len(), 1);
+        assert_eq!(p.fills[0].fill_qty, 3);
+        assert!(p.cancels.is_empty());
+        let r = p.residuals.iter().find(|r| r.order_id.0 == 1).unwrap();
+        assert_eq!(r.remaining_after, 2);
+    }
+
+    fn uniform_market() -> MarketParams {
+        MarketParams { clearing: ClearingMode::UniformPrice, ..market() }
+    }
+
+    #[test]
+    fn uniform_price_maximises_volume() {
+        // demand: 3@105, 4@101 ; supply: 2@99, 3@100, 5@103
+        // p=100 -> min(7,5)=5, p=101 -> min(7,5)=5 (imbalance 2 both), p=103 -> min(3,10)=3
+        let b1 = mk_order(1, Side::Bid, 105, 3, 3, 1, 0);
+        let b2 = mk_order(2, Side::Bid, 101, 4, 4, 2, 0);
+        let a1 = mk_order(3, Side::Ask,  99, 2, 2, 3, 0);
+        let a2 = mk_order(4, Side::Ask, 100, 3, 3, 4, 0);
+        let a3 = mk_order(5, Side::Ask, 103, 5, 5, 5, 0);
+        let orders = vec![b1, b2, a1, a2, a3];
+
+        // equal volume and imbalance at 100 and 101: lowest price wins
+        assert_eq!(uniform_clearing_price(&orders), Some(100));
+
+        let owners = owners(&[1,2,3,4,5]);
+        let p = match_market(PairId(1), 3, &uniform_market(), orders, &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.clearing_price, Some(100));
+        assert!(p.fills.iter().all(|f| f.price_tick == 100));
+        assert_eq!(p.fills.iter().map(|f| f.fill_qty).sum::<u64>(), 5);
+        // ask @103 never trades
+        assert!(p.fills.iter().all(|f| f.seller_order_id.0 != 5));
+    }
+
+    #[test]
+    fn uniform_price_prefers_smaller_imbalance() {
+        // p=100: demand 6, supply 4 -> vol 4 imb 2 ; p=101: demand 4, supply 4 -> vol 4 imb 0
+        let b1 = mk_order(1, Side::Bid, 101, 4, 4, 1, 0);
+        let b2 = mk_order(2, Side::Bid, 100, 2, 2, 2, 0);
+        let a1 = mk_order(3, Side::Ask, 100, 4, 4, 3, 0);
+        assert_eq!(uniform_clearing_price(&[b1, b2, a1]), Some(101));
+    }
+
+    #[test]
+    fn uniform_price_none_without_cross() {
+        let a = mk_order(1, Side::Ask, 101, 5, 5, 1, 0);
+        let b = mk_order(2, Side::Bid, 100, 5, 5, 2, 0);
+        assert_eq!(uniform_clearing_price(&[a.clone(), b.clone()]), None);
+
+        let owners = owners(&[1,2]);
+        let p = match_market(PairId(1), 1, &uniform_market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        assert_eq!(p.clearing_price, None);
+    }
+
+    #[test]
+    fn continuous_mode_reports_no_clearing_price() {
+        let a = mk_order(1, Side::Ask, 100, 5, 5, 1, 0);
+        let b = mk_order(2, Side::Bid, 100, 5, 5, 2, 0);
+        let owners = owners(&[1,2]);
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.clearing_price, None);
+    }
+
+    fn stp_market(stp: StpMode) -> MarketParams {
+        MarketParams { stp, ..market() }
+    }
+
+    /// Orders 1 and 2 belong to the same owner; 3 is someone else.
+    fn shared_owners() -> OwnerMap {
+        let mut m = owners(&[1,2,3]);
+        m.insert(2, m[&1]);
+        m
+    }
+
+    fn self_cross() -> Vec<Order> {
+        vec![
+            mk_order(1, Side::Ask, 100, 5, 5, 1, 0),   // oldest, same owner as 2
+            mk_order(2, Side::Bid, 100, 3, 3, 2, 0),   // newest
+            mk_order(3, Side::Ask, 101, 4, 4, 3, 0),
+        ]
+    }
+
+    #[test]
+    fn stp_none_allows_self_trade() {
+        let p = match_market(PairId(1), 1, &stp_market(StpMode::None), self_cross(), &shared_owners(), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.fills.len(), 1);
+        assert!(p.cancels.is_empty());
+    }
+
+    #[test]
+    fn stp_cancel_newest_cancels_taker() {
+        let p = match_market(PairId(1), 1, &stp_market(StpMode::CancelNewest), self_cross(), &shared_owners(), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        let c = cancel_of(&p, 2).unwrap();
+        assert_eq!((c.cancelled_qty, c.reason), (3, CancelReason::SelfTrade));
+        assert!(cancel_of(&p, 1).is_none());
+    }
+
+    #[test]
+    fn stp_cancel_oldest_cancels_maker_and_keeps_matching() {
+        let mut orders = self_cross();
+        orders[1].price_tick = 101; // bid can reach the next ask after the self-cross
+        let p = match_market(PairId(1), 1, &stp_market(StpMode::CancelOldest), orders, &shared_owners(), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        let c = cancel_of(&p, 1).unwrap();
+        assert_eq!((c.cancelled_qty, c.reason), (5, CancelReason::SelfTrade));
+        assert_eq!(p.fills.len(), 1);
+        assert_eq!((p.fills[0].buyer_order_id.0, p.fills[0].seller_order_id.0, p.fills[0].fill_qty), (2, 3, 3));
+    }
+
+    #[test]
+    fn stp_cancel_both() {
+        let p = match_market(PairId(1), 1, &stp_market(StpMode::CancelBoth), self_cross(), &shared_owners(), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        assert_eq!(cancel_of(&p, 1).unwrap().cancelled_qty, 5);
+        assert_eq!(cancel_of(&p, 2).unwrap().cancelled_qty, 3);
+    }
+
+    #[test]
+    fn stp_decrement_and_cancel_shrinks_larger_side() {
+        let p = match_market(PairId(1), 1, &stp_market(StpMode::DecrementAndCancel), self_cross(), &shared_owners(), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        assert_eq!(cancel_of(&p, 1).unwrap().cancelled_qty, 3);
+        assert_eq!(cancel_of(&p, 2).unwrap().cancelled_qty, 3);
+
+        let r1 = p.residuals.iter().find(|r| r.order_id.0 == 1).unwrap();
+        assert_eq!((r1.remaining_before, r1.remaining_after, r1.now_filled), (5, 2, false));
+        let r2 = p.residuals.iter().find(|r| r.order_id.0 == 2).unwrap();
+        assert_eq!((r2.remaining_after, r2.now_filled), (0, false));
+    }
+
+    #[test]
+    fn stp_inside_pro_rata_level() {
+        // asks 1 (same owner as bid 2) and 3 share the level; bid only trades with 3
+        let a1 = mk_order(1, Side::Ask, 100, 4, 4, 1, 0);
+        let a3 = mk_order(3, Side::Ask, 100, 4, 4, 2, 0);
+        let b2 = mk_order(2, Side::Bid, 100, 4, 4, 3, 0);
+        let m = MarketParams { allocation: AllocationPolicy::ProRata, stp: StpMode::CancelOldest, ..market() };
+        let mut owners = shared_owners();
+        owners.insert(3, [0x33; 32]);
+
+        let p = match_market(PairId(1), 1, &m, vec![a1,a3,b2], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(cancel_of(&p, 1).unwrap().reason, CancelReason::SelfTrade);
+        assert_eq!(p.fills.len(), 1);
+        assert_eq!((p.fills[0].seller_order_id.0, p.fills[0].fill_qty), (3, 4));
+    }
+
+    #[test]
+    fn fok_does_not_count_own_orders_under_stp() {
+        let a1 = mk_order(1, Side::Ask, 100, 5, 5, 1, 0);
+        let b2 = with_tif(mk_order(2, Side::Bid, 100, 5, 5, 2, 0), TimeInForce::Fok);
+        let p = match_market(PairId(1), 1, &stp_market(StpMode::CancelOldest), vec![a1,b2], &shared_owners(), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty());
+        assert_eq!(cancel_of(&p, 2).unwrap().reason, CancelReason::FokUnfilled);
+        assert!(cancel_of(&p, 1).is_none());
+    }
+
+    #[test]
+    fn only_active_markets_match() {
+        let owners = owners(&[1,2]);
+        for status in [MarketStatus::Paused, MarketStatus::CancelOnly, MarketStatus::Delisted] {
+            let a = mk_order(1, Side::Ask, 100, 5, 5, 1, 0);
+            let b = with_tif(mk_order(2, Side::Bid, 100, 5, 5, 2, 0), TimeInForce::Ioc);
+            let m = MarketParams { status, ..market() };
+            let p = match_market(PairId(1), 1, &m, vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+            assert!(p.fills.is_empty() && p.residuals.is_empty() && p.cancels.is_empty(), "{status:?}");
+        }
+    }
+
+    #[test]
+    fn maker_is_earlier_order_on_either_side() {
+        let owners = owners(&[1,2]);
+
+        // resting bid, incoming ask: bid is maker, price is the bid's
+        let b = mk_order(1, Side::Bid, 102, 5, 5, 1, 0);
+        let a = mk_order(2, Side::Ask,  99, 5, 5, 2, 0);
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!((p.fills[0].maker_side, p.fills[0].price_tick), (Side::Bid, 102));
+
+        // resting ask, incoming bid: ask is maker
+        let a = mk_order(1, Side::Ask,  99, 5, 5, 1, 0);
+        let b = mk_order(2, Side::Bid, 102, 5, 5, 2, 0);
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!((p.fills[0].maker_side, p.fills[0].price_tick), (Side::Ask, 99));
+    }
+
+    #[test]
+    fn fees_use_maker_and_taker_rates_rounded_up() {
+        // notional 100 * 5 = 500; maker 3bps -> 0.15 -> 1 ; taker 7bps -> 0.35 -> 1
+        let a = mk_order(1, Side::Ask, 100, 5, 5, 1, 0);
+        let b = mk_order(2, Side::Bid, 100, 5, 5, 2, 0);
+        let owners = owners(&[1,2]);
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!((p.fills[0].maker_fee, p.fills[0].taker_fee), (1, 1));
+
+        assert_eq!(fee_amount(10_000, 100, 3), 300);
+        assert_eq!(fee_amount(10_000, 100, 7), 700);
+        assert_eq!(fee_amount(3, 3, 10), 1);
+        assert_eq!(fee_amount(100, 5, 0), 0);
+    }
+
+    fn stop(o: Order, kind: StopKind, trigger_tick: u64) -> Order {
+        Order { stop: Some(StopTrigger { kind, trigger_tick, triggered: false }), ..o }
+    }
+
+    fn fills_of(p: &ExecutionPlan) -> Vec<(u64, u64, u64, u64)> {
+        p.fills.iter().map(|f| (f.buyer_order_id.0, f.seller_order_id.0, f.price_tick, f.fill_qty)).collect()
+    }
+
+    #[test]
+    fn stop_waits_off_book_without_a_trade() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 1, 1, 1, 0),
+            stop(mk_order(2, Side::Bid, 100, 1, 1, 2, 0), StopKind::Limit, 100),
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert!(p.fills.is_empty() && p.triggered.is_empty() && p.residuals.is_empty());
+    }
+
+    #[test]
+    fn stop_limit_triggers_on_last_trade_price() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 2, 2, 1, 0),
+            mk_order(2, Side::Bid, 100, 1, 1, 2, 0),
+            stop(mk_order(3, Side::Bid, 101, 1, 1, 3, 0), StopKind::Limit, 100),
+            stop(mk_order(4, Side::Bid, 101, 1, 1, 4, 0), StopKind::Limit, 101), // not reached
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2, 3, 4]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(fills_of(&p), vec![(2, 1, 100, 1), (3, 1, 100, 1)]);
+        assert_eq!(p.triggered, vec![OrderTrigger { order_id: OrderId(3), ingest_seq: 5, last_price: 100 }]);
+    }
+
+    #[test]
+    fn sell_stop_triggers_at_or_below() {
+        let orders = vec![
+            mk_order(1, Side::Bid, 100, 1, 1, 1, 0),
+            mk_order(2, Side::Ask, 100, 1, 1, 2, 0),
+            mk_order(3, Side::Bid, 95, 1, 1, 3, 0),
+            stop(mk_order(4, Side::Ask, 95, 1, 1, 4, 0), StopKind::Limit, 95),  // fired by 5's fill
+            stop(mk_order(5, Side::Ask, 95, 1, 1, 5, 0), StopKind::Limit, 100),
+            stop(mk_order(6, Side::Ask, 90, 1, 1, 6, 0), StopKind::Limit, 94),  // never reached
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2, 3, 4, 5, 6]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.triggered.iter().map(|t| t.order_id.0).collect::<Vec<_>>(), vec![5, 4]);
+        assert_eq!(fills_of(&p), vec![(1, 2, 100, 1), (3, 5, 95, 1)]);
+    }
+
+    #[test]
+    fn stops_cascade_in_one_batch() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 1, 1, 1, 0),
+            mk_order(2, Side::Ask, 105, 1, 1, 2, 0),
+            mk_order(3, Side::Ask, 110, 1, 1, 3, 0),
+            // listed out of order; fires second
+            stop(mk_order(4, Side::Bid, 110, 1, 1, 4, 0), StopKind::Limit, 105),
+            stop(mk_order(5, Side::Bid, 105, 1, 1, 5, 0), StopKind::Market, 100),
+            mk_order(6, Side::Bid, 100, 1, 1, 6, 0),
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2, 3, 4, 5, 6]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(fills_of(&p), vec![(6, 1, 100, 1), (5, 2, 105, 1), (4, 3, 110, 1)]);
+        assert_eq!(p.triggered.iter().map(|t| (t.order_id.0, t.ingest_seq, t.last_price)).collect::<Vec<_>>(),
+                   vec![(5, 7, 100), (4, 8, 105)]);
+    }
+
+    #[test]
+    fn triggered_stops_fire_in_arrival_order() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 3, 3, 1, 0),
+            stop(mk_order(3, Side::Bid, 100, 1, 1, 9, 0), StopKind::Limit, 100),
+            stop(mk_order(2, Side::Bid, 100, 1, 1, 8, 0), StopKind::Limit, 100),
+            mk_order(4, Side::Bid, 100, 1, 1, 10, 0),
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2, 3, 4]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.triggered.iter().map(|t| (t.order_id.0, t.ingest_seq)).collect::<Vec<_>>(), vec![(2, 11), (3, 12)]);
+        assert_eq!(fills_of(&p), vec![(4, 1, 100, 1), (2, 1, 100, 1), (3, 1, 100, 1)]);
+    }
+
+    #[test]
+    fn stop_market_remainder_is_cancelled() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 2, 2, 1, 0),
+            mk_order(2, Side::Bid, 100, 1, 1, 2, 0),
+            stop(mk_order(3, Side::Bid, 100, 5, 5, 3, 0), StopKind::Market, 100),
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2, 3]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(fills_of(&p), vec![(2, 1, 100, 1), (3, 1, 100, 1)]);
+        let c = cancel_of(&p, 3).unwrap();
+        assert_eq!((c.cancelled_qty, c.reason), (4, CancelReason::IocRemainder));
+    }
+
+    #[test]
+    fn uniform_stop_cascade_keeps_one_price() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 2, 2, 1, 0),
+            mk_order(2, Side::Bid, 100, 1, 1, 2, 0),
+            mk_order(3, Side::Ask, 105, 1, 1, 3, 0),
+            // fired at 100; alone against ask 3 it would clear at 105
+            stop(mk_order(4, Side::Bid, 110, 3, 3, 4, 0), StopKind::Limit, 100),
+        ];
+        let p = match_market(PairId(1), 1, &uniform_market(), orders, &owners(&[1, 2, 3, 4]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(p.clearing_price, Some(100));
+        assert_eq!(fills_of(&p), vec![(2, 1, 100, 1), (4, 1, 100, 1)]);
+        assert_eq!(p.triggered.iter().map(|t| t.order_id.0).collect::<Vec<_>>(), vec![4]);
+        // ask 3 doesn't accept 100; the stop rests for the next auction
+        assert_eq!(p.residuals.iter().find(|r| r.order_id.0 == 4).unwrap().remaining_after, 2);
+        assert!(p.fills.iter().all(|f| f.seller_order_id.0 != 3));
+    }
+
+    fn iceberg(o: Order, display: u64) -> Order {
+        Order { display_qty: Some(display), ..o }
+    }
+
+    #[test]
+    fn iceberg_slice_refills_behind_the_level() {
+        let orders = vec![
+            iceberg(mk_order(1, Side::Ask, 100, 10, 10, 1, 0), 3),
+            mk_order(2, Side::Ask, 100, 3, 3, 2, 0),
+            mk_order(3, Side::Bid, 100, 8, 8, 3, 0),
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2, 3]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(fills_of(&p), vec![(3, 1, 100, 3), (3, 2, 100, 3), (3, 1, 100, 2)]);
+        assert_eq!(p.refills, vec![OrderRefill { order_id: OrderId(1), ingest_seq: 4 }]);
+        // refilled slice keeps its maker role and price
+        assert!(p.fills.iter().all(|f| f.maker_side == Side::Ask));
+    }
+
+    #[test]
+    fn iceberg_reserve_keeps_price_priority() {
+        let orders = vec![
+            iceberg(mk_order(1, Side::Ask, 100, 6, 6, 1, 0), 2),
+            mk_order(2, Side::Ask, 101, 5, 5, 2, 0),
+            mk_order(3, Side::Bid, 101, 6, 6, 3, 0),
+        ];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2, 3]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(fills_of(&p), vec![(3, 1, 100, 2), (3, 1, 100, 2), (3, 1, 100, 2)]);
+        assert_eq!(p.refills.iter().map(|r| r.ingest_seq).collect::<Vec<_>>(), vec![4, 5]);
+    }
+
+    #[test]
+    fn pro_rata_counts_only_displayed_qty() {
+        let orders = vec![
+            iceberg(mk_order(1, Side::Ask, 100, 100, 100, 1, 0), 10),
+            mk_order(2, Side::Ask, 100, 10, 10, 2, 0),
+            mk_order(3, Side::Bid, 100, 10, 10, 3, 0),
+        ];
+        let p = match_market(PairId(1), 1, &pro_rata_market(), orders, &owners(&[1, 2, 3]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        assert_eq!(fills_of(&p), vec![(3, 1, 100, 5), (3, 2, 100, 5)]);
+        assert!(p.refills.is_empty());
+    }
+
+    #[test]
+    fn displayed_tracks_current_slice() {
+        let o = iceberg(mk_order(1, Side::Ask, 100, 10, 10, 1, 0), 4);
+        assert_eq!(o.displayed(), 4);
+        assert_eq!(Order { remaining: 7, ..o.clone() }.displayed(), 1);
+        assert_eq!(Order { remaining: 6, ..o.clone() }.displayed(), 4);
+        assert_eq!(Order { remaining: 2, ..o }.displayed(), 2);
+    }
+
+    #[test]
+    fn bad_inputs_are_errors_not_panics() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 1, 1, 1, 0),
+            mk_order(2, Side::Bid, 100, 1, 1, 2, 0),
+            mk_order(3, Side::Bid, 100, 1, 1, 3, 0),
+        ];
+        let err = match_market(PairId(1), 1, &market(), orders.clone(), &owners(&[1]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap_err();
+        assert_eq!(err, MatchError::MissingOwners(vec![OrderId(2), OrderId(3)]));
+        assert_eq!(err.offending_orders(), &[OrderId(2), OrderId(3)]);
+
+        let foreign = vec![Order { pair_id: PairId(9), ..orders[0].clone() }];
+        let err = match_market(PairId(1), 1, &market(), foreign, &owners(&[1]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap_err();
+        assert_eq!(err, MatchError::ForeignOrders(vec![OrderId(1)]));
+
+        let err = match_market(PairId(2), 1, &market(), orders, &owners(&[1, 2, 3]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap_err();
+        assert_eq!(err, MatchError::PairMismatch { market: PairId(1), requested: PairId(2) });
+        assert!(err.offending_orders().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn plan_round_trips_through_serde() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 5, 5, 1, 0),
+            with_tif(mk_order(2, Side::Bid, 101, 8, 8, 2, 0), TimeInForce::Ioc),
+        ];
+        let p = match_market(PairId(1), 3, &market(), orders, &owners(&[1, 2]), &StubPoseidon, true, |_b,_m| [7u8;32]).unwrap();
+        let json = serde_json::to_string(&p).unwrap();
+        let back: ExecutionPlan = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&back).unwrap(), json);
+        assert_eq!(back.fills[0].maker_fee, p.fills[0].maker_fee);
+    }
+
+    #[test]
+    fn plan_is_canonical_regardless_of_input_order() {
+        let orders = vec![
+            mk_order(1, Side::Ask, 100, 2, 2, 1, 0),
+            mk_order(2, Side::Ask, 101, 2, 2, 2, 0),
+            mk_order(3, Side::Ask, 102, 2, 2, 3, 0),
+            with_tif(mk_order(4, Side::Bid, 102, 5, 5, 4, 0), TimeInForce::Ioc),
+            with_tif(mk_order(5, Side::Bid, 99, 1, 1, 5, 0), TimeInForce::Ioc),
+        ];
+        let owners = owners(&[1, 2, 3, 4, 5]);
+        let run = |orders: Vec<Order>| {
+            match_market(PairId(1), 9, &market(), orders, &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap()
+        };
+        let a = run(orders.clone());
+        let b = run(orders.into_iter().rev().collect());
+
+        let ids: Vec<u64> = a.residuals.iter().map(|r| r.order_id.0).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        assert_eq!(a.cancels.iter().map(|c| c.order_id.0).collect::<Vec<_>>(), vec![5]);
+        assert_eq!(a.digest(&StubPoseidon), b.digest(&StubPoseidon));
+    }
+
+    #[test]
+    fn digest_covers_fills_and_residuals() {
+        let orders = vec![mk_order(1, Side::Ask, 100, 3, 3, 1, 0), mk_order(2, Side::Bid, 100, 2, 2, 2, 0)];
+        let p = match_market(PairId(1), 1, &market(), orders, &owners(&[1, 2]), &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
+        let d = p.digest(&StubPoseidon);
+
+        let mut q = p.clone();
+        q.fills[0].fill_qty += 1;
+        assert_ne!(q.digest(&StubPoseidon), d);
+
+        let mut q = p.clone();
+        q.residuals.swap(0, 1);
+        assert_ne!(q.digest(&StubPoseidon), d);
+
+        let mut q = p;
+        q.residuals.pop();
+        assert_ne!(q.digest(&StubPoseidon), d);
+    }
+
     #[test]
     fn no_cross_produces_no_fills() {
         let a = mk_order(1, Side::Ask, 101, 5, 5, 1, 0);
         let b = mk_order(2, Side::Bid, 100, 5, 5, 1, 0);
         let owners = owners(&[1,2]);
 
-        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]);
+        let p = match_market(PairId(1), 1, &market(), vec![a,b], &owners, &StubPoseidon, false, |_b,_m| [0u8;32]).unwrap();
         assert!(p.fills.is_empty());
         assert!(p.residuals.is_empty());
     }
@@ -0,0 +1,231 @@
+//! Binary Merkle trees over 32-byte leaves, for commitments that need cheap
+//! per-leaf inclusion proofs. The last node of an odd level is carried up
+//! unchanged (never duplicated), and the root is sealed with the leaf
+//! count, so a proof pins both the leaf's position and the tree size.
+//!
+//! `SparseMerkle` is the keyed variant for state: a full depth-64 tree over
+//! `u64` keys where absent keys hold the zero leaf.
+use crate::hash::{domains, Hasher};
+use alloc::{collections::{BTreeMap, BTreeSet}, vec::Vec};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MerkleProof {
+    pub index: u64,
+    pub leaf_count: u64,
+    /// Bottom-up; levels where the node is carried up contribute none.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// The leaf count as a 32-byte big-endian word (a small field element for
+/// Poseidon, a uint256 for the settlement contract).
+fn count_word(n: u64) -> [u8; 32] {
+    let mut w = [0u8; 32];
+    w[24..].copy_from_slice(&n.to_be_bytes());
+    w
+}
+
+fn seal<H: Hasher>(h: &H, leaf_count: u64, top: [u8; 32]) -> [u8; 32] {
+    h.h2(domains::MERKLE_ROOT, count_word(leaf_count), top)
+}
+
+fn next_level<H: Hasher>(h: &H, node_tag: u64, level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match *pair {
+            [l, r] => h.h2(node_tag, l, r),
+            [carried] => carried,
+            _ => unreachable!("chunks(2)"),
+        })
+        .collect()
+}
+
+/// Root over `leaves`; `node_tag` separates one commitment's inner nodes
+/// from another's. An empty tree seals an all-zero top node.
+pub fn merkle_root<H: Hasher>(h: &H, node_tag: u64, leaves: &[[u8; 32]]) -> [u8; 32] {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(h, node_tag, &level);
+    }
+    seal(h, leaves.len() as u64, level.first().copied().unwrap_or([0u8; 32]))
+}
+
+/// Inclusion proof for `leaves[index]`, or `None` when out of range.
+pub fn merkle_proof<H: Hasher>(h: &H, node_tag: u64, leaves: &[[u8; 32]], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut siblings = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut i = index;
+    while level.len() > 1 {
+        if let Some(s) = level.get(i ^ 1) {
+            siblings.push(*s);
+        }
+        level = next_level(h, node_tag, &level);
+        i /= 2;
+    }
+    Some(MerkleProof { index: index as u64, leaf_count: leaves.len() as u64, siblings })
+}
+
+/// Recomputes the root from `leaf` and `proof`. Rejects proofs with
+/// missing or surplus siblings.
+pub fn verify_merkle<H: Hasher>(h: &H, node_tag: u64, root: [u8; 32], leaf: [u8; 32], proof: &MerkleProof) -> bool {
+    if proof.index >= proof.leaf_count {
+        return false;
+    }
+    let (mut acc, mut i, mut width) = (leaf, proof.index, proof.leaf_count);
+    let mut siblings = proof.siblings.iter();
+    while width > 1 {
+        if i ^ 1 < width {
+            let Some(s) = siblings.next() else { return false };
+            acc = if i & 1 == 0 { h.h2(node_tag, acc, *s) } else { h.h2(node_tag, *s, acc) };
+        }
+        i /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && seal(h, proof.leaf_count, acc) == root
+}
+
+/// Depth-64 sparse Merkle tree. Only non-empty nodes are stored; an empty
+/// subtree of height `k` hashes to `empty[k]`.
+#[derive(Clone, Debug)]
+pub struct SparseMerkle {
+    node_tag: u64,
+    empty: [[u8; 32]; 65],
+    nodes: BTreeMap<(u8, u64), [u8; 32]>, // (height, key >> height)
+}
+
+impl SparseMerkle {
+    pub const DEPTH: u8 = 64;
+
+    pub fn new<H: Hasher>(h: &H, node_tag: u64) -> Self {
+        let mut empty = [[0u8; 32]; 65];
+        for k in 1..empty.len() {
+            empty[k] = h.h2(node_tag, empty[k - 1], empty[k - 1]);
+        }
+        Self { node_tag, empty, nodes: BTreeMap::new() }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.node(Self::DEPTH, 0)
+    }
+
+    /// The leaf at `key`, zero when absent.
+    pub fn leaf(&self, key: u64) -> [u8; 32] {
+        self.node(0, key)
+    }
+
+    fn node(&self, height: u8, prefix: u64) -> [u8; 32] {
+        self.nodes.get(&(height, prefix)).copied().unwrap_or(self.empty[height as usize])
+    }
+
+    fn put(&mut self, height: u8, prefix: u64, v: [u8; 32]) {
+        if v == self.empty[height as usize] {
+            self.nodes.remove(&(height, prefix));
+        } else {
+            self.nodes.insert((height, prefix), v);
+        }
+    }
+
+    /// Set several leaves (zero removes) and rehash each touched path once,
+    /// so neighbouring keys share the work above them.
+    pub fn update<H: Hasher>(&mut self, h: &H, leaves: impl IntoIterator<Item = (u64, [u8; 32])>) {
+        let mut dirty = BTreeSet::new();
+        for (key, v) in leaves {
+            self.put(0, key, v);
+            dirty.insert(key);
+        }
+        for height in 1..=Self::DEPTH {
+            dirty = dirty.into_iter().map(|p| p >> 1).collect();
+            for &p in &dirty {
+                let v = h.h2(self.node_tag, self.node(height - 1, p << 1), self.node(height - 1, p << 1 | 1));
+                self.put(height, p, v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pid::StubPoseidon;
+
+    const NODE: u64 = 0x6e6f6465;
+
+    fn leaves(n: u8) -> Vec<[u8; 32]> {
+        (0..n).map(|i| StubPoseidon.h_bytes(1, &[i])).collect()
+    }
+
+    #[test]
+    fn every_leaf_proves_against_the_root() {
+        let h = StubPoseidon;
+        for n in 1..=9 {
+            let ls = leaves(n);
+            let root = merkle_root(&h, NODE, &ls);
+            for (i, leaf) in ls.iter().enumerate() {
+                let p = merkle_proof(&h, NODE, &ls, i).unwrap();
+                assert!(p.siblings.len() <= 4);
+                assert!(verify_merkle(&h, NODE, root, *leaf, &p), "n={n} i={i}");
+            }
+            assert_eq!(merkle_proof(&h, NODE, &ls, ls.len()), None);
+        }
+    }
+
+    #[test]
+    fn tampered_proofs_fail() {
+        let h = StubPoseidon;
+        let ls = leaves(5);
+        let root = merkle_root(&h, NODE, &ls);
+        let p = merkle_proof(&h, NODE, &ls, 2).unwrap();
+
+        assert!(!verify_merkle(&h, NODE, root, ls[3], &p));
+        assert!(!verify_merkle(&h, NODE + 1, root, ls[2], &p));
+        let moved = MerkleProof { index: 3, ..p.clone() };
+        assert!(!verify_merkle(&h, NODE, root, ls[2], &moved));
+        let mut short = p.clone();
+        short.siblings.pop();
+        assert!(!verify_merkle(&h, NODE, root, ls[2], &short));
+        let mut long = p.clone();
+        long.siblings.push([0; 32]);
+        assert!(!verify_merkle(&h, NODE, root, ls[2], &long));
+        let out_of_range = MerkleProof { index: 5, ..p };
+        assert!(!verify_merkle(&h, NODE, root, ls[2], &out_of_range));
+    }
+
+    #[test]
+    fn root_binds_the_leaf_count() {
+        let h = StubPoseidon;
+        let ls = leaves(3);
+        // [a, b, c] carries c up; the two-leaf tree [H(a,b), c] has the same top
+        let folded = [h.h2(NODE, ls[0], ls[1]), ls[2]];
+        assert_ne!(merkle_root(&h, NODE, &ls), merkle_root(&h, NODE, &folded));
+        assert_ne!(merkle_root(&h, NODE, &[]), merkle_root(&h, NODE, &[[0; 32]]));
+    }
+
+    #[test]
+    fn sparse_root_depends_only_on_the_leaf_set() {
+        let h = StubPoseidon;
+        let ls = leaves(4);
+        let keys = [0, 1, 7, u64::MAX];
+
+        let mut batch = SparseMerkle::new(&h, NODE);
+        let empty = batch.root();
+        batch.update(&h, keys.into_iter().zip(ls.iter().copied()));
+
+        let mut one_by_one = SparseMerkle::new(&h, NODE);
+        for (k, l) in keys.into_iter().zip(ls.iter().copied()).rev() {
+            one_by_one.update(&h, [(k, l)]);
+        }
+        assert_eq!(batch.root(), one_by_one.root());
+        assert_eq!(batch.leaf(7), ls[2]);
+        assert_eq!(batch.leaf(8), [0; 32]);
+
+        // clearing every key gives back the empty tree, with nothing stored
+        batch.update(&h, keys.map(|k| (k, [0; 32])));
+        assert_eq!(batch.root(), empty);
+        assert!(batch.nodes.is_empty());
+        one_by_one.update(&h, [(1, [0; 32])]);
+        assert_ne!(one_by_one.root(), empty);
+    }
+}
@@ -1,11 +1,14 @@
-use crate::types::PkHash;
-
-pub trait Poseidon32 {
-    fn hash_many32(&self, domain_tag: u64, elems: &[[u8;32]]) -> [u8;32];
-}
+use crate::{hash::Hasher, types::PkHash};
+use alloc::vec;
 
+/// Tests only, not a real hash. PIDs are the XOR of the inputs under the
+/// tag (fully linkable); `h_bytes`/`h2` are an order-sensitive byte mixer
+/// (FNV-1a in four lanes) so tests can tell digests apart.
+#[cfg(any(test, feature = "test-stubs"))]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct StubPoseidon;
-impl Poseidon32 for StubPoseidon {
+#[cfg(any(test, feature = "test-stubs"))]
+impl Hasher for StubPoseidon {
     fn hash_many32(&self, domain_tag: u64, elems: &[[u8;32]]) -> [u8;32] {
         let mut out = [0u8; 32];
         out[..8].copy_from_slice(&domain_tag.to_be_bytes());
@@ -14,6 +17,24 @@ impl Poseidon32 for StubPoseidon {
         }
         out
     }
+
+    fn h_bytes(&self, domain_tag: u64, bytes: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        for (lane, chunk) in out.chunks_mut(8).enumerate() {
+            let mut x = 0xcbf2_9ce4_8422_2325u64 ^ domain_tag ^ lane as u64;
+            for &b in bytes {
+                x = (x ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+            }
+            chunk.copy_from_slice(&x.to_le_bytes());
+        }
+        out
+    }
+    fn h2(&self, domain_tag: u64, a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        let mut v = [0u8; 64];
+        v[..32].copy_from_slice(&a);
+        v[32..].copy_from_slice(&b);
+        self.h_bytes(domain_tag, &v)
+    }
 }
 
 /// Domain separators ( both host and SP1)
@@ -21,7 +42,12 @@ pub const DS_PID: u64 = 0x_7069645f00000001;
 
 /// Derive a per-fill PID.
 ///  If you don't use fill_salt, pass None.
-pub fn derive_pid<H: Poseidon32>(
+///
+/// Under `PoseidonBn254` every input is a BN254 field element, so a
+/// `pk_hash` at or above the modulus r aliases `pk_hash - r`: both owners
+/// get the same PIDs. Owner hashes must be below r; a circuit-side hash
+/// (Poseidon, or any hash reduced mod r) always is.
+pub fn derive_pid<H: Hasher>(
     h: &H,
     pk_hash: &PkHash,
     batch_id: u64,
@@ -43,8 +69,6 @@ pub fn derive_pid<H: Poseidon32>(
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn pid_changes_with_salt_and_ids() {
         let h = super::StubPoseidon;
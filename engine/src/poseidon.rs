@@ -0,0 +1,163 @@
+//! Poseidon over the BN254 scalar field with circomlib's parameters (x^5
+//! S-box, 8 full rounds, width = inputs + 1, no capacity tag), so every
+//! digest here can be recomputed in a circuit with circomlib `Poseidon(n)`.
+//!
+//! Encoding into field elements:
+//! - the domain tag is always the first input;
+//! - a 32-byte value is read big-endian and reduced mod r, so values at or
+//!   above r alias smaller ones (see `derive_pid`);
+//! - a byte string is its length followed by 31-byte big-endian chunks.
+//!
+//! circomlib stops at 12 inputs per call here; longer inputs chain, with
+//! the running digest as first input of the next call.
+use crate::hash::Hasher;
+use alloc::vec::Vec;
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use light_poseidon::{Poseidon, PoseidonHasher as _};
+
+/// Inputs per circomlib call (the largest width with published constants).
+const MAX_INPUTS: usize = 12;
+/// Bytes per field element when packing byte strings; always below r.
+const CHUNK: usize = 31;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PoseidonBn254;
+
+/// circomlib `Poseidon(inputs.len())` for 1..=12 inputs.
+pub fn poseidon_fr(inputs: &[Fr]) -> Fr {
+    let mut p = Poseidon::<Fr>::new_circom(inputs.len()).expect("1..=12 inputs");
+    p.hash(inputs).expect("width matches inputs")
+}
+
+/// Any number of inputs: one call up to 12, otherwise chained.
+fn poseidon_chain(inputs: &[Fr]) -> Fr {
+    if inputs.len() <= MAX_INPUTS {
+        return poseidon_fr(inputs);
+    }
+    let (head, mut rest) = inputs.split_at(MAX_INPUTS);
+    let mut acc = poseidon_fr(head);
+    while !rest.is_empty() {
+        let (chunk, tail) = rest.split_at(rest.len().min(MAX_INPUTS - 1));
+        let mut next = Vec::with_capacity(chunk.len() + 1);
+        next.push(acc);
+        next.extend_from_slice(chunk);
+        acc = poseidon_fr(&next);
+        rest = tail;
+    }
+    acc
+}
+
+#[inline]
+pub fn fr_from_u64(x: u64) -> Fr {
+    Fr::from(x)
+}
+
+/// `b` big-endian, reduced mod r: `b` and `b + r` give the same element.
+#[inline]
+pub fn fr_from_bytes32(b: &[u8; 32]) -> Fr {
+    Fr::from_be_bytes_mod_order(b)
+}
+
+/// Canonical 32-byte big-endian form.
+pub fn fr_to_bytes32(x: Fr) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&x.into_bigint().to_bytes_be());
+    out
+}
+
+impl Hasher for PoseidonBn254 {
+    fn h_bytes(&self, domain_tag: u64, bytes: &[u8]) -> [u8; 32] {
+        let mut inputs = Vec::with_capacity(bytes.len().div_ceil(CHUNK) + 2);
+        inputs.push(fr_from_u64(domain_tag));
+        inputs.push(fr_from_u64(bytes.len() as u64));
+        inputs.extend(bytes.chunks(CHUNK).map(Fr::from_be_bytes_mod_order));
+        fr_to_bytes32(poseidon_chain(&inputs))
+    }
+
+    fn h2(&self, domain_tag: u64, a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        fr_to_bytes32(poseidon_fr(&[fr_from_u64(domain_tag), fr_from_bytes32(&a), fr_from_bytes32(&b)]))
+    }
+
+    fn hash_many32(&self, domain_tag: u64, elems: &[[u8; 32]]) -> [u8; 32] {
+        let mut inputs = Vec::with_capacity(elems.len() + 1);
+        inputs.push(fr_from_u64(domain_tag));
+        inputs.extend(elems.iter().map(fr_from_bytes32));
+        fr_to_bytes32(poseidon_chain(&inputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex32(s: &str) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    fn fr(xs: &[u64]) -> Vec<Fr> {
+        xs.iter().copied().map(fr_from_u64).collect()
+    }
+
+    // circomlib test vectors (circomlibjs poseidon, test/poseidon.js)
+    #[test]
+    fn circomlib_known_answers() {
+        assert_eq!(
+            fr_to_bytes32(poseidon_fr(&fr(&[1]))),
+            hex32("29176100eaa962bdc1fe6c654d6a3c130e96a4d1168b33848b897dc502820133"),
+        );
+        assert_eq!(
+            fr_to_bytes32(poseidon_fr(&fr(&[1, 2]))),
+            hex32("115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"),
+        );
+        assert_eq!(
+            fr_to_bytes32(poseidon_fr(&fr(&[1, 2, 3, 4]))),
+            hex32("299c867db6c1fdd79dcefa40e4510b9837e60ebb1ce0663dbaa525df65250465"),
+        );
+    }
+
+    // pinned outputs of the encodings above; a change here is a format change
+    #[test]
+    fn engine_encoding_vectors() {
+        let h = PoseidonBn254;
+        let pid = crate::derive_pid(&h, &[0x11; 32], 7, 1, None);
+        assert_eq!(pid, hex32(PID_7_1));
+        assert_eq!(h.h2(1, [0; 32], [0; 32]), hex32(H2_1_ZERO));
+        assert_eq!(h.h_bytes(2, b"poseidon"), hex32(H_BYTES_2));
+    }
+
+    #[test]
+    fn long_inputs_chain_and_stay_distinct() {
+        let h = PoseidonBn254;
+        let long = [0xABu8; 31 * 30];
+        let mut other = long;
+        other[31 * 29] ^= 1;
+        assert_ne!(h.h_bytes(3, &long), h.h_bytes(3, &other));
+        assert_ne!(h.h_bytes(3, &long[..31 * 29]), h.h_bytes(3, &long));
+        // trailing zero bytes still change the digest through the length
+        assert_ne!(h.h_bytes(3, &[1, 0]), h.h_bytes(3, &[1]));
+
+        let elems: Vec<[u8; 32]> = (0..20u8).map(|i| [i; 32]).collect();
+        assert_ne!(h.hash_many32(4, &elems), h.hash_many32(4, &elems[..19]));
+    }
+
+    // documented on `derive_pid`: pk hashes at or above r alias
+    #[test]
+    fn pk_hashes_past_the_modulus_alias() {
+        let h = PoseidonBn254;
+        let mut pk = [0u8; 32];
+        pk.copy_from_slice(&Fr::MODULUS.to_bytes_be());
+        pk[31] += 5; // r + 5; r ends in 0x01
+        let mut five = [0u8; 32];
+        five[31] = 5;
+        assert_eq!(crate::derive_pid(&h, &pk, 7, 1, None), crate::derive_pid(&h, &five, 7, 1, None));
+    }
+
+    const PID_7_1: &str = "0ae05fb393c2567608f5344818da47ca4a92323732304a3a1f9d0c39e2425ae6";
+    const H2_1_ZERO: &str = "24143ae37a030e81da7ccdce00d74f3f8eeec3706b178221a64f36f726a98315";
+    const H_BYTES_2: &str = "1ede1e1ee0015334344356e6f72a7e3f12011ec99b0aa4b0164801c3a26b3764";
+}
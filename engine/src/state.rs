@@ -0,0 +1,323 @@
+//! Open-order state behind `new_state_root`: a sparse Merkle tree keyed by
+//! `OrderId` whose leaves are `commit::order_leaf` of each open order as it
+//! stands after the batch.
+//!
+//! A block moves the state at its `parent_state_root` forward in three
+//! steps: the cancels and amends made since the previous block
+//! (`BookUpdate`s, listed in the block), then the orders that arrived, then
+//! what matching did to them (`BatchEffects`). The new root depends only on
+//! the parent state and the block, which is what lets the zk guest
+//! recompute it.
+use crate::commit::{domains, order_leaf};
+use crate::hash::Hasher;
+use crate::merkle::SparseMerkle;
+use crate::types::*;
+use alloc::{collections::{BTreeMap, BTreeSet}, vec::Vec};
+
+/// What one batch did to the open orders, flattened across markets in
+/// plan order.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BatchEffects<'a> {
+    pub rejected: &'a [OrderReject],
+    pub quarantined: &'a [OrderId],
+    pub triggered: &'a [OrderTrigger],
+    pub refills: &'a [OrderRefill],
+    pub residuals: &'a [OrderResidual],
+}
+
+/// A change to an open order made between blocks.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BookUpdate {
+    Cancel(OrderId),
+    /// See `Order::amended`; amending to zero remaining cancels.
+    Amend { order_id: OrderId, price_tick: u64, remaining: u64, requeue_seq: u64 },
+}
+
+impl BookUpdate {
+    pub fn order_id(&self) -> OrderId {
+        match *self {
+            BookUpdate::Cancel(id) | BookUpdate::Amend { order_id: id, .. } => id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StateError {
+    UnknownOrder(OrderId),   // an update or effect names an order that is not open
+    DuplicateOrder(OrderId), // a new order is already open
+}
+
+#[derive(Clone)]
+pub struct OrderState<H: Hasher> {
+    hasher: H,
+    orders: BTreeMap<OrderId, Order>,
+    tree: SparseMerkle,
+}
+
+impl<H: Hasher> OrderState<H> {
+    pub fn new(hasher: H) -> Self {
+        let tree = SparseMerkle::new(&hasher, domains::STATE_NODE);
+        Self { hasher, orders: BTreeMap::new(), tree }
+    }
+
+    /// The state holding exactly `orders`, e.g. a block's parent state.
+    pub fn from_orders(hasher: H, orders: impl IntoIterator<Item = Order>) -> Result<Self, StateError> {
+        let mut state = Self::new(hasher);
+        let mut touched = BTreeSet::new();
+        for o in orders {
+            touched.insert(o.order_id);
+            if let Some(dup) = state.orders.insert(o.order_id, o) {
+                return Err(StateError::DuplicateOrder(dup.order_id));
+            }
+        }
+        state.flush(touched);
+        Ok(state)
+    }
+
+    /// Root of the empty state; the `parent_state_root` of the first block.
+    pub fn genesis_root(hasher: &H) -> [u8; 32] {
+        SparseMerkle::new(hasher, domains::STATE_NODE).root()
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    pub fn get(&self, id: OrderId) -> Option<&Order> {
+        self.orders.get(&id)
+    }
+
+    /// Open orders by `OrderId`.
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.orders.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// The updates that bring this state to `open` (e.g. what the database
+    /// holds now), and the orders of `open` that must then be added as new.
+    /// An order changed in a way no amend explains is cancelled and added
+    /// again.
+    pub fn diff(&self, open: Vec<Order>) -> (Vec<BookUpdate>, Vec<Order>) {
+        let ids: BTreeSet<OrderId> = open.iter().filter(|o| o.is_open()).map(|o| o.order_id).collect();
+        let mut updates: Vec<BookUpdate> = self.orders.keys()
+            .filter(|id| !ids.contains(id))
+            .map(|id| BookUpdate::Cancel(*id))
+            .collect();
+        let mut added = Vec::new();
+        for o in open.into_iter().filter(Order::is_open) {
+            let Some(cur) = self.orders.get(&o.order_id) else {
+                added.push(o);
+                continue;
+            };
+            let leaf = order_leaf(&self.hasher, &o);
+            if order_leaf(&self.hasher, cur) == leaf {
+                continue;
+            }
+            let amended = cur.amended(o.price_tick, o.remaining, o.ingest_seq);
+            if amended.is_some_and(|a| order_leaf(&self.hasher, &a) == leaf) {
+                updates.push(BookUpdate::Amend {
+                    order_id: o.order_id, price_tick: o.price_tick, remaining: o.remaining, requeue_seq: o.ingest_seq,
+                });
+            } else {
+                updates.push(BookUpdate::Cancel(o.order_id));
+                added.push(o);
+            }
+        }
+        (updates, added)
+    }
+
+    /// Apply cancels and amends made between blocks. On an error the state
+    /// is left part-way; apply to a clone when that matters.
+    pub fn update(&mut self, updates: &[BookUpdate]) -> Result<(), StateError> {
+        let mut touched = BTreeSet::new();
+        for u in updates {
+            let id = u.order_id();
+            let cur = self.orders.remove(&id).ok_or(StateError::UnknownOrder(id))?;
+            if let BookUpdate::Amend { price_tick, remaining, requeue_seq, .. } = *u {
+                if let Some(o) = cur.amended(price_tick, remaining, requeue_seq) {
+                    self.orders.insert(id, o);
+                }
+            }
+            touched.insert(id);
+        }
+        self.flush(touched);
+        Ok(())
+    }
+
+    /// Add a batch's new orders, apply what matching did and return the new
+    /// root. On an error the state is left part-way, as with `update`.
+    pub fn apply<'a>(
+        &mut self,
+        new_orders: impl IntoIterator<Item = &'a Order>,
+        effects: &BatchEffects<'_>,
+    ) -> Result<[u8; 32], StateError> {
+        let mut touched = BTreeSet::new();
+
+        for o in new_orders {
+            if self.orders.insert(o.order_id, o.clone()).is_some() {
+                return Err(StateError::DuplicateOrder(o.order_id));
+            }
+            touched.insert(o.order_id);
+        }
+
+        // what matching did to the book
+        let removed = effects.rejected.iter().map(|r| r.order_id).chain(effects.quarantined.iter().copied());
+        for id in removed {
+            self.orders.remove(&id).ok_or(StateError::UnknownOrder(id))?;
+            touched.insert(id);
+        }
+        for t in effects.triggered {
+            let o = touch(&mut self.orders, t.order_id, &mut touched)?;
+            if let Some(stop) = o.stop.as_mut() {
+                stop.triggered = true;
+                if stop.kind == StopKind::Market {
+                    o.tif = TimeInForce::Ioc;
+                }
+            }
+            o.ingest_seq = t.ingest_seq;
+        }
+        for r in effects.refills {
+            touch(&mut self.orders, r.order_id, &mut touched)?.ingest_seq = r.ingest_seq;
+        }
+        for r in effects.residuals {
+            touch(&mut self.orders, r.order_id, &mut touched)?.remaining = r.remaining_after;
+        }
+        self.orders.retain(|_, o| o.is_open());
+
+        self.flush(touched);
+        Ok(self.root())
+    }
+
+    fn flush(&mut self, touched: BTreeSet<OrderId>) {
+        let leaves: Vec<(u64, [u8; 32])> = touched.iter()
+            .map(|id| (id.0, self.orders.get(id).map_or([0u8; 32], |o| order_leaf(&self.hasher, o))))
+            .collect();
+        self.tree.update(&self.hasher, leaves);
+    }
+}
+
+fn touch<'s>(
+    orders: &'s mut BTreeMap<OrderId, Order>,
+    id: OrderId,
+    touched: &mut BTreeSet<OrderId>,
+) -> Result<&'s mut Order, StateError> {
+    touched.insert(id);
+    orders.get_mut(&id).ok_or(StateError::UnknownOrder(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pid::StubPoseidon;
+
+    fn order(id: u64, remaining: u64) -> Order {
+        Order {
+            order_id: OrderId(id), order_hash: [id as u8; 32], pair_id: PairId(1), side: Side::Bid, price_tick: 1,
+            amount: 10, remaining, time_bucket: 0, nonce: id, ingest_seq: id, tif: TimeInForce::Gtc, stop: None,
+            display_qty: None,
+        }
+    }
+
+    fn residual(id: u64, before: u64, after: u64) -> OrderResidual {
+        OrderResidual { order_id: OrderId(id), remaining_before: before, remaining_after: after, now_filled: after == 0 }
+    }
+
+    #[test]
+    fn root_follows_the_parent_state_and_the_block() {
+        let mut state = OrderState::new(StubPoseidon);
+        let genesis = state.root();
+        assert_eq!(genesis, OrderState::genesis_root(&StubPoseidon));
+
+        let residuals = [residual(1, 10, 0), residual(2, 10, 4)];
+        let effects = BatchEffects { residuals: &residuals, ..Default::default() };
+        state.apply(&[order(1, 10), order(2, 10)], &effects).unwrap();
+        assert_eq!((state.len(), state.get(OrderId(2)).map(|o| o.remaining)), (1, Some(4)));
+
+        // order 3 arrives; a state rebuilt from the parent's orders agrees
+        let parent = OrderState::from_orders(StubPoseidon, state.orders().cloned()).unwrap();
+        assert_eq!(parent.root(), state.root());
+        let root = state.apply(&[order(3, 10)], &BatchEffects::default()).unwrap();
+        let mut fresh = parent.clone();
+        assert_eq!(fresh.apply(&[order(3, 10)], &BatchEffects::default()).unwrap(), root);
+
+        // everything cancelled between batches
+        state.update(&[BookUpdate::Cancel(OrderId(2)), BookUpdate::Cancel(OrderId(3))]).unwrap();
+        assert_eq!(state.root(), genesis);
+    }
+
+    #[test]
+    fn orders_the_state_does_not_hold_are_errors() {
+        let mut state = OrderState::from_orders(StubPoseidon, [order(1, 10)]).unwrap();
+        assert_eq!(state.clone().update(&[BookUpdate::Cancel(OrderId(9))]), Err(StateError::UnknownOrder(OrderId(9))));
+        assert_eq!(state.clone().apply(&[order(1, 10)], &BatchEffects::default()), Err(StateError::DuplicateOrder(OrderId(1))));
+        let stray = [residual(9, 1, 0)];
+        let effects = BatchEffects { residuals: &stray, ..Default::default() };
+        assert_eq!(state.apply(&[], &effects), Err(StateError::UnknownOrder(OrderId(9))));
+        assert!(OrderState::from_orders(StubPoseidon, [order(1, 10), order(1, 5)]).is_err());
+    }
+
+    #[test]
+    fn amends_follow_order_amended() {
+        let mut state = OrderState::from_orders(StubPoseidon, [order(1, 10), order(2, 10), order(3, 10)]).unwrap();
+        state.update(&[
+            BookUpdate::Amend { order_id: OrderId(1), price_tick: 1, remaining: 4, requeue_seq: 50 },
+            BookUpdate::Amend { order_id: OrderId(2), price_tick: 2, remaining: 4, requeue_seq: 51 },
+            BookUpdate::Amend { order_id: OrderId(3), price_tick: 1, remaining: 0, requeue_seq: 52 },
+        ]).unwrap();
+        let o1 = state.get(OrderId(1)).unwrap();
+        assert_eq!((o1.amount, o1.remaining, o1.ingest_seq), (4, 4, 1)); // kept its place
+        let o2 = state.get(OrderId(2)).unwrap();
+        assert_eq!((o2.price_tick, o2.remaining, o2.ingest_seq), (2, 4, 51));
+        assert!(state.get(OrderId(3)).is_none());
+    }
+
+    #[test]
+    fn diff_lists_the_updates_that_reach_the_open_orders() {
+        let state = OrderState::from_orders(StubPoseidon, [order(1, 10), order(2, 10), order(3, 10), order(4, 10)]).unwrap();
+        let mut requeued = order(2, 12);
+        requeued.amount = 12;
+        requeued.ingest_seq = 40;
+        let mut rehashed = order(3, 10);
+        rehashed.order_hash = [0xee; 32];
+        let open = vec![order(5, 10), rehashed.clone(), requeued, order(4, 10)];
+
+        let (updates, added) = state.diff(open.clone());
+        assert_eq!(updates, [
+            BookUpdate::Cancel(OrderId(1)),
+            BookUpdate::Cancel(OrderId(3)),
+            BookUpdate::Amend { order_id: OrderId(2), price_tick: 1, remaining: 12, requeue_seq: 40 },
+        ]);
+        assert_eq!(added.iter().map(|o| o.order_id.0).collect::<Vec<_>>(), [5, 3]);
+
+        let mut next = state.clone();
+        next.update(&updates).unwrap();
+        next.apply(&added, &BatchEffects::default()).unwrap();
+        assert_eq!(next.root(), OrderState::from_orders(StubPoseidon, open).unwrap().root());
+    }
+
+    #[test]
+    fn rejects_quarantines_and_triggers_change_the_leaves() {
+        let mut stop = order(3, 10);
+        stop.stop = Some(StopTrigger { kind: StopKind::Market, trigger_tick: 1, triggered: false });
+        let book = [order(1, 10), order(2, 10), stop];
+
+        let untouched = OrderState::new(StubPoseidon).apply(&book, &BatchEffects::default()).unwrap();
+
+        let rejected = [OrderReject { order_id: OrderId(1), reason: RejectReason::Zero }];
+        let triggered = [OrderTrigger { order_id: OrderId(3), ingest_seq: 50, last_price: 1 }];
+        let effects = BatchEffects { rejected: &rejected, quarantined: &[OrderId(2)], triggered: &triggered, ..Default::default() };
+        let mut state = OrderState::new(StubPoseidon);
+        assert_ne!(state.apply(&book, &effects).unwrap(), untouched);
+        assert_eq!(state.len(), 1);
+        let o = state.get(OrderId(3)).unwrap();
+        assert_eq!((o.tif, o.ingest_seq, o.stop.unwrap().triggered), (TimeInForce::Ioc, 50, true));
+    }
+}
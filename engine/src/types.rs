@@ -1,13 +1,17 @@
 #![allow(dead_code)]
-use std::cmp::Ordering;
+use core::cmp::Ordering;
+use alloc::collections::BTreeMap;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PairId(pub u32);
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Side { Bid, Ask }  // Bid=buy, Ask=sell
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MarketParams {
     pub pair_id: PairId,
     pub price_tick: u64,     // min price increment
@@ -17,15 +21,82 @@ pub struct MarketParams {
     pub maker_bps: u16,
     pub taker_bps: u16,
     pub status: MarketStatus,
+    pub clearing: ClearingMode,
+    pub allocation: AllocationPolicy,
+    pub stp: StpMode,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MarketStatus { Active, Paused, CancelOnly, Delisted }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+/// How crossed orders in a batch are priced.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClearingMode {
+    #[default]
+    Continuous,   // price-time loop, each fill at the resting price
+    UniformPrice, // one volume-maximising price for the whole batch
+}
+
+/// How a taker's quantity is shared among resting orders at one price.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AllocationPolicy {
+    #[default]
+    Fifo,                         // strict ingest_seq priority
+    ProRata,                      // proportional to remaining size
+    FifoProRata { fifo_pct: u8 }, // fifo_pct% FIFO, the rest pro-rata
+}
+
+/// Self-trade prevention, applied when both sides of a cross share a `PkHash`.
+/// "Newest" is the later `ingest_seq` (the taker), "oldest" the resting maker.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StpMode {
+    #[default]
+    None,               // self-trades are allowed
+    CancelNewest,
+    CancelOldest,
+    CancelBoth,
+    DecrementAndCancel, // shrink both by the smaller size; the smaller one is gone
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderId(pub u64);
 
+/// How long an order may stay on the book.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeInForce {
+    #[default]
+    Gtc,      // rests until filled
+    Ioc,      // remainder cancelled at end of batch
+    Fok,      // fills completely in this batch or not at all
+    PostOnly, // maker only; rejected if it would take liquidity
+}
+
+/// What a stop order becomes once triggered.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StopKind {
+    Market, // IOC with `price_tick` as its worst acceptable price
+    Limit,  // plain limit order with its own `tif`
+}
+
+/// Conditional entry: the order stays off the book until a trade prints at
+/// or through `trigger_tick` (bids: at or above, asks: at or below).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StopTrigger {
+    pub kind: StopKind,
+    pub trigger_tick: u64,
+    pub triggered: bool,
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Order {
     pub order_id: OrderId,
     pub order_hash: [u8; 32],
@@ -37,10 +108,44 @@ pub struct Order {
     pub time_bucket: u32,
     pub nonce: u64,
     pub ingest_seq: u64, // strict FIFO tiebreaker within price
+    pub tif: TimeInForce,
+    pub stop: Option<StopTrigger>,
+    pub display_qty: Option<u64>, // iceberg: size of each visible slice
 }
 
 impl Order {
     #[inline] pub fn is_open(&self) -> bool { self.remaining > 0 }
+    /// Stop order still waiting for its trigger (not on the book).
+    #[inline] pub fn is_pending_stop(&self) -> bool { self.stop.is_some_and(|s| !s.triggered) }
+
+    /// Quantity visible on the book. An iceberg shows `display_qty` at a
+    /// time, cut from `amount`, so the unfilled part of the current slice
+    /// follows from how much has been filled so far.
+    #[inline]
+    pub fn displayed(&self) -> u64 {
+        match self.display_qty {
+            Some(d) if d > 0 => self.remaining.min(d - self.amount.saturating_sub(self.remaining) % d),
+            _ => self.remaining,
+        }
+    }
+
+    /// This order after an amend to `price_tick` / `remaining`, or `None`
+    /// when nothing would remain. Shrinking at the same price keeps the
+    /// queue position; anything else requeues at `requeue_seq`. `amount`
+    /// moves with `remaining`, so the filled quantity is unchanged.
+    pub fn amended(&self, price_tick: u64, remaining: u64, requeue_seq: u64) -> Option<Order> {
+        if remaining == 0 {
+            return None;
+        }
+        let mut o = self.clone();
+        o.amount = o.amount - o.remaining + remaining;
+        o.remaining = remaining;
+        if price_tick != self.price_tick || remaining > self.remaining {
+            o.price_tick = price_tick;
+            o.ingest_seq = requeue_seq;
+        }
+        Some(o)
+    }
 }
 
 impl PartialEq for Order {
@@ -51,10 +156,14 @@ impl Eq for Order {}
 /// Hidden owner mapping (from DB)
 pub type PkHash = [u8; 32];
 
+/// order_id -> owner, ordered so iteration is the same on every node.
+pub type OwnerMap = BTreeMap<u64, PkHash>;
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FillDraft {
     pub batch_id: u64,
-    pub match_id: u64,           // unique within batch
+    pub match_id: u64,           // unique within batch, see batch::match_id_base
     pub pair_id: PairId,
     pub price_tick: u64,
     pub fill_qty: u64,
@@ -69,11 +178,38 @@ pub struct FillDraft {
     pub buyer_pid: [u8; 32],
     pub seller_pid: [u8; 32],
 
-    pub fee_bps: u16,
+    pub maker_side: Side, // side with the earlier ingest_seq
+    pub maker_fee: u128,  // quote units, see r#match::fee_amount
+    pub taker_fee: u128,
+    pub fill_salt: Option<[u8; 32]>,
+}
+
+/// One order's side of an implied trade (see `implied`). The legs of a
+/// trade sit on different markets, have no counterparty order of their own
+/// and share `group_id`; settle all of them or none.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FillLeg {
+    pub batch_id: u64,
+    pub match_id: u64, // from this leg's market range, like FillDraft
+    pub group_id: u64, // match_id of the outright leg
+    pub pair_id: PairId,
+    pub price_tick: u64,
+    pub fill_qty: u64,
+    pub time_bucket: u32,
+
+    pub order_id: OrderId,
+    pub order_hash: [u8; 32],
+    pub side: Side,
+    pub pid: [u8; 32],
+
+    pub is_maker: bool, // false for the latest of the three orders
+    pub fee: u128,
     pub fill_salt: Option<[u8; 32]>,
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderResidual {
     pub order_id: OrderId,
     pub remaining_before: u64,
@@ -81,22 +217,77 @@ pub struct OrderResidual {
     pub now_filled: bool,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CancelReason {
+    IocRemainder,
+    FokUnfilled,
+    PostOnlyWouldCross,
+    SelfTrade,
+}
+
+/// A stop order that entered the book this batch.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderTrigger {
+    pub order_id: OrderId,
+    pub ingest_seq: u64, // re-stamped: queues behind everything already open
+    pub last_price: u64, // trade price that fired it
+}
+
+/// An iceberg that used up a slice and was re-queued for the next one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderRefill {
+    pub order_id: OrderId,
+    pub ingest_seq: u64, // new queue position at its price
+}
+
+/// Why an order was refused before matching.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RejectReason {
+    PairMismatch,
+    MarketClosed(MarketStatus), // CancelOnly / Delisted
+    Zero,                       // zero amount, price, trigger or display
+    RemainingExceedsAmount,
+    OffTick,                    // price or trigger not a multiple of price_tick
+    OffStep,                    // amount or display not a multiple of size_step
+    NotionalTooSmall,
+    NotionalTooLarge,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderReject {
+    pub order_id: OrderId,
+    pub reason: RejectReason,
+}
+
+/// Quantity removed from the book without trading.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderCancel {
+    pub order_id: OrderId,
+    pub cancelled_qty: u64,
+    pub reason: CancelReason,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct OrderKey {
     pub side: Side,
     pub price_tick: u64,
     pub ingest_seq: u64,
+    pub order_id: OrderId, // breaks ingest_seq ties, like LevelBook's queues
 }
 
 impl OrderKey {
+    #[allow(clippy::should_implement_trait)]
     pub fn cmp(a: &Self, b: &Self) -> Ordering {
+        let time = || a.ingest_seq.cmp(&b.ingest_seq).then_with(|| a.order_id.cmp(&b.order_id));
         match (a.side, b.side) {
-            (Side::Bid, Side::Bid) =>
-                a.price_tick.cmp(&b.price_tick).reverse()
-                    .then_with(|| a.ingest_seq.cmp(&b.ingest_seq)),
-            (Side::Ask, Side::Ask) =>
-                a.price_tick.cmp(&b.price_tick)
-                    .then_with(|| a.ingest_seq.cmp(&b.ingest_seq)),
+            (Side::Bid, Side::Bid) => a.price_tick.cmp(&b.price_tick).reverse().then_with(time),
+            (Side::Ask, Side::Ask) => a.price_tick.cmp(&b.price_tick).then_with(time),
             _ => Ordering::Equal,
         }
     }
@@ -0,0 +1,126 @@
+use crate::types::{MarketParams, MarketStatus, Order, RejectReason};
+
+/// Static checks an order must pass before it may trade on `market`.
+///
+/// `Paused` markets still accept orders (they simply don't match);
+/// `CancelOnly` and `Delisted` markets accept nothing new.
+pub fn validate_order(market: &MarketParams, o: &Order) -> Result<(), RejectReason> {
+    if o.pair_id != market.pair_id {
+        return Err(RejectReason::PairMismatch);
+    }
+    match market.status {
+        MarketStatus::Active | MarketStatus::Paused => {}
+        MarketStatus::CancelOnly | MarketStatus::Delisted => {
+            return Err(RejectReason::MarketClosed(market.status));
+        }
+    }
+    if o.amount == 0 || o.price_tick == 0 {
+        return Err(RejectReason::Zero);
+    }
+    if o.remaining > o.amount {
+        return Err(RejectReason::RemainingExceedsAmount);
+    }
+    if !o.price_tick.is_multiple_of(market.price_tick.max(1)) {
+        return Err(RejectReason::OffTick);
+    }
+    if let Some(stop) = o.stop {
+        if stop.trigger_tick == 0 {
+            return Err(RejectReason::Zero);
+        }
+        if !stop.trigger_tick.is_multiple_of(market.price_tick.max(1)) {
+            return Err(RejectReason::OffTick);
+        }
+    }
+    if !o.amount.is_multiple_of(market.size_step.max(1)) {
+        return Err(RejectReason::OffStep);
+    }
+    if let Some(d) = o.display_qty {
+        if d == 0 {
+            return Err(RejectReason::Zero);
+        }
+        if !d.is_multiple_of(market.size_step.max(1)) {
+            return Err(RejectReason::OffStep);
+        }
+    }
+    let notional = o.price_tick as u128 * o.amount as u128;
+    if notional < market.notional_min {
+        return Err(RejectReason::NotionalTooSmall);
+    }
+    if notional > market.notional_max {
+        return Err(RejectReason::NotionalTooLarge);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::*;
+
+    fn market() -> MarketParams {
+        MarketParams {
+            pair_id: PairId(1),
+            price_tick: 5, size_step: 10,
+            notional_min: 1_000, notional_max: 100_000,
+            maker_bps: 0, taker_bps: 0,
+            status: MarketStatus::Active,
+            clearing: ClearingMode::Continuous,
+            allocation: AllocationPolicy::Fifo,
+            stp: StpMode::None,
+        }
+    }
+
+    fn order(px: u64, amt: u64) -> Order {
+        Order {
+            order_id: OrderId(1), order_hash: [0;32], pair_id: PairId(1), side: Side::Bid,
+            price_tick: px, amount: amt, remaining: amt, time_bucket: 0, nonce: 0,
+            ingest_seq: 1, tif: TimeInForce::Gtc, stop: None, display_qty: None,
+        }
+    }
+
+    #[test]
+    fn accepts_order_within_constraints() {
+        assert_eq!(validate_order(&market(), &order(100, 20)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_tick_step_and_notional_violations() {
+        let m = market();
+        assert_eq!(validate_order(&m, &order(101, 20)), Err(RejectReason::OffTick));
+        assert_eq!(validate_order(&m, &order(100, 25)), Err(RejectReason::OffStep));
+        assert_eq!(validate_order(&m, &order(5, 10)), Err(RejectReason::NotionalTooSmall));
+        assert_eq!(validate_order(&m, &order(10_000, 20)), Err(RejectReason::NotionalTooLarge));
+        assert_eq!(validate_order(&m, &order(0, 20)), Err(RejectReason::Zero));
+    }
+
+    #[test]
+    fn rejects_wrong_pair_and_closed_market() {
+        let o = Order { pair_id: PairId(2), ..order(100, 20) };
+        assert_eq!(validate_order(&market(), &o), Err(RejectReason::PairMismatch));
+
+        let m = MarketParams { status: MarketStatus::CancelOnly, ..market() };
+        assert_eq!(validate_order(&m, &order(100, 20)), Err(RejectReason::MarketClosed(MarketStatus::CancelOnly)));
+
+        let m = MarketParams { status: MarketStatus::Paused, ..market() };
+        assert_eq!(validate_order(&m, &order(100, 20)), Ok(()));
+    }
+
+    #[test]
+    fn checks_stop_trigger() {
+        let stop = |t| Order {
+            stop: Some(StopTrigger { kind: StopKind::Limit, trigger_tick: t, triggered: false }),
+            ..order(100, 20)
+        };
+        assert_eq!(validate_order(&market(), &stop(95)), Ok(()));
+        assert_eq!(validate_order(&market(), &stop(96)), Err(RejectReason::OffTick));
+        assert_eq!(validate_order(&market(), &stop(0)), Err(RejectReason::Zero));
+    }
+
+    #[test]
+    fn checks_display_qty() {
+        let ice = |d| Order { display_qty: Some(d), ..order(100, 20) };
+        assert_eq!(validate_order(&market(), &ice(10)), Ok(()));
+        assert_eq!(validate_order(&market(), &ice(5)), Err(RejectReason::OffStep));
+        assert_eq!(validate_order(&market(), &ice(0)), Err(RejectReason::Zero));
+    }
+}
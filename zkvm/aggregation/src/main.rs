@@ -0,0 +1,22 @@
+//! Aggregation guest: verifies a run of block-guest proofs recursively and
+//! commits one `AggregateCommitment` for the range. The host passes each
+//! compressed block proof with `SP1Stdin::write_proof`, in block order.
+
+#![no_main]
+sp1_zkvm::entrypoint!(main);
+
+use alloy_sol_types::SolType;
+use fibonacci_lib::{aggregate, public_values_digest, vkey_bytes, AggregateCommitment};
+
+pub fn main() {
+    // digest of the block guest's verifying key (`SP1VerifyingKey::hash_u32`)
+    let block_vkey = sp1_zkvm::io::read::<[u32; 8]>();
+    let public_values = sp1_zkvm::io::read::<Vec<Vec<u8>>>();
+
+    for pv in &public_values {
+        sp1_zkvm::lib::verify::verify_sp1_proof(&block_vkey, &public_values_digest(pv));
+    }
+
+    let range = aggregate(vkey_bytes(block_vkey), &public_values).unwrap_or_else(|e| panic!("{e}"));
+    sp1_zkvm::io::commit_slice(&AggregateCommitment::abi_encode(&range));
+}
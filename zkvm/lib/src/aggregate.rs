@@ -0,0 +1,142 @@
+//! Folding a range of block proofs into one: the aggregation guest checks
+//! each block proof recursively, then `aggregate` checks that the headers
+//! form a chain and reduces them to the range's endpoints.
+//!
+//! Block `i + 1` must be numbered one past block `i` and start from its
+//! `newStateRoot`. `blocksDigest` is `sha256(d_1 || .. || d_n)` with
+//! `d_i = sha256(public values of block i)`, the digests the guest verified,
+//! so a contract can still check any single header against the range.
+use crate::{AggregateCommitment, BlockCommitment};
+use alloy_sol_types::SolType;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AggregateError {
+    Empty,
+    /// Public values at `index` are not an ABI `BlockCommitment`.
+    Decode { index: usize },
+    /// Block numbers must be consecutive.
+    Gap { expected: u64, got: u64 },
+    /// A block follows block `u64::MAX`.
+    Overflow,
+    /// `parentStateRoot` of `block` is not the previous `newStateRoot`.
+    Unlinked { block: u64 },
+}
+
+impl core::fmt::Display for AggregateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AggregateError::Empty => write!(f, "no blocks to aggregate"),
+            AggregateError::Decode { index } => write!(f, "public values {index} are not a BlockCommitment"),
+            AggregateError::Gap { expected, got } => write!(f, "expected block {expected}, got {got}"),
+            AggregateError::Overflow => write!(f, "no block follows block {}", u64::MAX),
+            AggregateError::Unlinked { block } => write!(f, "block {block} does not start from the previous state root"),
+        }
+    }
+}
+
+impl std::error::Error for AggregateError {}
+
+/// The digest a block proof's public values are verified under.
+pub fn public_values_digest(public_values: &[u8]) -> [u8; 32] {
+    Sha256::digest(public_values).into()
+}
+
+/// A verifying-key digest (`SP1VerifyingKey::hash_u32`) as committed,
+/// words big-endian.
+pub fn vkey_bytes(words: [u32; 8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    for (chunk, word) in out.as_chunks_mut::<4>().0.iter_mut().zip(words) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// Check that `public_values` (each an ABI `BlockCommitment`, in order)
+/// chain, and commit the range. `block_vkey` is the block guest's
+/// verifying-key digest the proofs were checked against.
+pub fn aggregate(block_vkey: [u8; 32], public_values: &[Vec<u8>]) -> Result<AggregateCommitment, AggregateError> {
+    let mut blocks = Vec::with_capacity(public_values.len());
+    let mut digests = Sha256::new();
+    for (index, pv) in public_values.iter().enumerate() {
+        blocks.push(BlockCommitment::abi_decode(pv).map_err(|_| AggregateError::Decode { index })?);
+        digests.update(public_values_digest(pv));
+    }
+    let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+        return Err(AggregateError::Empty);
+    };
+    for pair in blocks.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        let expected = prev.blockNumber.checked_add(1).ok_or(AggregateError::Overflow)?;
+        if next.blockNumber != expected {
+            return Err(AggregateError::Gap { expected, got: next.blockNumber });
+        }
+        if next.parentStateRoot != prev.newStateRoot {
+            return Err(AggregateError::Unlinked { block: next.blockNumber });
+        }
+    }
+    Ok(AggregateCommitment {
+        blockVkey: block_vkey.into(),
+        firstBlock: first.blockNumber,
+        lastBlock: last.blockNumber,
+        parentStateRoot: first.parentStateRoot,
+        newStateRoot: last.newStateRoot,
+        blocksDigest: <[u8; 32]>::from(digests.finalize()).into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Block `n`, moving the state from root `n - 1` to root `n`.
+    fn block(n: u64) -> BlockCommitment {
+        BlockCommitment {
+            blockNumber: n,
+            batchId: n,
+            hashScheme: 0,
+            commitScheme: 0,
+            parentStateRoot: [n as u8 - 1; 32].into(),
+            newStateRoot: [n as u8; 32].into(),
+            marketsRoot: [0u8; 32].into(),
+            ordersCommitment: [0u8; 32].into(),
+            fillsCommitment: [0u8; 32].into(),
+            timestampMs: n,
+        }
+    }
+
+    fn encode(blocks: &[BlockCommitment]) -> Vec<Vec<u8>> {
+        blocks.iter().map(BlockCommitment::abi_encode).collect()
+    }
+
+    #[test]
+    fn a_chain_commits_its_endpoints() {
+        let pvs = encode(&[block(1), block(2), block(3)]);
+        let agg = aggregate([7u8; 32], &pvs).unwrap();
+        assert_eq!((agg.firstBlock, agg.lastBlock), (1, 3));
+        assert_eq!(agg.parentStateRoot, block(1).parentStateRoot);
+        assert_eq!(agg.newStateRoot, block(3).newStateRoot);
+        let digests: Vec<u8> = pvs.iter().flat_map(|pv| public_values_digest(pv)).collect();
+        assert_eq!(agg.blocksDigest, public_values_digest(&digests));
+    }
+
+    #[test]
+    fn blocks_must_start_from_the_previous_root() {
+        let forked = BlockCommitment { parentStateRoot: [9u8; 32].into(), ..block(3) };
+        let err = aggregate([0u8; 32], &encode(&[block(1), block(2), forked])).unwrap_err();
+        assert_eq!(err, AggregateError::Unlinked { block: 3 });
+    }
+
+    #[test]
+    fn blocks_must_be_consecutive() {
+        let err = aggregate([0u8; 32], &encode(&[block(1), block(3)])).unwrap_err();
+        assert_eq!(err, AggregateError::Gap { expected: 2, got: 3 });
+        assert_eq!(aggregate([0u8; 32], &[]).unwrap_err(), AggregateError::Empty);
+        let last = BlockCommitment { blockNumber: u64::MAX, ..block(1) };
+        let wrapped = BlockCommitment { blockNumber: 0, ..block(2) };
+        let err = aggregate([0u8; 32], &encode(&[last, wrapped])).unwrap_err();
+        assert_eq!(err, AggregateError::Overflow);
+        let err = aggregate([0u8; 32], &[BlockCommitment::abi_encode(&block(1)), vec![0u8; 31]]).unwrap_err();
+        assert_eq!(err, AggregateError::Decode { index: 1 });
+    }
+}
@@ -0,0 +1,158 @@
+//! Re-executing a sequencer block: the guest reads a `BlockInput`, runs
+//! `execute_block` and commits the resulting `BlockCommitment`. Host tools
+//! run the same function to check a block before proving it.
+//!
+//! Matching is `engine::match_batch` (per-market `match_market` plus
+//! implied routes), the commitments are `engine::commit` and the state root
+//! is `engine::state`, so every field comes out of the code the sequencer
+//! ran. The state starts from `orders`, the open orders at
+//! `parent_state_root`: their tree is rebuilt and must have that root
+//! before anything is matched. The block lists the cancels and amends made
+//! since (`updates`), so the pre-batch book follows from the two.
+use crate::BlockCommitment;
+use engine::commit::{commit_fills, commit_markets, commit_orders, CommitScheme};
+use engine::hash::{HashBackend, HashScheme};
+use engine::state::{BatchEffects, BookUpdate, OrderState};
+use engine::types::*;
+use engine::{BatchOptions, ImpliedRoute};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Everything the sequencer matched a block from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockInput {
+    pub block_number: u64,
+    pub batch_id: u64,
+    pub hash_scheme: HashScheme,
+    pub commit_scheme: CommitScheme,
+    pub parent_state_root: [u8; 32],
+    pub timestamp_ms: u64,
+    pub markets: Vec<MarketParams>, // as loaded; markets_root keeps this order
+    pub orders: Vec<Order>,         // open orders at parent_state_root
+    pub updates: Vec<BookUpdate>,   // cancels and amends since the previous block
+    pub new_orders: Vec<Order>,     // arrived since the previous block
+    pub owners: OwnerMap,           // owner pk hash of every order above
+    pub implied: Vec<ImpliedRoute>,
+    /// Salt of every fill and leg by match id, when the block salts fills.
+    pub fill_salts: Option<BTreeMap<u64, [u8; 32]>>,
+}
+
+/// A block as stored for proving: the guest input plus the public values
+/// the sequencer committed to (`BlockHeader::abi_encode`), which a proof
+/// of `input` must reproduce.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockExport {
+    pub input: BlockInput,
+    pub public_values: Vec<u8>,
+}
+
+/// Names of the guest's cycle-tracked phases (`profile-block` reports
+/// them). `DECODE` is the guest reading its input; the rest are the
+/// stages of `execute_block_traced`.
+pub mod phase {
+    pub const DECODE: &str = "decode";
+    pub const MATCH: &str = "match";
+    pub const STATE: &str = "state";
+    pub const COMMIT: &str = "commit";
+    pub const ALL: [&str; 4] = [DECODE, MATCH, STATE, COMMIT];
+}
+
+/// What matching did to a block, flattened in plan order as the sequencer
+/// stores it.
+pub struct MatchedBlock {
+    pub book: Vec<Order>, // the pre-batch book, resting orders then `new_orders`
+    pub fills: Vec<FillDraft>,
+    pub legs: Vec<FillLeg>,
+    pub residuals: Vec<OrderResidual>,
+    pub triggered: Vec<OrderTrigger>,
+    pub refills: Vec<OrderRefill>,
+    pub rejected: Vec<OrderReject>,
+    pub quarantined: Vec<OrderId>,
+}
+
+/// Match the block again and rebuild its header fields.
+///
+/// Panics if a fill needs a salt that `fill_salts` does not have, which
+/// makes the proof fail rather than commit a different block.
+pub fn execute_block(input: &BlockInput) -> BlockCommitment {
+    execute_block_traced(input, |_, _| {})
+}
+
+/// `execute_block`, calling `mark(phase, true)` before and
+/// `mark(phase, false)` after each of `phase::{MATCH, STATE, COMMIT}`.
+pub fn execute_block_traced(input: &BlockInput, mut mark: impl FnMut(&'static str, bool)) -> BlockCommitment {
+    let h = HashBackend::new(input.hash_scheme);
+
+    mark(phase::STATE, true);
+    let mut state = pre_batch_state(input);
+    mark(phase::STATE, false);
+
+    mark(phase::MATCH, true);
+    let m = match_block(input, state.orders().cloned().collect());
+    mark(phase::MATCH, false);
+
+    mark(phase::STATE, true);
+    let effects = BatchEffects {
+        rejected: &m.rejected,
+        quarantined: &m.quarantined,
+        triggered: &m.triggered,
+        refills: &m.refills,
+        residuals: &m.residuals,
+    };
+    let new_state_root = state.apply(&input.new_orders, &effects).expect("effects of open orders");
+    mark(phase::STATE, false);
+
+    mark(phase::COMMIT, true);
+    let header = BlockCommitment {
+        blockNumber: input.block_number,
+        batchId: input.batch_id,
+        hashScheme: input.hash_scheme.id(),
+        commitScheme: input.commit_scheme.id(),
+        parentStateRoot: input.parent_state_root.into(),
+        newStateRoot: new_state_root.into(),
+        marketsRoot: commit_markets(&h, input.commit_scheme, &input.markets).into(),
+        ordersCommitment: commit_orders(&h, input.commit_scheme, &m.book).into(),
+        fillsCommitment: commit_fills(&h, input.commit_scheme, &m.fills, &m.legs).into(),
+        timestampMs: input.timestamp_ms,
+    };
+    mark(phase::COMMIT, false);
+    header
+}
+
+/// The parent state with the block's `updates` applied: what was open when
+/// the batch was matched. Panics unless `orders` rebuild to
+/// `parent_state_root`, or on updates to orders that are not open.
+pub fn pre_batch_state(input: &BlockInput) -> OrderState<HashBackend> {
+    let h = HashBackend::new(input.hash_scheme);
+    let mut state = OrderState::from_orders(h, input.orders.iter().cloned()).expect("parent orders");
+    assert!(state.root() == input.parent_state_root, "orders do not match parent_state_root");
+    state.update(&input.updates).expect("updates to open orders");
+    state
+}
+
+/// The matching half of `execute_block`, from the `resting` orders of
+/// `pre_batch_state`.
+pub fn match_block(input: &BlockInput, resting: Vec<Order>) -> MatchedBlock {
+    let h = HashBackend::new(input.hash_scheme);
+    let book: Vec<Order> = resting.iter().chain(&input.new_orders).cloned().collect();
+    let salt = |_batch: u64, match_id: u64| -> [u8; 32] {
+        let salts = input.fill_salts.as_ref().expect("salts for a salted block");
+        *salts.get(&match_id).unwrap_or_else(|| panic!("no salt for match {match_id}"))
+    };
+    let opts = BatchOptions { threads: 1, implied: input.implied.clone() };
+    let plan = engine::match_batch(
+        input.batch_id, &input.markets, resting, input.new_orders.clone(), &input.owners, &h,
+        input.fill_salts.is_some(), salt, &opts,
+    );
+
+    MatchedBlock {
+        fills: plan.fills().cloned().collect(),
+        legs: plan.legs().cloned().collect(),
+        residuals: plan.plans.iter().flat_map(|p| p.residuals.iter().copied()).collect(),
+        triggered: plan.plans.iter().flat_map(|p| p.triggered.iter().copied()).collect(),
+        refills: plan.plans.iter().flat_map(|p| p.refills.iter().copied()).collect(),
+        quarantined: plan.quarantined(),
+        rejected: plan.rejected,
+        book,
+    }
+}
@@ -0,0 +1,118 @@
+//! Proving cost of a block in zkVM cycles, modelled as linear in the
+//! pre-batch book and the fills matched from it:
+//!
+//! `cycles ≈ base + per_order * orders + per_fill * fills`
+//!
+//! `profile-block` fits it from guest executions over synthetic blocks for
+//! one hash scheme; the sequencer uses it to keep blocks within a proving
+//! budget. Orders dominate (every one is hashed into the commitment and the
+//! state tree), fills add matching and their own leaves.
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CostModel {
+    pub base: f64,
+    pub per_order: f64,
+    pub per_fill: f64,
+}
+
+/// One profiled execution.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CostSample {
+    pub orders: u64,
+    pub fills: u64, // fills and implied legs
+    pub cycles: u64,
+}
+
+impl CostModel {
+    pub fn cycles(&self, orders: u64, fills: u64) -> u64 {
+        (self.base + self.per_order * orders as f64 + self.per_fill * fills as f64).max(0.0).ceil() as u64
+    }
+
+    /// Most orders a block can hold and stay within `budget` cycles,
+    /// assuming one fill per order: icebergs aside, every fill completes
+    /// at least one of its two orders.
+    pub fn max_orders(&self, budget: u64) -> u64 {
+        let per = self.per_order + self.per_fill;
+        if per <= 0.0 {
+            return u64::MAX;
+        }
+        ((budget as f64 - self.base) / per).floor().max(0.0) as u64
+    }
+
+    /// Least-squares fit over `samples`. `None` unless they vary in both
+    /// orders and fills independently (at least three, not collinear).
+    pub fn fit(samples: &[CostSample]) -> Option<Self> {
+        // normal equations: (XᵀX) β = Xᵀy with rows [1, orders, fills]
+        let mut a = [[0f64; 4]; 3];
+        for s in samples {
+            let x = [1.0, s.orders as f64, s.fills as f64];
+            for i in 0..3 {
+                for j in 0..3 {
+                    a[i][j] += x[i] * x[j];
+                }
+                a[i][3] += x[i] * s.cycles as f64;
+            }
+        }
+        // Gauss-Jordan with partial pivoting
+        for col in 0..3 {
+            let pivot = (col..3).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-9 {
+                return None;
+            }
+            a.swap(col, pivot);
+            for row in 0..3 {
+                if row != col {
+                    let pivot = a[col];
+                    let k = a[row][col] / pivot[col];
+                    for (dst, src) in a[row][col..].iter_mut().zip(&pivot[col..]) {
+                        *dst -= k * src;
+                    }
+                }
+            }
+        }
+        Some(CostModel { base: a[0][3] / a[0][0], per_order: a[1][3] / a[1][1], per_fill: a[2][3] / a[2][2] })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: CostModel = CostModel { base: 5_000_000.0, per_order: 40_000.0, per_fill: 25_000.0 };
+
+    fn sample(orders: u64, fills: u64) -> CostSample {
+        CostSample { orders, fills, cycles: MODEL.cycles(orders, fills) }
+    }
+
+    #[test]
+    fn fit_recovers_the_coefficients() {
+        let samples: Vec<_> = [(10, 0), (10, 5), (100, 20), (400, 150), (1000, 30)].map(|(o, f)| sample(o, f)).into();
+        let fit = CostModel::fit(&samples).unwrap();
+        assert!((fit.base - MODEL.base).abs() < 1e-3);
+        assert!((fit.per_order - MODEL.per_order).abs() < 1e-6);
+        assert!((fit.per_fill - MODEL.per_fill).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fit_needs_orders_and_fills_to_vary_independently() {
+        assert_eq!(CostModel::fit(&[]), None);
+        assert_eq!(CostModel::fit(&[sample(10, 2), sample(50, 7)]), None);
+        // fills always half the orders
+        assert_eq!(CostModel::fit(&[sample(10, 5), sample(20, 10), sample(40, 20), sample(80, 40)]), None);
+        // fills never vary
+        assert_eq!(CostModel::fit(&[sample(10, 3), sample(20, 3), sample(40, 3)]), None);
+    }
+
+    #[test]
+    fn max_orders_inverts_cycles() {
+        for n in [0, 1, 7, 1000] {
+            let budget = MODEL.cycles(n, n);
+            assert_eq!(MODEL.max_orders(budget), n);
+            assert_eq!(MODEL.max_orders(budget - 1), n.saturating_sub(1));
+        }
+        assert_eq!(MODEL.max_orders(0), 0);
+        let free = CostModel { base: 100.0, per_order: 0.0, per_fill: 0.0 };
+        assert_eq!(free.max_orders(0), u64::MAX);
+    }
+}
@@ -1,22 +1,40 @@
 use alloy_sol_types::sol;
 
+pub mod aggregate;
+pub mod block;
+pub mod cost;
+pub use aggregate::{aggregate, public_values_digest, vkey_bytes, AggregateError};
+pub use block::{execute_block, execute_block_traced, match_block, pre_batch_state, BlockExport, BlockInput, MatchedBlock};
+pub use cost::{CostModel, CostSample};
+
 sol! {
-    /// The public values encoded as a struct that can be easily deserialized inside Solidity.
-    struct PublicValuesStruct {
-        uint32 n;
-        uint32 a;
-        uint32 b;
+    /// A sequencer block header as committed by the guest: everything a
+    /// settlement contract needs to chain state and check fill proofs.
+    /// `hashScheme` and `commitScheme` are the header's scheme ids.
+    #[derive(Debug)]
+    struct BlockCommitment {
+        uint64 blockNumber;
+        uint64 batchId;
+        uint16 hashScheme;
+        uint16 commitScheme;
+        bytes32 parentStateRoot;
+        bytes32 newStateRoot;
+        bytes32 marketsRoot;
+        bytes32 ordersCommitment;
+        bytes32 fillsCommitment;
+        uint64 timestampMs;
     }
-}
 
-/// Compute the n'th fibonacci number (wrapping around on overflows), using normal Rust code.
-pub fn fibonacci(n: u32) -> (u32, u32) {
-    let mut a = 0u32;
-    let mut b = 1u32;
-    for _ in 0..n {
-        let c = a.wrapping_add(b);
-        a = b;
-        b = c;
+    /// A run of consecutive blocks proven together (see `aggregate`): the
+    /// state moved from `parentStateRoot` to `newStateRoot` through blocks
+    /// `firstBlock..=lastBlock`, each proven by the block guest `blockVkey`.
+    #[derive(Debug)]
+    struct AggregateCommitment {
+        bytes32 blockVkey;
+        uint64 firstBlock;
+        uint64 lastBlock;
+        bytes32 parentStateRoot;
+        bytes32 newStateRoot;
+        bytes32 blocksDigest;
     }
-    (a, b)
 }
@@ -1,5 +1,10 @@
-//! A simple program that takes a number `n` as input, and writes the `n-1`th and `n`th fibonacci
-//! number as an output.
+//! Block-matching guest: reads a `BlockInput`, re-runs the sequencer's
+//! matching and commitments over it, and commits the resulting header as an
+//! ABI-encoded `BlockCommitment`. A valid proof means the header follows
+//! from the input by the engine's rules.
+//!
+//! Each phase is wrapped in an SP1 cycle tracker, so executions report
+//! cycles per phase (see `profile-block`).
 
 // These two lines are necessary for the program to properly compile.
 //
@@ -9,22 +14,20 @@
 sp1_zkvm::entrypoint!(main);
 
 use alloy_sol_types::SolType;
-use fibonacci_lib::{fibonacci, PublicValuesStruct};
+use fibonacci_lib::block::phase;
+use fibonacci_lib::{execute_block_traced, BlockCommitment, BlockInput};
 
 pub fn main() {
-    // Read an input to the program.
-    //
-    // Behind the scenes, this compiles down to a custom system call which handles reading inputs
-    // from the prover.
-    let n = sp1_zkvm::io::read::<u32>();
+    println!("cycle-tracker-report-start: {}", phase::DECODE);
+    let input = sp1_zkvm::io::read::<BlockInput>();
+    println!("cycle-tracker-report-end: {}", phase::DECODE);
 
-    // Compute the n'th fibonacci number using a function from the workspace lib crate.
-    let (a, b) = fibonacci(n);
+    let header = execute_block_traced(&input, |name, start| {
+        let edge = if start { "start" } else { "end" };
+        println!("cycle-tracker-report-{edge}: {name}");
+    });
 
-    // Encode the public values of the program.
-    let bytes = PublicValuesStruct::abi_encode(&PublicValuesStruct { n, a, b });
-
-    // Commit to the public values of the program. The final proof will have a commitment to all the
-    // bytes that were committed to.
+    // The public values: the header a settlement contract checks and chains.
+    let bytes = BlockCommitment::abi_encode(&header);
     sp1_zkvm::io::commit_slice(&bytes);
 }
@@ -0,0 +1,107 @@
+//! Aggregates proofs of consecutive blocks into one proof of the range.
+//!
+//! Block proofs are read from `--dir` as written by `prove-block` (compressed).
+//! The range proof goes to `range-<first>-<last>.proof.bin` and its decoded
+//! public values to `range-<first>-<last>.public.json`, in the same directory.
+//!
+//! ```shell
+//! RUST_LOG=info cargo run --release --bin aggregate -- --dir blocks --first 40 --last 47 --execute
+//! RUST_LOG=info cargo run --release --bin aggregate -- --dir blocks --first 40 --last 47 --prove
+//! ```
+
+use alloy_sol_types::SolType;
+use clap::{Parser, ValueEnum};
+use fibonacci_lib::AggregateCommitment;
+use fibonacci_script::{aggregation_stdin, expected_aggregate, load_block_proofs, AGGREGATION_ELF, BLOCK_ELF};
+use sp1_sdk::{HashableKey, ProverClient, SP1ProofWithPublicValues};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long, default_value = "blocks")]
+    dir: PathBuf,
+
+    #[arg(long)]
+    first: u64,
+
+    #[arg(long)]
+    last: u64,
+
+    #[arg(long)]
+    execute: bool,
+
+    #[arg(long)]
+    prove: bool,
+
+    #[arg(long, value_enum, default_value = "compressed")]
+    system: ProofSystem,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum ProofSystem {
+    Compressed,
+    Plonk,
+    Groth16,
+}
+
+fn main() -> anyhow::Result<()> {
+    sp1_sdk::utils::setup_logger();
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+
+    if args.execute == args.prove {
+        anyhow::bail!("specify either --execute or --prove");
+    }
+
+    let client = ProverClient::from_env();
+    let (_, block_vk) = client.setup(BLOCK_ELF);
+    let proofs = load_block_proofs(&args.dir, args.first, args.last)?;
+    let expected = expected_aggregate(&proofs, &block_vk)?;
+    let stdin = aggregation_stdin(&proofs, &block_vk)?;
+    println!("blocks: {}..={} ({} proofs)", args.first, args.last, proofs.len());
+
+    if args.execute {
+        let (output, report) = client.execute(AGGREGATION_ELF, &stdin).run()?;
+        anyhow::ensure!(output.as_slice() == expected, "aggregation guest committed something else");
+        println!("Program executed successfully.");
+        println!("Number of cycles: {}", report.total_instruction_count());
+        return Ok(());
+    }
+
+    let (pk, vk) = client.setup(AGGREGATION_ELF);
+    let prove = client.prove(&pk, &stdin);
+    let proof = match args.system {
+        ProofSystem::Compressed => prove.compressed().run(),
+        ProofSystem::Plonk => prove.plonk().run(),
+        ProofSystem::Groth16 => prove.groth16().run(),
+    }?;
+    println!("Successfully generated proof!");
+    anyhow::ensure!(proof.public_values.as_slice() == expected, "aggregation proof committed something else");
+    client.verify(&proof, &vk)?;
+
+    let stem = format!("range-{}-{}", args.first, args.last);
+    let proof_path = args.dir.join(format!("{stem}.proof.bin"));
+    let public_path = args.dir.join(format!("{stem}.public.json"));
+    proof.save(&proof_path)?;
+    std::fs::write(&public_path, public_json(&proof, &vk.bytes32())?)?;
+    println!("Wrote {} and {}", proof_path.display(), public_path.display());
+    Ok(())
+}
+
+fn public_json(proof: &SP1ProofWithPublicValues, vkey: &str) -> anyhow::Result<String> {
+    let bytes = proof.public_values.as_slice();
+    let c = AggregateCommitment::abi_decode(bytes)?;
+    let hex32 = |b: &[u8]| format!("0x{}", hex::encode(b));
+    let v = serde_json::json!({
+        "blockVkey": hex32(c.blockVkey.as_slice()),
+        "firstBlock": c.firstBlock,
+        "lastBlock": c.lastBlock,
+        "parentStateRoot": hex32(c.parentStateRoot.as_slice()),
+        "newStateRoot": hex32(c.newStateRoot.as_slice()),
+        "blocksDigest": hex32(c.blocksDigest.as_slice()),
+        "vkey": vkey,
+        "publicValues": format!("0x{}", hex::encode(bytes)),
+    });
+    Ok(serde_json::to_string_pretty(&v)?)
+}
@@ -1,5 +1,5 @@
-//! An end-to-end example of using the SP1 SDK to generate a proof of a program that can have an
-//! EVM-Compatible proof generated which can be verified on-chain.
+//! Generates an EVM-compatible proof of a block (or of an aggregated range
+//! of blocks) and writes a fixture for verifying it on-chain.
 //!
 //! You can run this script using the following command:
 //! ```shell
@@ -7,29 +7,49 @@
 //! ```
 //! or
 //! ```shell
-//! RUST_LOG=info cargo run --release --bin evm -- --system plonk
+//! RUST_LOG=info cargo run --release --bin evm -- --system plonk --file blocks/block-42.json
+//! ```
+//! Without `--file` the block is a synthetic one (`--orders`, `--crosses`).
+//! or, for an aggregated range of block proofs written by `prove-block`:
+//! ```shell
+//! RUST_LOG=info cargo run --release --bin evm -- --system groth16 --dir blocks --first 40 --last 47
 //! ```
 
 use alloy_sol_types::SolType;
 use clap::{Parser, ValueEnum};
-use fibonacci_lib::PublicValuesStruct;
-use serde::{Deserialize, Serialize};
-use sp1_sdk::{
-    include_elf, HashableKey, ProverClient, SP1ProofWithPublicValues, SP1Stdin, SP1VerifyingKey,
+use fibonacci_lib::{AggregateCommitment, BlockCommitment};
+use fibonacci_script::{
+    aggregation_stdin, expected_aggregate, load_block, load_block_proofs, AGGREGATION_ELF, BLOCK_ELF,
 };
-use std::path::PathBuf;
-
-/// The ELF (executable and linkable format) file for the Succinct RISC-V zkVM.
-pub const FIBONACCI_ELF: &[u8] = include_elf!("fibonacci-program");
+use serde::{Deserialize, Serialize};
+use sp1_sdk::{EnvProver, HashableKey, ProverClient, SP1ProofWithPublicValues, SP1Stdin, SP1VerifyingKey};
+use std::path::{Path, PathBuf};
 
 /// The arguments for the EVM command.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct EVMArgs {
-    #[arg(long, default_value = "20")]
-    n: u32,
     #[arg(long, value_enum, default_value = "groth16")]
     system: ProofSystem,
+    /// Exported block (`BlockExport` JSON) to prove.
+    #[arg(long, conflicts_with = "first")]
+    file: Option<PathBuf>,
+    /// Orders in the synthetic block proven without `--file`.
+    #[arg(long, default_value = "20")]
+    orders: usize,
+    /// Crossing bid/ask pairs among them.
+    #[arg(long, default_value = "5")]
+    crosses: usize,
+    #[arg(long, default_value = "poseidon-bn254")]
+    hash_scheme: String,
+    /// First block of an aggregated range; proves the range instead of one block.
+    #[arg(long, requires = "last")]
+    first: Option<u64>,
+    #[arg(long, requires = "first")]
+    last: Option<u64>,
+    /// Where the block proofs of the range are.
+    #[arg(long, default_value = "blocks")]
+    dir: PathBuf,
 }
 
 /// Enum representing the available proof systems
@@ -42,10 +62,26 @@ enum ProofSystem {
 /// A fixture that can be used to test the verification of SP1 zkVM proofs inside Solidity.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct SP1FibonacciProofFixture {
-    a: u32,
-    b: u32,
-    n: u32,
+struct SP1BlockProofFixture {
+    block_number: u64,
+    parent_state_root: String,
+    new_state_root: String,
+    fills_commitment: String,
+    vkey: String,
+    public_values: String,
+    proof: String,
+}
+
+/// A fixture for an aggregated range of block proofs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SP1AggregateProofFixture {
+    first_block: u64,
+    last_block: u64,
+    parent_state_root: String,
+    new_state_root: String,
+    block_vkey: String,
+    blocks_digest: String,
     vkey: String,
     public_values: String,
     proof: String,
@@ -61,14 +97,23 @@ fn main() {
     // Setup the prover client.
     let client = ProverClient::from_env();
 
+    if let (Some(first), Some(last)) = (args.first, args.last) {
+        prove_range(&client, &args.dir, first, last, args.system);
+        return;
+    }
+
     // Setup the program.
-    let (pk, vk) = client.setup(FIBONACCI_ELF);
+    let (pk, vk) = client.setup(BLOCK_ELF);
 
     // Setup the inputs.
+    let hash_scheme: engine::HashScheme = args.hash_scheme.parse()
+        .expect("--hash-scheme must be blake3, poseidon-bn254 or sha256");
+    let export = load_block(args.file.as_deref(), args.orders, args.crosses, hash_scheme)
+        .expect("failed to load block");
     let mut stdin = SP1Stdin::new();
-    stdin.write(&args.n);
+    stdin.write(&export.input);
 
-    println!("n: {}", args.n);
+    println!("block: {}", export.input.block_number);
     println!("Proof System: {:?}", args.system);
 
     // Generate the proof based on the selected proof system.
@@ -77,10 +122,57 @@ fn main() {
         ProofSystem::Groth16 => client.prove(&pk, &stdin).groth16().run(),
     }
     .expect("failed to generate proof");
+    assert_eq!(proof.public_values.as_slice(), export.public_values, "proof committed a different header");
 
     create_proof_fixture(&proof, &vk, args.system);
 }
 
+/// Aggregate the block proofs of `first..=last` and write an EVM fixture for
+/// the range proof.
+fn prove_range(client: &EnvProver, dir: &Path, first: u64, last: u64, system: ProofSystem) {
+    let (_, block_vk) = client.setup(BLOCK_ELF);
+    let proofs = load_block_proofs(dir, first, last).expect("failed to load block proofs");
+    let expected = expected_aggregate(&proofs, &block_vk).expect("blocks do not chain");
+    let stdin = aggregation_stdin(&proofs, &block_vk).expect("failed to build aggregation input");
+
+    println!("blocks: {first}..={last}");
+    println!("Proof System: {:?}", system);
+
+    let (pk, vk) = client.setup(AGGREGATION_ELF);
+    let proof = match system {
+        ProofSystem::Plonk => client.prove(&pk, &stdin).plonk().run(),
+        ProofSystem::Groth16 => client.prove(&pk, &stdin).groth16().run(),
+    }
+    .expect("failed to generate proof");
+    assert_eq!(proof.public_values.as_slice(), expected, "aggregation proof committed something else");
+
+    let bytes = proof.public_values.as_slice();
+    let c = AggregateCommitment::abi_decode(bytes).unwrap();
+    let hex32 = |b: &[u8]| format!("0x{}", hex::encode(b));
+    let fixture = SP1AggregateProofFixture {
+        first_block: c.firstBlock,
+        last_block: c.lastBlock,
+        parent_state_root: hex32(c.parentStateRoot.as_slice()),
+        new_state_root: hex32(c.newStateRoot.as_slice()),
+        block_vkey: hex32(c.blockVkey.as_slice()),
+        blocks_digest: hex32(c.blocksDigest.as_slice()),
+        vkey: vk.bytes32().to_string(),
+        public_values: format!("0x{}", hex::encode(bytes)),
+        proof: format!("0x{}", hex::encode(proof.bytes())),
+    };
+    println!("Verification Key: {}", fixture.vkey);
+    println!("Public Values: {}", fixture.public_values);
+    println!("Proof Bytes: {}", fixture.proof);
+
+    let fixture_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../contracts/src/fixtures");
+    std::fs::create_dir_all(&fixture_path).expect("failed to create fixture path");
+    std::fs::write(
+        fixture_path.join(format!("{:?}-aggregate-fixture.json", system).to_lowercase()),
+        serde_json::to_string_pretty(&fixture).unwrap(),
+    )
+    .expect("failed to write fixture");
+}
+
 /// Create a fixture for the given proof.
 fn create_proof_fixture(
     proof: &SP1ProofWithPublicValues,
@@ -89,13 +181,15 @@ fn create_proof_fixture(
 ) {
     // Deserialize the public values.
     let bytes = proof.public_values.as_slice();
-    let PublicValuesStruct { n, a, b } = PublicValuesStruct::abi_decode(bytes).unwrap();
+    let c = BlockCommitment::abi_decode(bytes).unwrap();
+    let hex32 = |b: &[u8]| format!("0x{}", hex::encode(b));
 
     // Create the testing fixture so we can test things end-to-end.
-    let fixture = SP1FibonacciProofFixture {
-        a,
-        b,
-        n,
+    let fixture = SP1BlockProofFixture {
+        block_number: c.blockNumber,
+        parent_state_root: hex32(c.parentStateRoot.as_slice()),
+        new_state_root: hex32(c.newStateRoot.as_slice()),
+        fills_commitment: hex32(c.fillsCommitment.as_slice()),
         vkey: vk.bytes32().to_string(),
         public_values: format!("0x{}", hex::encode(bytes)),
         proof: format!("0x{}", hex::encode(proof.bytes())),
@@ -121,7 +215,7 @@ fn create_proof_fixture(
     let fixture_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../contracts/src/fixtures");
     std::fs::create_dir_all(&fixture_path).expect("failed to create fixture path");
     std::fs::write(
-        fixture_path.join(format!("{:?}-fixture.json", system).to_lowercase()),
+        fixture_path.join(format!("{:?}-block-fixture.json", system).to_lowercase()),
         serde_json::to_string_pretty(&fixture).unwrap(),
     )
     .expect("failed to write fixture");
@@ -1,5 +1,5 @@
-//! An end-to-end example of using the SP1 SDK to generate a proof of a program that can be executed
-//! or have a core proof generated.
+//! Executes or proves one block in the block guest and checks the header it
+//! commits against the expected public values.
 //!
 //! You can run this script using the following command:
 //! ```shell
@@ -7,16 +7,17 @@
 //! ```
 //! or
 //! ```shell
-//! RUST_LOG=info cargo run --release -- --prove
+//! RUST_LOG=info cargo run --release -- --prove --file blocks/block-42.json
 //! ```
+//! Without `--file` the block is a synthetic one (`--orders`, `--crosses`).
+//! Blocks stored by the sequencer are better proven with `prove-block`.
 
 use alloy_sol_types::SolType;
 use clap::Parser;
-use fibonacci_lib::PublicValuesStruct;
-use sp1_sdk::{include_elf, ProverClient, SP1Stdin};
-
-/// The ELF (executable and linkable format) file for the Succinct RISC-V zkVM.
-pub const FIBONACCI_ELF: &[u8] = include_elf!("fibonacci-program");
+use fibonacci_lib::BlockCommitment;
+use fibonacci_script::{load_block, BLOCK_ELF};
+use sp1_sdk::{ProverClient, SP1Stdin};
+use std::path::PathBuf;
 
 /// The arguments for the command.
 #[derive(Parser, Debug)]
@@ -28,11 +29,23 @@ struct Args {
     #[arg(long)]
     prove: bool,
 
+    /// Exported block (`BlockExport` JSON).
+    #[arg(long)]
+    file: Option<PathBuf>,
+
+    /// Orders in the synthetic block.
     #[arg(long, default_value = "20")]
-    n: u32,
+    orders: usize,
+
+    /// Crossing bid/ask pairs among them.
+    #[arg(long, default_value = "5")]
+    crosses: usize,
+
+    #[arg(long, default_value = "poseidon-bn254")]
+    hash_scheme: String,
 }
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     // Setup the logger.
     sp1_sdk::utils::setup_logger();
     dotenv::dotenv().ok();
@@ -44,49 +57,45 @@ fn main() {
         eprintln!("Error: You must specify either --execute or --prove");
         std::process::exit(1);
     }
+    let hash_scheme: engine::HashScheme = args.hash_scheme.parse()
+        .map_err(|_| anyhow::anyhow!("--hash-scheme must be blake3, poseidon-bn254 or sha256"))?;
 
     // Setup the prover client.
     let client = ProverClient::from_env();
 
     // Setup the inputs.
+    let export = load_block(args.file.as_deref(), args.orders, args.crosses, hash_scheme)?;
     let mut stdin = SP1Stdin::new();
-    stdin.write(&args.n);
+    stdin.write(&export.input);
 
-    println!("n: {}", args.n);
+    println!("block: {}", export.input.block_number);
 
     if args.execute {
         // Execute the program
-        let (output, report) = client.execute(FIBONACCI_ELF, &stdin).run().unwrap();
+        let (output, report) = client.execute(BLOCK_ELF, &stdin).run()?;
         println!("Program executed successfully.");
 
         // Read the output.
-        let decoded = PublicValuesStruct::abi_decode(output.as_slice()).unwrap();
-        let PublicValuesStruct { n, a, b } = decoded;
-        println!("n: {}", n);
-        println!("a: {}", a);
-        println!("b: {}", b);
-
-        let (expected_a, expected_b) = fibonacci_lib::fibonacci(n);
-        assert_eq!(a, expected_a);
-        assert_eq!(b, expected_b);
+        let header = BlockCommitment::abi_decode(output.as_slice())?;
+        println!("new state root: {}", header.newStateRoot);
+        println!("fills commitment: {}", header.fillsCommitment);
+        anyhow::ensure!(output.as_slice() == export.public_values, "guest committed a different header");
         println!("Values are correct!");
 
         // Record the number of cycles executed.
         println!("Number of cycles: {}", report.total_instruction_count());
     } else {
         // Setup the program for proving.
-        let (pk, vk) = client.setup(FIBONACCI_ELF);
+        let (pk, vk) = client.setup(BLOCK_ELF);
 
         // Generate the proof
-        let proof = client
-            .prove(&pk, &stdin)
-            .run()
-            .expect("failed to generate proof");
-
+        let proof = client.prove(&pk, &stdin).run()?;
         println!("Successfully generated proof!");
 
         // Verify the proof.
-        client.verify(&proof, &vk).expect("failed to verify proof");
+        client.verify(&proof, &vk)?;
+        anyhow::ensure!(proof.public_values.as_slice() == export.public_values, "proof committed a different header");
         println!("Successfully verified proof!");
     }
+    Ok(())
 }
@@ -0,0 +1,100 @@
+//! Profiles the block guest: executes it over synthetic blocks of varying
+//! order and fill counts, reports cycles per phase and fits a `CostModel`.
+//!
+//! The model (with the samples behind it) is written to `--out`, from where
+//! the sequencer's `BlockBuilder::proving_budget` can use it.
+//!
+//! ```shell
+//! RUST_LOG=info cargo run --release --bin profile-block -- --orders 100,200,400,800 --fill-ratios 0,0.25,0.5
+//! ```
+
+use clap::Parser;
+use fibonacci_lib::block::phase;
+use fibonacci_lib::{match_block, pre_batch_state, CostModel, CostSample};
+use fibonacci_script::{synthetic_block, BLOCK_ELF};
+use serde::Serialize;
+use sp1_sdk::{ProverClient, SP1Stdin};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Orders per synthetic block.
+    #[arg(long, value_delimiter = ',', default_value = "50,100,200,400")]
+    orders: Vec<usize>,
+
+    /// Fraction of orders that trade, each in a cross of two.
+    #[arg(long, value_delimiter = ',', default_value = "0,0.25,0.5,1")]
+    fill_ratios: Vec<f64>,
+
+    #[arg(long, default_value = "4")]
+    markets: u32,
+
+    #[arg(long, default_value = "poseidon-bn254")]
+    hash_scheme: String,
+
+    #[arg(long, default_value = "cost-model.json")]
+    out: PathBuf,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    hash_scheme: String,
+    model: CostModel,
+    samples: Vec<ProfiledBlock>,
+}
+
+#[derive(Serialize)]
+struct ProfiledBlock {
+    #[serde(flatten)]
+    sample: CostSample,
+    phases: BTreeMap<String, u64>,
+}
+
+fn main() -> anyhow::Result<()> {
+    sp1_sdk::utils::setup_logger();
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+    let hash_scheme: engine::HashScheme = args.hash_scheme.parse()
+        .map_err(|_| anyhow::anyhow!("--hash-scheme must be blake3, poseidon-bn254 or sha256"))?;
+
+    let client = ProverClient::from_env();
+    let mut samples = Vec::new();
+    println!("{:>7} {:>6} {:>12} {}", "orders", "fills", "cycles", phase::ALL.join(" / "));
+    for &orders in &args.orders {
+        for &ratio in &args.fill_ratios {
+            let crosses = ((orders as f64 * ratio.clamp(0.0, 1.0)) / 2.0) as usize;
+            let input = synthetic_block(orders, crosses, args.markets, hash_scheme);
+            let matched = match_block(&input, pre_batch_state(&input).orders().cloned().collect());
+            let fills = (matched.fills.len() + matched.legs.len()) as u64;
+
+            let mut stdin = SP1Stdin::new();
+            stdin.write(&input);
+            let (_, report) = client.execute(BLOCK_ELF, &stdin).run()?;
+            let cycles = report.total_instruction_count();
+            let phases: BTreeMap<String, u64> = phase::ALL.iter()
+                .map(|p| (p.to_string(), report.cycle_tracker.get(*p).copied().unwrap_or_default()))
+                .collect();
+            println!(
+                "{orders:>7} {fills:>6} {cycles:>12} {}",
+                phase::ALL.iter().map(|p| phases[*p].to_string()).collect::<Vec<_>>().join(" / "),
+            );
+            samples.push(ProfiledBlock { sample: CostSample { orders: orders as u64, fills, cycles }, phases });
+        }
+    }
+
+    let fitted: Vec<CostSample> = samples.iter().map(|s| s.sample).collect();
+    let model = CostModel::fit(&fitted)
+        .ok_or_else(|| anyhow::anyhow!("samples must vary orders and fills independently to fit a model"))?;
+    println!(
+        "cycles ≈ {:.0} + {:.1} * orders + {:.1} * fills",
+        model.base, model.per_order, model.per_fill,
+    );
+
+    let profile = Profile { hash_scheme: hash_scheme.to_string(), model, samples };
+    std::fs::write(&args.out, serde_json::to_string_pretty(&profile)?)?;
+    println!("Wrote {}", args.out.display());
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! Proves a block the sequencer has already built, by re-executing it in the block guest.
+//!
+//! The block comes from the sequencer's `block_inputs` table or from an exported
+//! `BlockExport` JSON file. Artifacts go next to the block file (or under `--out`):
+//! `block-<n>.proof.bin` and `block-<n>.public.json`.
+//!
+//! ```shell
+//! RUST_LOG=info cargo run --release --bin prove-block -- --block 42 --execute
+//! RUST_LOG=info cargo run --release --bin prove-block -- --file blocks/block-42.json --prove --system groth16
+//! RUST_LOG=info cargo run --release --bin prove-block -- --file blocks/block-42.json --verify
+//! ```
+
+use alloy_sol_types::SolType;
+use clap::{Parser, ValueEnum};
+use fibonacci_lib::{BlockCommitment, BlockExport};
+use fibonacci_script::{block_proof_path, BLOCK_ELF};
+use sp1_sdk::{HashableKey, ProverClient, SP1ProofWithPublicValues, SP1Stdin};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Block number to load from the sequencer database.
+    #[arg(long, conflicts_with = "file")]
+    block: Option<u64>,
+
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: Option<String>,
+
+    /// Exported block (`BlockExport` JSON).
+    #[arg(long)]
+    file: Option<PathBuf>,
+
+    #[arg(long)]
+    execute: bool,
+
+    #[arg(long)]
+    prove: bool,
+
+    /// Check a previously written proof against the block.
+    #[arg(long)]
+    verify: bool,
+
+    #[arg(long, value_enum, default_value = "compressed")]
+    system: ProofSystem,
+
+    /// Where artifacts go; defaults to the block file's directory, or `blocks/`.
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum ProofSystem {
+    Core,
+    Compressed,
+    Plonk,
+    Groth16,
+}
+
+fn main() -> anyhow::Result<()> {
+    sp1_sdk::utils::setup_logger();
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+
+    if [args.execute, args.prove, args.verify].iter().filter(|m| **m).count() != 1 {
+        anyhow::bail!("specify exactly one of --execute, --prove or --verify");
+    }
+
+    let (export, out_dir) = load(&args)?;
+    let expected = BlockCommitment::abi_decode(&export.public_values)?;
+    let n = expected.blockNumber;
+    std::fs::create_dir_all(&out_dir)?;
+    let proof_path = block_proof_path(&out_dir, n);
+    println!("block: {n} (batch {})", expected.batchId);
+
+    let client = ProverClient::from_env();
+    let mut stdin = SP1Stdin::new();
+    stdin.write(&export.input);
+
+    if args.execute {
+        let (output, report) = client.execute(BLOCK_ELF, &stdin).run()?;
+        println!("Program executed successfully.");
+        check_public_values(output.as_slice(), &export.public_values)?;
+        println!("Public values match the sequencer's header.");
+        println!("Number of cycles: {}", report.total_instruction_count());
+    } else if args.prove {
+        let (pk, vk) = client.setup(BLOCK_ELF);
+        let prove = client.prove(&pk, &stdin);
+        let proof = match args.system {
+            ProofSystem::Core => prove.core().run(),
+            ProofSystem::Compressed => prove.compressed().run(),
+            ProofSystem::Plonk => prove.plonk().run(),
+            ProofSystem::Groth16 => prove.groth16().run(),
+        }?;
+        println!("Successfully generated proof!");
+        check_public_values(proof.public_values.as_slice(), &export.public_values)?;
+        client.verify(&proof, &vk)?;
+
+        proof.save(&proof_path)?;
+        let public_path = out_dir.join(format!("block-{n}.public.json"));
+        std::fs::write(&public_path, public_json(&proof, &vk.bytes32())?)?;
+        println!("Wrote {} and {}", proof_path.display(), public_path.display());
+    } else {
+        let (_, vk) = client.setup(BLOCK_ELF);
+        let proof = SP1ProofWithPublicValues::load(&proof_path)?;
+        check_public_values(proof.public_values.as_slice(), &export.public_values)?;
+        client.verify(&proof, &vk)?;
+        println!("Successfully verified {}", proof_path.display());
+    }
+    Ok(())
+}
+
+/// The block and the directory its artifacts belong in. Blocks read from the
+/// database are also written out there, so the proof has its input alongside.
+fn load(args: &Args) -> anyhow::Result<(BlockExport, PathBuf)> {
+    if let Some(path) = &args.file {
+        let export = serde_json::from_slice(&std::fs::read(path)?)?;
+        let dir = args.out.clone().unwrap_or_else(|| {
+            path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf()
+        });
+        return Ok((export, dir));
+    }
+    let Some(n) = args.block else {
+        anyhow::bail!("specify --block <n> or --file <path>");
+    };
+    let Some(url) = &args.database_url else {
+        anyhow::bail!("--block needs --database-url (or DATABASE_URL)");
+    };
+    let export = load_from_db(url, n)?;
+    let dir = args.out.clone().unwrap_or_else(|| PathBuf::from("blocks"));
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(format!("block-{n}.json")), serde_json::to_string_pretty(&export)?)?;
+    Ok((export, dir))
+}
+
+fn load_from_db(url: &str, block_number: u64) -> anyhow::Result<BlockExport> {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    rt.block_on(async {
+        let pool = sqlx::postgres::PgPoolOptions::new().max_connections(1).connect(url).await?;
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT export::text FROM block_inputs WHERE block_number = $1")
+                .bind(block_number as i64)
+                .fetch_optional(&pool)
+                .await?;
+        let Some((json,)) = row else {
+            anyhow::bail!("block {block_number} has no stored input");
+        };
+        Ok(serde_json::from_str(&json)?)
+    })
+}
+
+fn check_public_values(got: &[u8], expected: &[u8]) -> anyhow::Result<()> {
+    if got != expected {
+        let got = BlockCommitment::abi_decode(got)?;
+        let expected = BlockCommitment::abi_decode(expected)?;
+        anyhow::bail!("guest committed {got:?}, sequencer header is {expected:?}");
+    }
+    Ok(())
+}
+
+/// The committed header, decoded, plus what a verifier needs alongside it.
+fn public_json(proof: &SP1ProofWithPublicValues, vkey: &str) -> anyhow::Result<String> {
+    let bytes = proof.public_values.as_slice();
+    let c = BlockCommitment::abi_decode(bytes)?;
+    let hex32 = |b: &[u8]| format!("0x{}", hex::encode(b));
+    let v = serde_json::json!({
+        "blockNumber": c.blockNumber,
+        "batchId": c.batchId,
+        "hashScheme": c.hashScheme,
+        "commitScheme": c.commitScheme,
+        "parentStateRoot": hex32(c.parentStateRoot.as_slice()),
+        "newStateRoot": hex32(c.newStateRoot.as_slice()),
+        "marketsRoot": hex32(c.marketsRoot.as_slice()),
+        "ordersCommitment": hex32(c.ordersCommitment.as_slice()),
+        "fillsCommitment": hex32(c.fillsCommitment.as_slice()),
+        "timestampMs": c.timestampMs,
+        "vkey": vkey,
+        "publicValues": format!("0x{}", hex::encode(bytes)),
+    });
+    Ok(serde_json::to_string_pretty(&v)?)
+}
@@ -0,0 +1,139 @@
+//! Shared by the script binaries: the guest ELFs and the block-proof files
+//! `prove-block` writes, as the aggregation guest consumes them.
+
+use alloy_sol_types::SolType;
+use engine::hash::{HashBackend, HashScheme};
+use engine::state::OrderState;
+use engine::types::*;
+use fibonacci_lib::{aggregate, execute_block, vkey_bytes, AggregateCommitment, BlockCommitment, BlockExport, BlockInput};
+use sp1_sdk::{include_elf, HashableKey, SP1Proof, SP1ProofWithPublicValues, SP1Stdin, SP1VerifyingKey};
+use std::path::{Path, PathBuf};
+
+/// The block guest (`zkvm/program`).
+pub const BLOCK_ELF: &[u8] = include_elf!("fibonacci-program");
+
+/// The aggregation guest (`zkvm/aggregation`).
+pub const AGGREGATION_ELF: &[u8] = include_elf!("aggregation-program");
+
+pub fn block_proof_path(dir: &Path, block_number: u64) -> PathBuf {
+    dir.join(format!("block-{block_number}.proof.bin"))
+}
+
+/// Proofs of blocks `first..=last` from `dir`, in order.
+pub fn load_block_proofs(dir: &Path, first: u64, last: u64) -> anyhow::Result<Vec<SP1ProofWithPublicValues>> {
+    anyhow::ensure!(first <= last, "empty range {first}..={last}");
+    (first..=last)
+        .map(|n| {
+            let path = block_proof_path(dir, n);
+            SP1ProofWithPublicValues::load(&path).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))
+        })
+        .collect()
+}
+
+/// Input for the aggregation guest. Block proofs must be compressed
+/// (`prove-block --system compressed`) to be verified recursively.
+pub fn aggregation_stdin(proofs: &[SP1ProofWithPublicValues], block_vk: &SP1VerifyingKey) -> anyhow::Result<SP1Stdin> {
+    let mut stdin = SP1Stdin::new();
+    stdin.write(&block_vk.hash_u32());
+    stdin.write(&proofs.iter().map(|p| p.public_values.to_vec()).collect::<Vec<_>>());
+    for p in proofs {
+        let SP1Proof::Compressed(proof) = &p.proof else {
+            anyhow::bail!("block proofs must be compressed to aggregate");
+        };
+        stdin.write_proof(*proof.clone(), block_vk.vk.clone());
+    }
+    Ok(stdin)
+}
+
+/// What the aggregation guest should commit for `proofs`, checked on the
+/// host first so a broken chain fails before proving starts.
+pub fn expected_aggregate(proofs: &[SP1ProofWithPublicValues], block_vk: &SP1VerifyingKey) -> anyhow::Result<Vec<u8>> {
+    let public_values: Vec<Vec<u8>> = proofs.iter().map(|p| p.public_values.to_vec()).collect();
+    let range = aggregate(vkey_bytes(block_vk.hash_u32()), &public_values)?;
+    Ok(AggregateCommitment::abi_encode(&range))
+}
+
+/// The block in `file` (a `BlockExport`), or else a one-market
+/// `synthetic_block` with the public values the host's `execute_block`
+/// gives it.
+pub fn load_block(
+    file: Option<&Path>, orders: usize, crosses: usize, hash_scheme: HashScheme,
+) -> anyhow::Result<BlockExport> {
+    if let Some(path) = file {
+        return Ok(serde_json::from_slice(&std::fs::read(path)?)?);
+    }
+    let input = synthetic_block(orders, crosses, 1, hash_scheme);
+    let public_values = BlockCommitment::abi_encode(&execute_block(&input));
+    Ok(BlockExport { input, public_values })
+}
+
+/// A first block (on the empty state) of `orders` new orders over
+/// `markets` markets, `crosses` of whose bid/ask pairs trade in full (one
+/// fill each) while the rest rest out of range. Every order has its own
+/// owner, so nothing self-trades.
+pub fn synthetic_block(orders: usize, crosses: usize, markets: u32, hash_scheme: HashScheme) -> BlockInput {
+    let markets: Vec<MarketParams> = (1..=markets.max(1))
+        .map(|p| MarketParams {
+            pair_id: PairId(p),
+            price_tick: 1,
+            size_step: 1,
+            notional_min: 0,
+            notional_max: u128::MAX,
+            maker_bps: 0,
+            taker_bps: 0,
+            status: MarketStatus::Active,
+            clearing: ClearingMode::default(),
+            allocation: AllocationPolicy::default(),
+            stp: StpMode::default(),
+        })
+        .collect();
+    let crosses = crosses.min(orders / 2);
+    let mut book = Vec::with_capacity(orders);
+    let mut owners = OwnerMap::new();
+    for i in 0..orders as u64 {
+        let bid = i % 2 == 0;
+        let crossing = i < 2 * crosses as u64;
+        let price_tick = match (crossing, bid) {
+            (true, true) => 101,
+            (true, false) => 100,
+            (false, true) => 90,
+            (false, false) => 110,
+        };
+        let mut order_hash = [0u8; 32];
+        order_hash[..8].copy_from_slice(&i.to_le_bytes());
+        book.push(Order {
+            order_id: OrderId(i + 1),
+            order_hash,
+            // a cross's bid and ask share a market
+            pair_id: markets[(i / 2) as usize % markets.len()].pair_id,
+            side: if bid { Side::Bid } else { Side::Ask },
+            price_tick,
+            amount: 10,
+            remaining: 10,
+            time_bucket: 0,
+            nonce: i,
+            ingest_seq: i + 1,
+            tif: TimeInForce::Gtc,
+            stop: None,
+            display_qty: None,
+        });
+        let mut pk = [0u8; 32];
+        pk[24..].copy_from_slice(&(i + 1).to_be_bytes());
+        owners.insert(i + 1, pk);
+    }
+    BlockInput {
+        block_number: 1,
+        batch_id: 1,
+        hash_scheme,
+        commit_scheme: Default::default(),
+        parent_state_root: OrderState::genesis_root(&HashBackend::new(hash_scheme)),
+        timestamp_ms: 0,
+        markets,
+        orders: Vec::new(),
+        updates: Vec::new(),
+        new_orders: book,
+        owners,
+        implied: Vec::new(),
+        fill_salts: None,
+    }
+}